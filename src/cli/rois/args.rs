@@ -137,7 +137,7 @@ impl ROIArgs {
                 workload = Some(w);
                 maxsize = Some(m)
             });
-            s.spawn(|_| stranding = shared::parse::strandpred(pbars, args));
+            s.spawn(|_| stranding = shared::parse::strandpred(pbars, args, &core.stranding));
             s.spawn(|_| retain = parse::retain(pbarr, args));
         });
 
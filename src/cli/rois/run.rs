@@ -11,15 +11,24 @@ use crate::core::hooks::engine::REATHooksEngine;
 use crate::core::hooks::filters;
 use crate::core::hooks::stats::{EditingStatType, ROIEditingIndex};
 use crate::core::mismatches::roi::{ROIMismatchesBuilder, ROIMismatchesVec};
+use crate::core::rpileup::downsample::DepthDownsampler;
 use crate::core::rpileup::hts::HTSPileupEngine;
-use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, ROINucCounter, StrandedNucCounter};
+use crate::core::rpileup::ncounter::cnt::{
+    BaseNucCounter, PerSampleNucCounter, ROINucCounter, StrandedNucCounter, UMINucCounter,
+};
 use crate::core::runner::REATRunner;
 
 pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> ProgressBar) {
-    let args = ROIArgs::new(&core, args, &factory);
+    let mut args = ROIArgs::new(&core, args, &factory);
+    // Streamed reads are consumed once -> bins are processed sequentially in the order of reads
+    let sequential = core.stream.is_some();
+    if let Some(stream) = &core.stream {
+        stream.lock().unwrap().sort(&mut args.workload);
+    }
 
     let mut hooks: REATHooksEngine<ROIMismatchesVec> = REATHooksEngine::new();
     let mut statsto = HashMap::new();
+    let samples = ROIMismatchesVec::columns(core.persample.as_ref().map_or(&[][..], |(_, names)| names.as_slice()));
     let builder = match args.ei {
         None => {
             // Always with prefilter since there are no site-level stats right now
@@ -36,31 +45,80 @@ pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> Progress
         }
     };
 
+    let builder = builder
+        .with_samples(samples)
+        .with_known_sites(core.knownsnps.clone())
+        .with_editing_db(core.editingdb.clone())
+        .with_indels(core.indels)
+        .with_fractional_coverage(core.fracmulti);
+
+    // Reads are downsampled before counting, filtered ones don't take up the depth
+    let downsampler = core.maxdepth.map(|x| DepthDownsampler::new(x, core.readfilter.clone()));
+
     // Initialize basic counter
-    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3);
+    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3)
+        .with_mates_overlap(core.mates_overlap)
+        .with_cigar_mask(core.cigarmask)
+        .with_long_reads(core.longreads)
+        .with_base_weights(core.weights)
+        .with_multimappers_fraction(core.fracmulti)
+        .with_indels(core.indels);
+    // Only nucleotides are reported per sample
+    let sample = UMINucCounter::new(ROINucCounter::new(counter.nucleotides_only()), core.umi.clone());
     let counter = ROINucCounter::new(counter);
+    let counter = UMINucCounter::new(counter, core.umi);
+    let split = core.persample.map(|x| x.0);
 
     let mut strander = args.stranding;
-    match core.stranding {
+    let results = match core.stranding {
         Stranding::Unstranded => {
             // Compose strander + pileuper
-            let pileuper = HTSPileupEngine::new(core.bamfiles, counter);
+            let counter = PerSampleNucCounter::new(counter, sample, split);
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
             // Launch the processing
             let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, statsto).unwrap()
+            shared::run(args.workload, runner, factory(), statsto, sequential)
         }
         Stranding::Stranded(x) => {
             // Remove all stranding algorithm -> they are not required
             strander.clear();
             // Compose strander + pileuper
             let deductor = crate::core::stranding::deduce::DeduceStrandByDesign::new(x);
-            let pileuper = HTSPileupEngine::new(core.bamfiles, StrandedNucCounter::new(counter, deductor));
+            let counter = PerSampleNucCounter::new(
+                StrandedNucCounter::new(counter, deductor),
+                StrandedNucCounter::new(sample, deductor),
+                split,
+            );
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
+
+            // Launch the processing
+            let runner = REATRunner::new(builder, strander, pileuper, hooks);
+            shared::run(args.workload, runner, factory(), statsto, sequential)
+        }
+        Stranding::AlignerTags => {
+            // Heuristics would duplicate loci/ROIs already counted for the forward/reverse strands
+            strander.clear();
+            // Compose strander + pileuper
+            let deductor = crate::core::stranding::deduce::DeduceStrandByTags::default();
+            let counter = PerSampleNucCounter::new(
+                StrandedNucCounter::new(counter, deductor),
+                StrandedNucCounter::new(sample, deductor),
+                split,
+            );
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
 
             // Launch the processing
             let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, statsto).unwrap()
+            shared::run(args.workload, runner, factory(), statsto, sequential)
         }
     };
+    shared::save(&results, &mut core.saveto);
 
     // debug_assert!(stats.is_empty());
 
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use clap::Arg;
 use clap::ArgMatches;
@@ -9,8 +10,11 @@ use rust_htslib::bam::Record;
 use crate::cli::shared::stranding::Stranding;
 use crate::core::io::bed::BedRecord;
 use crate::core::io::fasta::BasicFastaReader;
+use crate::core::mismatches::prefilters::{KnownEditingSites, KnownSites};
 use crate::core::refpred::RefEngine;
+use crate::core::rpileup::ncounter::cnt::{BaseWeights, CigarMask, MatesOverlap, SampleSplit};
 use crate::core::rpileup::ncounter::filters;
+use crate::core::rpileup::stream::HTSStream;
 
 use super::parse;
 use super::validate;
@@ -26,6 +30,11 @@ pub mod core {
     pub const SAVETO: &str = "saveto";
     pub const NAME: &str = "name";
     pub const EXCLUDE_LIST: &str = "exclude";
+    pub const KNOWN_SNPS: &str = "known-snps";
+    pub const KNOWN_SITES: &str = "known-sites";
+    pub const PER_SAMPLE: &str = "per-sample";
+    pub const INDELS: &str = "indels";
+    pub const LONG_READS: &str = "long-reads";
 
     pub const SECTION_NAME: &str = "Core";
 
@@ -37,11 +46,14 @@ pub mod core {
                 .required(true)
                 .takes_value(true)
                 .multiple_values(true)
-                .validator(validate::path)
+                .validator(validate::input)
                 .long_help(
                     "Path to the input BAM file(s). \
                     May contain a space-separated list of files, in which case they are treated as \
-                    technical replicates and pulled together",
+                    technical replicates and pulled together. \
+                    Use \"-\" to read a coordinate-sorted BAM/CRAM stream from stdin without an index \
+                    (e.g. samtools sort -o - in.bam | reat site -i - ...). \
+                    Bins are processed sequentially in this mode.",
                 ),
             Arg::new(REFERENCE)
                 .short('r')
@@ -68,14 +80,18 @@ pub mod core {
                 .required(true)
                 .takes_value(true)
                 .validator(validate::stranding)
-                .possible_values(&["u", "s", "f", "s/f", "f/s"])
+                .possible_values(&["u", "s", "f", "s/f", "f/s", "xs", "auto"])
                 .long_help(
                     "Strand-specificity of the experiment, \
                     i.e. matching between the read strand and the gene strand. Use \"u\" for unstranded experiments; \
                     other available options based on the RSeQC nomenclature(see infer_experiment.py docs): \
                     same:\"s\" (++,--), flip:\"f\" (+-,-+), \
                     same read1/flip read2:\"s/f\" (1++,1--/2+-,2-+), \
-                    flip read1/same read2:\"f/s\" (1+-,1-+/2++,2--)",
+                    flip read1/same read2:\"f/s\" (1+-,1-+/2++,2--). \
+                    Use \"xs\" for unstranded libraries to take the strand of spliced reads from the XS/ts aligner \
+                    tags (HISAT2, STAR, minimap2); all other reads are counted with the unknown(.) strand. \
+                    Use \"auto\" to infer the design from reads overlapping the annotated genes \
+                    (requires --annotation) as in the RSeQC infer_experiment.py.",
                 ),
             Arg::new(NAME).short('n').long(NAME).takes_value(true).default_value("NA").long_help("Name of the run."),
             Arg::new(SAVETO)
@@ -97,6 +113,37 @@ pub mod core {
                 .takes_value(true)
                 .validator(validate::path)
                 .long_help("Path to a BED file with regions to exclude from the analysis"),
+            Arg::new(KNOWN_SNPS).long(KNOWN_SNPS).takes_value(true).validator(validate::path).long_help(
+                "Path to a VCF/BCF or BED(.bed, .bed.gz) file with known polymorphic positions (e.g. dbSNP) \
+                    to drop from the site output and ROI summaries regardless of the sample genotype. \
+                    All positions covered by the BED intervals or the reference alleles of variants up to 50bp \
+                    are masked. Longer variants (e.g. structural ones) are ignored.",
+            ),
+            Arg::new(KNOWN_SITES).long(KNOWN_SITES).takes_value(true).validator(validate::path).long_help(
+                "Path to a database of known editing sites (e.g. REDIportal) to annotate novel vs known sites. \
+                    Supported formats: BED(.bed, .bed.gz) with optional IDs in the name column, \
+                    VCF/BCF(.vcf, .vcf.gz, .bcf) with IDs in the ID column, \
+                    or TSV with 1-based positions in the first two columns(contig, position). \
+                    Sites get \"in_db\" and \"db_id\" columns, ROIs get the number of known sites inside(\"known\").",
+            ),
+            Arg::new(PER_SAMPLE).long(PER_SAMPLE).takes_value(true).possible_values(&["file", "rg"]).long_help(
+                "Report counts for each sample in separate columns in addition to the pooled ones. \
+                    Use \"file\" to treat each input file as a separate sample or \"rg\" to split reads \
+                    by read groups (RG tag). Columns are named after input files(without extension) or read group IDs",
+            ),
+            Arg::new(INDELS).long(INDELS).takes_value(false).long_help(
+                "Count insertions and deletions in addition to nucleotides. \
+                    Sites get extra columns with deletions, insertions and inserted sequences \
+                    (insertions are assigned to the preceding reference position). \
+                    ROIs get extra columns with the total number of deleted bases and insertions.",
+            ),
+            Arg::new(LONG_READS).long(LONG_READS).takes_value(false).long_help(
+                "Tune the counting for long reads (ONT/PacBio): the CIGAR of each read is indexed once and \
+                    reused for all genomic bins the read overlaps, jumping straight to the first overlapping \
+                    operation instead of walking all preceding ones. \
+                    Recommended for long spliced reads spanning many bins. \
+                    Combine with --max-error-rate to skip reads with a high error rate.",
+            ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -110,8 +157,21 @@ pub mod reads_filtering {
     pub const INCLUDE_FLAGS: &str = "in-flags";
     pub const EXCLUDE_FLAGS: &str = "ex-flags";
     pub const PHREAD: &str = "phread";
+    pub const WEIGHTS: &str = "weights";
+    pub const NH_FRACTION: &str = "nh-fraction";
     pub const TRIM5: &str = "trim5";
     pub const TRIM3: &str = "trim3";
+    pub const MATES_OVERLAP: &str = "mates-overlap";
+    pub const MASK_SPLICE: &str = "mask-splice";
+    pub const MASK_INDELS: &str = "mask-indels";
+    pub const MASK_SOFTCLIP: &str = "mask-softclip";
+    pub const UMI: &str = "umi";
+    pub const MAX_EDIT_DISTANCE: &str = "max-nm";
+    pub const MAX_MISMATCHES_PER_KB: &str = "max-mm-per-kb";
+    pub const MAX_NON_AG_MISMATCHES: &str = "max-non-ag-mm";
+    pub const MAX_ERROR_RATE: &str = "max-error-rate";
+    pub const READ_FILTER: &str = "read-filter";
+    pub const MAX_DEPTH: &str = "max-depth";
 
     pub const SECTION_NAME: &str = "Reads hooks";
 
@@ -163,6 +223,20 @@ pub mod reads_filtering {
                     For a reference, phread is defined as -10 log₁₀[error probability], \
                     so phread = 20 means 1 error in 100 base calls",
                 ),
+            Arg::new(WEIGHTS).long(WEIGHTS).takes_value(true).possible_values(&["phred", "phred+mapq"]).long_help(
+                "Additionally weight each counted base by the probability that it is correct: \
+                    \"phred\" uses the base-call probability 1 - 10^(-phread/10); \
+                    \"phred+mapq\" also multiplies it by the same probability derived from the read mapq. \
+                    Sites get extra columns with weighted counts(wA, wC, wG, wT), \
+                    ROI mismatches are summarized from weighted counts. \
+                    Combine with a lower --phread to keep low-quality bases without letting them dominate.",
+            ),
+            Arg::new(NH_FRACTION).long(NH_FRACTION).takes_value(false).long_help(
+                "Count multi-mapping reads with the NH:i:n tag as 1/n of a read. \
+                Weighted counts are reported for sites(wA, wC, wG, wT) and used for ROI mismatches and \
+                the editing index; ROIs get an extra column with the fractional coverage. \
+                Use together with --mapq 0 to keep reads mapped to more than a few loci.",
+            ),
             Arg::new(TRIM5)
                 .short('5')
                 .long(TRIM5)
@@ -185,6 +259,107 @@ pub mod reads_filtering {
                     Can be used to hard skip low-quality bases at the end of filters if no trimming was done \
                     before / during the alignment.",
                 ),
+            Arg::new(MATES_OVERLAP)
+                .long(MATES_OVERLAP)
+                .takes_value(true)
+                .possible_values(&["count", "best-quality", "drop-discordant"])
+                .default_value("count")
+                .long_help(
+                    "How to count bases covered by both mates of the same fragment. \
+                    \"count\" counts each mate independently, i.e. overlapping bases are counted twice; \
+                    \"best-quality\" counts the overlapping base once, keeping the mate base with the higher quality; \
+                    \"drop-discordant\" counts concordant bases once and skips discordant ones. \
+                    Recommended for paired-end libraries with short inserts.",
+                ),
+            Arg::new(MASK_SPLICE)
+                .long(MASK_SPLICE)
+                .takes_value(true)
+                .validator(validate::numeric(0u16, 65535u16))
+                .default_value("0")
+                .long_help(
+                    "Skip bases within X aligned bases of a splice junction (N CIGAR operation). \
+                    Mismatches next to splice junctions are often alignment artifacts. Use zero(0) to disable.",
+                ),
+            Arg::new(MASK_INDELS)
+                .long(MASK_INDELS)
+                .takes_value(true)
+                .validator(validate::numeric(0u16, 65535u16))
+                .default_value("0")
+                .long_help(
+                    "Skip bases within X aligned bases of an insertion or deletion (I/D CIGAR operations). \
+                    Use zero(0) to disable.",
+                ),
+            Arg::new(MASK_SOFTCLIP)
+                .long(MASK_SOFTCLIP)
+                .takes_value(true)
+                .validator(validate::numeric(0u16, 65535u16))
+                .default_value("0")
+                .long_help(
+                    "Skip bases within X aligned bases of a soft clipped read end (S CIGAR operation). \
+                    Use zero(0) to disable.",
+                ),
+            Arg::new(UMI).long(UMI).takes_value(true).multiple_values(true).validator(validate::auxtag).long_help(
+                "Collapse PCR duplicates using UMIs stored in the given BAM tag(s), e.g. \"RX UB\". \
+                    Tags are checked in the given order. Reads with the same UMI, 5` alignment position and strand \
+                    are treated as a single molecule and each base is counted once using the majority consensus. \
+                    Mates of the same fragment belong to the same molecule, hence their overlap is counted once \
+                    regardless of --mates-overlap. Reads without UMI tags are counted as is.",
+            ),
+            Arg::new(MAX_EDIT_DISTANCE)
+                .long(MAX_EDIT_DISTANCE)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, u32::MAX))
+                .long_help(
+                    "Skip reads with the edit distance to the reference (NM tag) > threshold. \
+                    Reads without the NM tag are not filtered.",
+                ),
+            Arg::new(MAX_MISMATCHES_PER_KB)
+                .long(MAX_MISMATCHES_PER_KB)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1000f32))
+                .long_help(
+                    "Skip reads with more than X mismatches (MD tag) per 1000 aligned bases. \
+                    Heavily mismatched reads are often misaligned or originate from paralogs. \
+                    Reads without the MD tag are not filtered.",
+                ),
+            Arg::new(MAX_NON_AG_MISMATCHES)
+                .long(MAX_NON_AG_MISMATCHES)
+                .takes_value(true)
+                .validator(validate::numeric(0u32, u32::MAX))
+                .long_help(
+                    "Skip reads with more than X mismatches (MD tag) other than A->G and T->C, \
+                    i.e. not explained by A-to-I editing on either strand. \
+                    Reads without the MD tag are not filtered.",
+                ),
+            Arg::new(MAX_ERROR_RATE)
+                .long(MAX_ERROR_RATE)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .long_help(
+                    "Skip reads with the edit distance to the reference (NM tag) > X per alignment column, \
+                    i.e. per aligned, inserted or deleted base. \
+                    Useful for long reads (ONT/PacBio) with a variable per-read error rate. \
+                    Reads without the NM tag are not filtered.",
+                ),
+            Arg::new(READ_FILTER).long(READ_FILTER).takes_value(true).validator(validate::read_filter).long_help(
+                "Count only reads satisfying the given expression, e.g. \"NH==1 && (AS>=-10 || mapq>=30)\". \
+                    Conditions compare read fields (mapq, flag, alen - number of aligned bases, \
+                    or any two-letter SAM aux tag) with integers or strings using ==, !=, <, <=, >, >=, \
+                    and can be combined with && (and), || (or), ! (not), and parentheses. \
+                    Conditions on tags missing in the read are false, e.g. \"RG!=sample1\" skips reads without RG.",
+            ),
+            Arg::new(MAX_DEPTH)
+                .long(MAX_DEPTH)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, u32::MAX))
+                .long_help(
+                    "Downsample reads to keep the depth of each input file below X. \
+                    Reads starting at an already saturated position are skipped; reads starting at the same position \
+                    are selected by the hash of their name, so the results are reproducible regardless of the number \
+                    of threads. Mates from the same genomic bin are kept or skipped together, even if the second \
+                    one exceeds the depth. \
+                    Speeds up the processing of extremely covered genes (e.g. mitochondrial or ribosomal).",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -197,16 +372,26 @@ pub mod autoref {
     pub const MIN_FREQ: &str = "ref-min-freq";
     pub const HYPEREDITING: &str = "hyperedit";
     pub const VCF: &str = "vcf";
+    pub const VCF_SAMPLE: &str = "vcf-sample";
+    pub const GENOTYPE: &str = "genotype";
+    pub const GT_ERROR_RATE: &str = "gt-error-rate";
+    pub const GT_HETEROZYGOSITY: &str = "gt-heterozygosity";
+    pub const GT_EDITING_PRIOR: &str = "gt-editing-prior";
+    pub const GT_MIN_QUALITY: &str = "gt-min-qual";
 
     pub const SECTION_NAME: &str = "Autoref";
 
     pub fn args<'a>() -> Vec<Arg<'a>> {
         let args = vec![
-            Arg::new(VCF)
-                .long(VCF)
-                .takes_value(true)
-                .validator(validate::path)
-                .long_help("Path to a VCF file with filtered variants relevant to the given sample."),
+            Arg::new(VCF).long(VCF).takes_value(true).validator(validate::path).long_help(
+                "Path to a VCF/BCF file with filtered variants relevant to the given sample. \
+                    Multi-sample files require the sample name (--vcf-sample). \
+                    Indexed files (bcftools index, .tbi/.csi) are queried for each bin instead of being loaded \
+                    in memory, which is recommended for large cohort or WGS call sets.",
+            ),
+            Arg::new(VCF_SAMPLE).long(VCF_SAMPLE).takes_value(true).requires(VCF).long_help(
+                "Name of the sample in the multi-sample VCF/BCF file whose genotypes are used to adjust the reference.",
+            ),
             Arg::new(MIN_COVERAGE)
                 .long(MIN_COVERAGE)
                 .takes_value(true)
@@ -231,6 +416,49 @@ pub mod autoref {
                 "Turn on the \"hyperediting\" mode, i.e. do not correct(replace) A with G and T with C. \
                     This will ensure that potentially hyper-editable sites are not accidentally lost",
             ),
+            Arg::new(GENOTYPE).long(GENOTYPE).takes_value(false).conflicts_with(VCF).long_help(
+                "Predict the reference with a diploid genotype caller instead of the coverage/frequency cutoffs. \
+                    Genotypes are called from sequenced nucleotides using a uniform sequencing error rate \
+                    and a prior favoring the assembly nucleotide, heterozygous sites are reported as such(e.g. A/G). \
+                    Sites get an extra column with the Phred-scaled genotype quality(gq).",
+            ),
+            Arg::new(GT_ERROR_RATE)
+                .long(GT_ERROR_RATE)
+                .takes_value(true)
+                .requires(GENOTYPE)
+                .validator(validate::numeric(0.0001f32, 0.5f32))
+                .default_value("0.01")
+                .long_help("Sequencing error rate used by the genotype caller"),
+            Arg::new(GT_HETEROZYGOSITY)
+                .long(GT_HETEROZYGOSITY)
+                .takes_value(true)
+                .requires(GENOTYPE)
+                .validator(validate::numeric(0f32, 0.5f32))
+                .default_value("0.001")
+                .long_help(
+                    "Prior probability of a heterozygous SNP relative to the assembly nucleotide. \
+                    Homozygous SNPs get half of it.",
+                ),
+            Arg::new(GT_EDITING_PRIOR)
+                .long(GT_EDITING_PRIOR)
+                .takes_value(true)
+                .requires(GENOTYPE)
+                .validator(validate::numeric(0f32, 1f32))
+                .long_help(
+                    "Multiply priors of genotypes that could be explained by A->G or T->C editing \
+                    (i.e. A/G, G/G for the assembly A and T/C, C/C for the assembly T) by the given factor. \
+                    Use it to avoid calling partially edited sites as heterozygous SNPs.",
+                ),
+            Arg::new(GT_MIN_QUALITY)
+                .long(GT_MIN_QUALITY)
+                .takes_value(true)
+                .requires(GENOTYPE)
+                .validator(validate::numeric(0u8, 99u8))
+                .default_value("20")
+                .long_help(
+                    "Minimum Phred-scaled quality to accept the called genotype, \
+                    the assembly nucleotide is used for less confident sites",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
@@ -248,13 +476,19 @@ pub mod stranding {
 
     pub fn args<'a>() -> Vec<Arg<'a>> {
         let args = vec![
-            Arg::new(ANNOTATION).long(ANNOTATION).takes_value(true).validator(validate::path).long_help(
-                "Genome annotation in the GFF3 format. \
+            Arg::new(ANNOTATION)
+                .long(ANNOTATION)
+                .takes_value(true)
+                .validator(validate::path)
+                .required_if_eq(super::core::STRANDING, "auto")
+                .long_help(
+                    "Genome annotation in the GFF3 format. \
                     Genomic features (exons and genes) are used only to inference site/ROI strand based on the most \
                     likely direction of transcription (see the GitHub documentation for details). \
                     It is recommended to provide genome annotation for unstranded libraries, \
-                    otherwise stranding will be highly inaccurate.",
-            ),
+                    otherwise stranding will be highly inaccurate. \
+                    Genes are also used to infer the library design with \"--stranding auto\".",
+                ),
             Arg::new(EXTEND_UTR3)
                 .long(EXTEND_UTR3)
                 .takes_value(true)
@@ -297,18 +531,43 @@ pub fn all<'a>() -> Vec<Arg<'a>> {
         .collect()
 }
 
-type ReadsFilter = filters::Sequential<Record, filters::ByQuality, filters::ByFlags>;
+pub type ReadsFilter = filters::Sequential<
+    Record,
+    filters::Sequential<
+        Record,
+        filters::Sequential<Record, filters::ByQuality, filters::ByFlags>,
+        filters::ByMismatchLoad,
+    >,
+    filters::ByExpression,
+>;
 
 pub struct CoreArgs {
     pub name: String,
     pub threads: usize,
     pub trim5: u16,
     pub trim3: u16,
+    pub mates_overlap: MatesOverlap,
+    pub cigarmask: CigarMask,
+    pub weights: Option<BaseWeights>,
+    pub fracmulti: bool,
+    pub maxdepth: Option<u32>,
+    pub indels: bool,
+    pub longreads: bool,
+    pub umi: Vec<Vec<u8>>,
     pub bamfiles: Vec<PathBuf>,
+    // Coordinate-sorted reads from stdin, processed sequentially
+    pub stream: Option<Arc<Mutex<HTSStream>>>,
+    pub reference: PathBuf,
     pub refnucpred: Box<dyn RefEngine>,
+    // Whether the reference engine estimates genotype qualities
+    pub genoqual: bool,
     pub readfilter: ReadsFilter,
     pub stranding: Stranding,
     pub excluded: Option<Vec<BedRecord>>,
+    pub knownsnps: Option<Arc<KnownSites>>,
+    pub editingdb: Option<Arc<KnownEditingSites>>,
+    // How to split reads by samples + sample names
+    pub persample: Option<(SampleSplit, Vec<String>)>,
     pub saveto: csv::Writer<File>,
 }
 
@@ -319,17 +578,36 @@ impl CoreArgs {
         let (trim5, trim3) = parse::trimming(factory(), args);
 
         let reference = parse::reference(factory(), args);
-        let refreader = BasicFastaReader::new(reference);
+        let bamfiles = parse::bamfiles(factory(), args, &reference);
+        let stream = parse::stream(factory(), &bamfiles, &reference);
+        let persample = parse::persample(factory(), args, &bamfiles);
+        let refreader = BasicFastaReader::new(reference.clone());
+        let readfilter = parse::readfilter(factory(), args);
+        let stranding = parse::stranding(factory(), args, &bamfiles, &reference, &readfilter);
         Self {
             name,
             threads,
             trim5,
             trim3,
-            bamfiles: parse::bamfiles(factory(), args),
+            mates_overlap: parse::mates_overlap(factory(), args),
+            cigarmask: parse::cigarmask(factory(), args),
+            weights: parse::weights(factory(), args),
+            fracmulti: parse::fracmulti(factory(), args),
+            maxdepth: parse::maxdepth(factory(), args),
+            indels: parse::indels(factory(), args),
+            longreads: parse::longreads(factory(), args),
+            umi: parse::umi(factory(), args),
+            bamfiles,
+            stream,
+            reference,
             refnucpred: parse::refnucpred(factory(), args, Box::new(refreader)),
-            readfilter: parse::readfilter(factory(), args),
-            stranding: parse::stranding(factory(), args),
+            genoqual: args.is_present(autoref::GENOTYPE),
+            readfilter,
+            stranding,
             excluded: parse::excluded(factory(), args),
+            knownsnps: parse::knownsnps(factory(), args),
+            editingdb: parse::knownsites(factory(), args),
+            persample,
             saveto: parse::saveto(factory(), args),
         }
     }
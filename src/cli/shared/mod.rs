@@ -1,4 +1,4 @@
-pub use run::run;
+pub use run::{collide, run};
 
 pub mod args;
 pub mod parse;
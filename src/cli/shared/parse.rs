@@ -1,29 +1,33 @@
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 use bio_types::genome::AbstractInterval;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
 use itertools::Itertools;
-use rust_htslib::bam::Record;
+use rust_htslib::bam::{Read, Record};
 
 use crate::cli::shared::stranding::Stranding;
 use crate::core::io::fasta::FastaReader;
-use crate::core::io::{bed, vcf};
+use crate::core::io::{bed, hts, tsv, vcf};
 use crate::core::mismatches::{prefilters, MismatchesVec};
-use crate::core::refpred::{AutoRef, RefEngine, VCFCorrectedReference};
+use crate::core::refpred::{AutoRef, GenotypeRef, RefEngine, VCFCorrectedReference};
+use crate::core::rpileup::ncounter::cnt::{BaseWeights, CigarMask, MatesOverlap, SampleSplit};
 use crate::core::rpileup::ncounter::filters;
-use crate::core::stranding::deduce::StrandSpecificExperimentDesign;
+use crate::core::rpileup::stream::HTSStream;
+use crate::core::stranding::deduce::{ExperimentDesignTally, StrandSpecificExperimentDesign};
 use crate::core::stranding::predict::algo::{StrandByAtoIEditing, StrandByGenomicAnnotation};
 use crate::core::stranding::predict::{REATStrandingEngine, StrandingAlgo};
 
 use super::args;
 
-pub fn readfilter(
-    pbar: ProgressBar,
-    matches: &ArgMatches,
-) -> filters::Sequential<Record, filters::ByQuality, filters::ByFlags> {
+// Number of informative reads and the minimum fraction of reads explained by a design for "--stranding auto"
+const AUTO_STRANDING_READS: u32 = 200_000;
+const AUTO_STRANDING_MIN_FRACTION: f32 = 0.8;
+
+pub fn readfilter(pbar: ProgressBar, matches: &ArgMatches) -> args::ReadsFilter {
     pbar.set_message("Parsing filters filter options...");
     let (mapq, nomapq255, phread) = (
         matches.value_of(args::reads_filtering::MAPQ).unwrap().parse().unwrap(),
@@ -38,20 +42,44 @@ pub fn readfilter(
     );
     let byflags = filters::ByFlags::new(include, exclude);
 
-    let msg = format!(
+    let bymismatches = filters::ByMismatchLoad::new(
+        matches.value_of(args::reads_filtering::MAX_EDIT_DISTANCE).map(|x| x.parse().unwrap()),
+        matches.value_of(args::reads_filtering::MAX_MISMATCHES_PER_KB).map(|x| x.parse().unwrap()),
+        matches.value_of(args::reads_filtering::MAX_NON_AG_MISMATCHES).map(|x| x.parse().unwrap()),
+        matches.value_of(args::reads_filtering::MAX_ERROR_RATE).map(|x| x.parse().unwrap()),
+    );
+    let byexpression = matches
+        .value_of(args::reads_filtering::READ_FILTER)
+        .map_or(Default::default(), |x| filters::ByExpression::from_str(x).unwrap());
+
+    let mut msg = format!(
         "Reads filter options: require flags {}, disallow flags {}, mapq >= {}, phread >= {}. ",
         byflags.include(),
         byflags.exclude(),
         byquality.mapq(),
         byquality.phread()
     );
+    if bymismatches.is_enabled() {
+        let fmt = |x: Option<String>| x.unwrap_or_else(|| "any".to_owned());
+        msg += &format!(
+            "Mismatches: NM <= {}, per kb <= {}, non A->G/T->C <= {}, error rate <= {}. ",
+            fmt(bymismatches.max_edit_distance().map(|x| x.to_string())),
+            fmt(bymismatches.max_mismatches_per_kb().map(|x| x.to_string())),
+            fmt(bymismatches.max_non_ag_mismatches().map(|x| x.to_string())),
+            fmt(bymismatches.max_error_rate().map(|x| x.to_string())),
+        );
+    }
+    if let Some(x) = matches.value_of(args::reads_filtering::READ_FILTER) {
+        msg += &format!("Custom filter: {}. ", x);
+    }
     if nomapq255 {
         pbar.finish_with_message(msg + "Mapq = 255 is NOT allowed.");
     } else {
         pbar.finish_with_message(msg + "Mapq = 255 is allowed.");
     }
 
-    filters::Sequential::new(byquality, byflags)
+    let filter = filters::Sequential::new(filters::Sequential::new(byquality, byflags), bymismatches);
+    filters::Sequential::new(filter, byexpression)
 }
 
 pub fn trimming(pbar: ProgressBar, matches: &ArgMatches) -> (u16, u16) {
@@ -69,18 +97,136 @@ pub fn trimming(pbar: ProgressBar, matches: &ArgMatches) -> (u16, u16) {
     (trim5, trim3)
 }
 
+pub fn cigarmask(pbar: ProgressBar, matches: &ArgMatches) -> CigarMask {
+    pbar.set_message("Parsing CIGAR masking options...");
+    let mask = CigarMask {
+        splice: matches.value_of(args::reads_filtering::MASK_SPLICE).unwrap().parse().unwrap(),
+        indels: matches.value_of(args::reads_filtering::MASK_INDELS).unwrap().parse().unwrap(),
+        softclip: matches.value_of(args::reads_filtering::MASK_SOFTCLIP).unwrap().parse().unwrap(),
+    };
+
+    if mask.is_empty() {
+        pbar.finish_with_message("Masking around CIGAR events disabled.");
+    } else {
+        pbar.finish_with_message(format!(
+            "Skipping bases near CIGAR events: splice junctions: {}bp; indels: {}bp; soft clips: {}bp.",
+            mask.splice, mask.indels, mask.softclip
+        ));
+    }
+    mask
+}
+
+pub fn mates_overlap(pbar: ProgressBar, matches: &ArgMatches) -> MatesOverlap {
+    pbar.set_message("Parsing mates overlap options...");
+    let (result, msg) = match matches.value_of(args::reads_filtering::MATES_OVERLAP).unwrap() {
+        "count" => (MatesOverlap::Count, "Overlapping mates: bases are counted for each mate independently."),
+        "best-quality" => (MatesOverlap::BestQuality, "Overlapping mates: only the best quality base is counted."),
+        "drop-discordant" => {
+            (MatesOverlap::DropDiscordant, "Overlapping mates: concordant bases are counted once, discordant skipped.")
+        }
+        _ => panic!("Unknown mates overlap policy"),
+    };
+    pbar.finish_with_message(msg);
+    result
+}
+
+pub fn weights(pbar: ProgressBar, matches: &ArgMatches) -> Option<BaseWeights> {
+    let (result, msg) = match matches.value_of(args::reads_filtering::WEIGHTS) {
+        None => (None, "Weighted counting is disabled."),
+        Some("phred") => (Some(BaseWeights::Phred), "Bases are weighted by the base-call probability."),
+        Some("phred+mapq") => {
+            (Some(BaseWeights::PhredMapq), "Bases are weighted by the base-call and mapping probabilities.")
+        }
+        _ => panic!("Unknown base weights"),
+    };
+    pbar.finish_with_message(msg);
+    result
+}
+
+pub fn fracmulti(pbar: ProgressBar, matches: &ArgMatches) -> bool {
+    let fracmulti = matches.is_present(args::reads_filtering::NH_FRACTION);
+    if fracmulti {
+        pbar.finish_with_message("Multi-mapping reads are counted fractionally(1/NH).");
+    } else {
+        pbar.finish_with_message("Multi-mapping reads are counted as unique ones.");
+    }
+    fracmulti
+}
+
+pub fn maxdepth(pbar: ProgressBar, matches: &ArgMatches) -> Option<u32> {
+    let maxdepth = matches.value_of(args::reads_filtering::MAX_DEPTH).map(|x| x.parse().unwrap());
+    match maxdepth {
+        Some(x) => pbar.finish_with_message(format!("Reads are downsampled to the maximum depth of {}.", x)),
+        None => pbar.finish_with_message("Reads downsampling is disabled."),
+    }
+    maxdepth
+}
+
+pub fn indels(pbar: ProgressBar, matches: &ArgMatches) -> bool {
+    let indels = matches.is_present(args::core::INDELS);
+    if indels {
+        pbar.finish_with_message("Insertions and deletions will be counted.");
+    } else {
+        pbar.finish_with_message("Insertions and deletions are ignored.");
+    }
+    indels
+}
+
+pub fn longreads(pbar: ProgressBar, matches: &ArgMatches) -> bool {
+    let longreads = matches.is_present(args::core::LONG_READS);
+    if longreads {
+        pbar.finish_with_message("Counting is tuned for long reads.");
+    } else {
+        pbar.finish_with_message("Counting is tuned for short reads.");
+    }
+    longreads
+}
+
+pub fn umi(pbar: ProgressBar, matches: &ArgMatches) -> Vec<Vec<u8>> {
+    pbar.set_message("Parsing UMI options...");
+    match matches.values_of(args::reads_filtering::UMI) {
+        Some(tags) => {
+            let tags = tags.collect_vec();
+            pbar.finish_with_message(format!(
+                "PCR duplicates will be collapsed by UMIs from tags: {}",
+                tags.join(", ")
+            ));
+            tags.into_iter().map(|x| x.as_bytes().to_vec()).collect()
+        }
+        None => {
+            pbar.finish_with_message("UMI-based collapsing of PCR duplicates is disabled.");
+            vec![]
+        }
+    }
+}
+
 pub fn saveto(pbar: ProgressBar, matches: &ArgMatches) -> csv::Writer<File> {
     pbar.set_message("Parsing output path...");
     let result = matches.value_of(args::core::SAVETO).unwrap();
     let file = File::create(result).unwrap();
-    let file = csv::WriterBuilder::new().from_writer(file);
+    // Header is written by the results themselves, see mismatches::columns
+    let file = csv::WriterBuilder::new().has_headers(false).from_writer(file);
     pbar.finish_with_message(format!("Result will be saved to {}", result));
     file
 }
 
-pub fn stranding(pbar: ProgressBar, matches: &ArgMatches) -> Stranding {
+pub fn stranding(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamfiles: &[PathBuf],
+    reference: &Path,
+    readfilter: &impl filters::ReadsFilter<Record>,
+) -> Stranding {
     pbar.set_message("Parsing stranding parameter...");
-    let stranding = Stranding::from_str(matches.value_of(args::core::STRANDING).unwrap()).unwrap();
+    let value = matches.value_of(args::core::STRANDING).unwrap();
+    if value == "auto" {
+        assert!(
+            !bamfiles.iter().any(|x| hts::is_stdin(x)),
+            "Library design can't be inferred for reads from stdin, specify the stranding explicitly"
+        );
+        return infer_stranding(pbar, matches, bamfiles, reference, readfilter);
+    }
+    let stranding = Stranding::from_str(value).unwrap();
     let msg = match stranding {
         Stranding::Unstranded => {
             "Unstranded library, transcription strand will be predicted by heuristics"
@@ -93,12 +239,43 @@ pub fn stranding(pbar: ProgressBar, matches: &ArgMatches) -> Stranding {
                 StrandSpecificExperimentDesign::Flip1Same2 => { "Paired-end stranded library: read1 is reverse to the transcription strand, read2 matches transcription strand" }
             }
         }
+        Stranding::AlignerTags => {
+            "Unstranded library, transcription strand of spliced reads is derived from the XS/ts tags"
+        }
     };
     pbar.finish_with_message(msg);
     stranding
 }
 
-pub fn strandpred<T>(pbar: ProgressBar, matches: &ArgMatches) -> REATStrandingEngine<T>
+fn infer_stranding(
+    pbar: ProgressBar,
+    matches: &ArgMatches,
+    bamfiles: &[PathBuf],
+    reference: &Path,
+    readfilter: &impl filters::ReadsFilter<Record>,
+) -> Stranding {
+    pbar.set_message("Inferring library design from reads overlapping annotated genes...");
+    let gff3 = matches.value_of(args::stranding::ANNOTATION).unwrap();
+    let annotation = StrandByGenomicAnnotation::from_gff(gff3.as_ref(), 0, |_| {});
+
+    let mut tally = ExperimentDesignTally::default();
+    for file in bamfiles {
+        let mut reader = hts::reader(file, reference);
+        let reads = reader
+            .records()
+            .map(|x| x.unwrap_or_else(|_| panic!("Failed to read {} (HTS file corrupted?)", file.display())));
+        tally.sample(reads, readfilter, &annotation, AUTO_STRANDING_READS);
+    }
+
+    let stranding = match tally.design(AUTO_STRANDING_MIN_FRACTION) {
+        Some(x) => Stranding::Stranded(x),
+        None => Stranding::Unstranded,
+    };
+    pbar.finish_with_message(format!("Inferred \"{}\" library. {}", stranding, tally));
+    stranding
+}
+
+pub fn strandpred<T>(pbar: ProgressBar, matches: &ArgMatches, stranding: &Stranding) -> REATStrandingEngine<T>
 where
     T: MismatchesVec,
     StrandByGenomicAnnotation: StrandingAlgo<T>,
@@ -109,8 +286,7 @@ where
 
     let mut engine = REATStrandingEngine::new();
 
-    let stranding = Stranding::from_str(matches.value_of(args::core::STRANDING).unwrap()).unwrap();
-    if stranding != Stranding::Unstranded {
+    if *stranding != Stranding::Unstranded {
         pbar.finish_with_message(format!(
             "Strand prediction is disabled -> working with \"{}\" stranded library",
             stranding
@@ -143,17 +319,50 @@ pub fn refnucpred(pbar: ProgressBar, matches: &ArgMatches, reader: Box<dyn Fasta
 
     if let Some(file) = matches.value_of(args::autoref::VCF) {
         let file = Path::new(file);
-        let snv = vcf::parse(file);
+        let sample = matches.value_of(args::autoref::VCF_SAMPLE);
+        let name = file.file_name().unwrap().to_str().unwrap();
+        let sample_msg = sample.map_or_else(String::new, |x| format!(" (sample {})", x));
+
+        // Indexed files are queried for each bin instead of loading all variants in memory
+        if vcf::is_indexed(file) {
+            let variants = vcf::IndexedVCF::new(file.to_owned(), sample.map(|x| x.to_owned()));
+            pbar.finish_with_message(format!(
+                "Reference will be adjusted by SNPs fetched from the indexed file: {}{}.",
+                name, sample_msg
+            ));
+            return Box::new(VCFCorrectedReference::indexed(variants, reader));
+        }
+        pbar.set_message(format!("Loading all SNPs from the non-indexed file {} in memory...", name));
+        let snv = vcf::parse(file, sample);
 
         let heterozygotes: usize = snv.heterozygous.iter().map(|x| x.len()).sum();
         let homozygotes: usize = snv.homozygous.iter().map(|x| x.len()).sum();
 
         let variants = VCFCorrectedReference::new(snv, reader);
         pbar.finish_with_message(format!(
-            "Reference will be adjusted by SNPs(heterozygotes: {heterozygotes}, homozygotes: {homozygotes}) from: {}.",
-            file.file_name().unwrap().to_str().unwrap()
+            "Reference will be adjusted by SNPs(heterozygotes: {heterozygotes}, homozygotes: {homozygotes}) from: {}{}. \
+            WARNING: the file is not indexed, all SNPs were loaded in memory. \
+            Index it (bcftools index/tabix) to fetch SNPs only for the processed bins.",
+            name, sample_msg
         ));
         Box::new(variants)
+    } else if matches.is_present(args::autoref::GENOTYPE) {
+        let (errorrate, heterozygosity, minquality, editing) = (
+            matches.value_of(args::autoref::GT_ERROR_RATE).unwrap().parse().unwrap(),
+            matches.value_of(args::autoref::GT_HETEROZYGOSITY).unwrap().parse().unwrap(),
+            matches.value_of(args::autoref::GT_MIN_QUALITY).unwrap().parse().unwrap(),
+            matches.value_of(args::autoref::GT_EDITING_PRIOR).map(|x| x.parse().unwrap()),
+        );
+        let mut msg = format!(
+            "Reference prediction by genotype calling: error rate {}, heterozygosity {}, min quality {}.",
+            errorrate, heterozygosity, minquality
+        );
+        if let Some(editing) = editing {
+            msg += &format!(" A->G or T->C genotypes prior is scaled by {} (editing-aware).", editing);
+        }
+        let result = GenotypeRef::new(errorrate, heterozygosity, minquality, reader).with_editing_prior(editing);
+        pbar.finish_with_message(msg);
+        Box::new(result)
     } else {
         let (mincoverage, minfreq, hyperedit) = (
             matches.value_of(args::autoref::MIN_COVERAGE).unwrap().parse().unwrap(),
@@ -173,10 +382,17 @@ pub fn refnucpred(pbar: ProgressBar, matches: &ArgMatches, reader: Box<dyn Fasta
     }
 }
 
-pub fn bamfiles(pbar: ProgressBar, matches: &ArgMatches) -> Vec<PathBuf> {
+pub fn bamfiles(pbar: ProgressBar, matches: &ArgMatches, reference: &Path) -> Vec<PathBuf> {
     pbar.set_message("Parsing paths to the input files...");
     let result: Vec<PathBuf> = matches.values_of(args::core::INPUT).unwrap().map(|x| x.into()).collect();
-    if result.len() == 1 {
+    assert!(
+        result.len() == 1 || !result.iter().any(|x| hts::is_stdin(x)),
+        "Reads from stdin can't be pooled with other input files"
+    );
+    hts::validate_reference(&result, reference);
+    if hts::is_stdin(&result[0]) {
+        pbar.finish_with_message("Input: coordinate-sorted reads from stdin")
+    } else if result.len() == 1 {
         pbar.finish_with_message(format!("Input file path: {}", result[0].display()))
     } else {
         let paths = result.iter().map(|x| x.display()).join(" ");
@@ -185,6 +401,50 @@ pub fn bamfiles(pbar: ProgressBar, matches: &ArgMatches) -> Vec<PathBuf> {
     result
 }
 
+pub fn stream(pbar: ProgressBar, bamfiles: &[PathBuf], reference: &Path) -> Option<Arc<Mutex<HTSStream>>> {
+    if !bamfiles.iter().any(|x| hts::is_stdin(x)) {
+        pbar.finish_with_message("Reads are fetched from the indexed input files.");
+        return None;
+    }
+    pbar.set_message("Parsing the stdin header...");
+    let stream = HTSStream::stdin(reference);
+    pbar.finish_with_message(format!(
+        "Streaming reads for {} contigs from stdin, bins will be processed sequentially",
+        stream.contigs().len()
+    ));
+    Some(Arc::new(Mutex::new(stream)))
+}
+
+pub fn persample(pbar: ProgressBar, matches: &ArgMatches, bamfiles: &[PathBuf]) -> Option<(SampleSplit, Vec<String>)> {
+    pbar.set_message("Parsing per-sample options...");
+    let result = match matches.value_of(args::core::PER_SAMPLE) {
+        None => None,
+        Some("file") => {
+            let names = bamfiles
+                .iter()
+                .map(|x| x.file_stem().map_or_else(|| x.display().to_string(), |x| x.to_string_lossy().to_string()))
+                .collect_vec();
+            Some((SampleSplit::ByFile(bamfiles.len()), names))
+        }
+        Some("rg") => {
+            assert!(
+                !bamfiles.iter().any(|x| hts::is_stdin(x)),
+                "Per read group counts are not supported for reads from stdin"
+            );
+            let names = hts::readgroups(bamfiles);
+            assert!(!names.is_empty(), "There are no read groups (@RG) in the input files headers");
+            let groups = names.iter().enumerate().map(|(ind, x)| (x.as_bytes().to_vec(), ind)).collect();
+            Some((SampleSplit::ByReadGroup(groups), names))
+        }
+        Some(x) => panic!("Unknown per-sample option: {}", x),
+    };
+    match &result {
+        None => pbar.finish_with_message("Samples are pooled together."),
+        Some((_, names)) => pbar.finish_with_message(format!("Per-sample counts for: {}", names.join(", "))),
+    }
+    result
+}
+
 pub fn reference(pbar: ProgressBar, matches: &ArgMatches) -> PathBuf {
     pbar.set_message("Parsing path to the reference assembly...");
     let result: PathBuf = matches.value_of(args::core::REFERENCE).unwrap().into();
@@ -245,3 +505,46 @@ pub fn excluded(pbar: ProgressBar, matches: &ArgMatches) -> Option<Vec<bed::BedR
         None
     }
 }
+
+pub fn knownsnps(pbar: ProgressBar, matches: &ArgMatches) -> Option<Arc<prefilters::KnownSites>> {
+    pbar.set_message("Parsing known SNPs...");
+
+    let path = match matches.value_of(args::core::KNOWN_SNPS) {
+        Some(x) => Path::new(x),
+        None => {
+            pbar.finish_with_message("Known SNPs masking is disabled.");
+            return None;
+        }
+    };
+    let name = path.file_name().unwrap().to_string_lossy();
+    let positions = match name.ends_with(".bed") || name.ends_with(".bed.gz") {
+        true => bed::positions(path),
+        false => vcf::positions(path),
+    };
+    let known = prefilters::KnownSites::new(positions);
+    pbar.finish_with_message(format!("Known SNPs that will be masked: {} positions from {}", known.len(), name));
+    Some(Arc::new(known))
+}
+
+pub fn knownsites(pbar: ProgressBar, matches: &ArgMatches) -> Option<Arc<prefilters::KnownEditingSites>> {
+    pbar.set_message("Parsing known editing sites...");
+
+    let path = match matches.value_of(args::core::KNOWN_SITES) {
+        Some(x) => Path::new(x),
+        None => {
+            pbar.finish_with_message("Known editing sites annotation is disabled.");
+            return None;
+        }
+    };
+    let name = path.file_name().unwrap().to_string_lossy();
+    let sites = if name.ends_with(".bed") || name.ends_with(".bed.gz") {
+        bed::named_positions(path)
+    } else if name.ends_with(".vcf") || name.ends_with(".vcf.gz") || name.ends_with(".bcf") {
+        vcf::named_positions(path)
+    } else {
+        tsv::named_positions(path)
+    };
+    let known = prefilters::KnownEditingSites::new(sites);
+    pbar.finish_with_message(format!("Known editing sites: {} positions from {}", known.len(), name));
+    Some(Arc::new(known))
+}
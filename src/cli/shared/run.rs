@@ -6,25 +6,29 @@ use std::io;
 use indicatif::ProgressBar;
 use itertools::Itertools;
 use rayon::prelude::*;
+use rust_htslib::bam::Record;
 
 use crate::cli::shared;
 use crate::cli::shared::thread_cache::ThreadCache;
 use crate::core::hooks::stats::EditingStatType;
 use crate::core::hooks::stats::ROIEditingIndex;
 use crate::core::mismatches::{Batch, MismatchesVec};
+use crate::core::rpileup::hts::HTSPileupEngine;
+use crate::core::rpileup::{ReadsCollider, ReadsCollidingEngine};
 use crate::core::runner::Runner;
 use crate::core::strandutil::Stranded;
 
 const OUTPUT_IO_ERROR: &str = "Failed to write results to the output TSV file.";
 const STATS_IO_ERROR: &str = "Failed to write statistics to the output TSV file.";
 
+// Run the workload, results are grouped by contig and sorted by the contig name
 pub fn run<RunnerT, Mismatches, Workload, W: io::Write>(
     workload: Vec<Workload>,
     runner: RunnerT,
     pbar: ProgressBar,
-    saveto: &mut csv::Writer<W>,
     mut statsto: HashMap<EditingStatType, csv::Writer<W>>,
-) -> csv::Result<()>
+    sequential: bool,
+) -> Vec<Vec<Mismatches>>
 where
     Mismatches: Send + MismatchesVec,
     Workload: Sized + Send,
@@ -39,15 +43,16 @@ where
     pbar.set_length(workload.len() as u64);
 
     let ctxstore = ThreadCache::new(move || RefCell::new(runner.clone()));
-    let edits: Vec<Batch<Mismatches>> = workload
-        .into_par_iter()
-        // .into_iter()
-        .filter_map(|w| {
-            let result = ctxstore.get().borrow_mut().run(w);
-            pbar.inc(1);
-            result
-        })
-        .collect();
+    let process = |w: Workload| {
+        let result = ctxstore.get().borrow_mut().run(w);
+        pbar.inc(1);
+        result
+    };
+    // Streamed reads can be consumed only in the workload order
+    let edits: Vec<Batch<Mismatches>> = match sequential {
+        true => workload.into_iter().filter_map(process).collect(),
+        false => workload.into_par_iter().filter_map(process).collect(),
+    };
 
     // Report the result
     pbar.set_style(shared::style::run::finished());
@@ -101,10 +106,45 @@ where
         }
     }
     // Sort by contig name
-    let percontig = percontig.into_iter().sorted_by(|x, y| x.0.cmp(&y.0));
+    percontig.into_iter().sorted_by(|x, y| x.0.cmp(&y.0)).map(|x| x.1).collect()
+}
 
-    for items in percontig {
-        Mismatches::ugly_in_contig_sort_and_to_csv(items.1, saveto).expect(OUTPUT_IO_ERROR);
+pub fn save<Mismatches: MismatchesVec, W: io::Write>(percontig: &[Vec<Mismatches>], saveto: &mut csv::Writer<W>) {
+    for (ind, items) in percontig.iter().enumerate() {
+        Mismatches::ugly_in_contig_sort_and_to_csv(items, ind == 0, saveto).expect(OUTPUT_IO_ERROR);
     }
-    Ok(())
+}
+
+// Run the reads collider over the workload, results are sorted by contig and then by the workload order
+pub fn collide<Collider, Workload, Output>(
+    workload: Vec<Workload>,
+    pileuper: HTSPileupEngine<Collider>,
+    pbar: &ProgressBar,
+    contig: impl Fn(&Output) -> &str,
+) -> Vec<Output>
+where
+    Workload: Send,
+    Output: Clone + Send,
+    Collider: for<'a> ReadsCollider<'a, Record, Workload = Workload, ColliderResult = &'a Output> + Clone,
+    HTSPileupEngine<Collider>: Send,
+{
+    pbar.set_style(shared::style::run::running());
+    pbar.set_length(workload.len() as u64);
+
+    let ctxstore = ThreadCache::new(move || RefCell::new(pileuper.clone()));
+    let mut results: Vec<Output> = workload
+        .into_par_iter()
+        .filter_map(|w| {
+            let mut pileuper = ctxstore.get().borrow_mut();
+            pileuper.run(w);
+            let result = pileuper.result().cloned();
+            pbar.inc(1);
+            result
+        })
+        .collect();
+    // Stable sort -> the workload order is preserved within each contig
+    results.sort_by(|x, y| contig(x).cmp(contig(y)));
+
+    pbar.set_style(shared::style::run::finished());
+    results
 }
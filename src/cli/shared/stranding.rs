@@ -8,6 +8,8 @@ use crate::core::stranding::deduce::StrandSpecificExperimentDesign::*;
 pub enum Stranding {
     Unstranded,
     Stranded(StrandSpecificExperimentDesign),
+    // Unstranded library, strand of spliced reads is taken from the XS/ts aligner tags
+    AlignerTags,
 }
 
 impl FromStr for Stranding {
@@ -20,6 +22,7 @@ impl FromStr for Stranding {
             "f" => Ok(Stranding::Stranded(Flip)),
             "s/f" => Ok(Stranding::Stranded(Same1Flip2)),
             "f/s" => Ok(Stranding::Stranded(Flip1Same2)),
+            "xs" => Ok(Stranding::AlignerTags),
             _ => Err(format!("Unknown strand: {}", s)),
         }
     }
@@ -30,6 +33,7 @@ impl Display for Stranding {
         let symbol = match self {
             Stranding::Unstranded => "u",
             Stranding::Stranded(x) => x.symbol(),
+            Stranding::AlignerTags => "xs",
         };
         write!(f, "{}", symbol)
     }
@@ -2,6 +2,8 @@ use std::path::Path;
 use std::str::FromStr;
 
 use crate::cli::shared::stranding::Stranding;
+use crate::core::io::hts;
+use crate::core::rpileup::ncounter::filters;
 
 pub fn path(rawpath: &str) -> Result<(), String> {
     let path = Path::new(&rawpath);
@@ -12,6 +14,14 @@ pub fn path(rawpath: &str) -> Result<(), String> {
     }
 }
 
+// Input HTS file or "-" for the stream from stdin
+pub fn input(rawpath: &str) -> Result<(), String> {
+    match rawpath == hts::STDIN {
+        true => Ok(()),
+        false => path(rawpath),
+    }
+}
+
 pub fn writable(_rawpath: &str) -> Result<(), String> {
     // TODO: are there any good way to actually check that file is writeable?
     Ok(())
@@ -28,12 +38,30 @@ pub fn writable(_rawpath: &str) -> Result<(), String> {
 }
 
 pub fn stranding(stranding: &str) -> Result<(), String> {
+    // Inferred from the data
+    if stranding == "auto" {
+        return Ok(());
+    }
     match Stranding::from_str(stranding) {
         Ok(_) => Ok(()),
         Err(x) => Err(x),
     }
 }
 
+pub fn read_filter(expr: &str) -> Result<(), String> {
+    filters::ByExpression::from_str(expr).map(|_| ())
+}
+
+pub fn auxtag(tag: &str) -> Result<(), String> {
+    // From the SAM specification: [A-Za-z][A-Za-z0-9]
+    let bytes = tag.as_bytes();
+    if bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1].is_ascii_alphanumeric() {
+        Ok(())
+    } else {
+        Err(format!("{} is not a valid BAM tag", tag))
+    }
+}
+
 pub fn numeric<T>(low: T, upper: T) -> impl Fn(&str) -> Result<(), String>
 where
     T: FromStr + std::fmt::Display + std::cmp::PartialOrd + Sized,
@@ -57,7 +85,7 @@ where
 mod test {
     #[test]
     fn stranding() {
-        for symbol in ["u", "s", "f", "s/f", "f/s"] {
+        for symbol in ["u", "s", "f", "s/f", "f/s", "xs", "auto"] {
             assert!(super::stranding(symbol).is_ok());
         }
         for symbol in [".", "r", "uf", "ff", "rr", "+", "-"] {
@@ -65,6 +93,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn auxtag() {
+        for tag in ["RX", "UB", "X0", "ub"] {
+            assert!(super::auxtag(tag).is_ok());
+        }
+        for tag in ["", "R", "0X", "RXX", "R_"] {
+            assert!(super::auxtag(tag).is_err());
+        }
+    }
+
+    #[test]
+    fn read_filter() {
+        for expr in ["NH==1", "AS>=-10 && nM<=3", "!(RG==sample1)"] {
+            assert!(super::read_filter(expr).is_ok());
+        }
+        for expr in ["NH", "NH=1", "mapq>=high", "(NH==1"] {
+            assert!(super::read_filter(expr).is_err());
+        }
+    }
+
     #[test]
     fn numeric() {
         let validator = super::numeric(10, 12);
@@ -1,3 +1,5 @@
+use std::fs::File;
+
 use clap::Arg;
 use clap::ArgMatches;
 use indicatif::ProgressBar;
@@ -7,6 +9,7 @@ use crate::cli::shared::validate;
 use crate::core::mismatches::prefilters;
 use crate::core::mismatches::prefilters::retain::RetainSitesFromIntervals;
 use crate::core::mismatches::site::SiteMismatchesVec;
+use crate::core::rpileup::ncounter::cnt::CellBarcodes;
 use crate::core::stranding::predict::REATStrandingEngine;
 use crate::core::workload::SiteWorkload;
 
@@ -20,6 +23,10 @@ pub mod output_filtering {
     pub const MIN_COVERAGE: &str = "out-min-cov";
     pub const FORCE_LIST: &str = "force";
     pub const REGIONS: &str = "region";
+    pub const READ_STATS: &str = "read-stats";
+    pub const PER_READ: &str = "per-read";
+    pub const LINKAGE: &str = "linkage";
+    pub const LINKAGE_WINDOW: &str = "linkage-window";
 
     pub const SECTION_NAME: &str = "Output hooks";
 
@@ -57,13 +64,148 @@ pub mod output_filtering {
             Arg::new(REGIONS).long(REGIONS).takes_value(true).validator(validate::path).long_help(
                 "Process only sites overlapping the given BED file.",
             ),
+            Arg::new(READ_STATS).long(READ_STATS).takes_value(false).long_help(
+                "Report read-level statistics of mismatches: number of mismatching bases on the forward(mmfwd) and \
+                reverse(mmrev) strand of reads, their mean distance to the nearest read end(mmdist), \
+                Fisher's exact test p-value for the strand bias of mismatches(sbpval), \
+                and the read position bias(posbias), i.e. ratio of the mean distance to the read end \
+                for mismatching vs matching bases. Mismatches are relative to the predicted nucleotide, \
+                statistics are left empty for heterozygous sites.",
+            ),
+            Arg::new(PER_READ).long(PER_READ).takes_value(true).validator(validate::writable).long_help(
+                "Path to an additional TSV file with mismatches of each read against the predicted reference: \
+                read name, contig, read strand, comma-separated positions and types of mismatches \
+                relative to the forward strand of the genome(e.g. A>G, T>C). \
+                Useful to study co-occurring edits in long reads (ONT/PacBio). \
+                Reads spanning several genomic bins are reported once per bin, increase --binsize to avoid this. \
+                Requires an extra pass over the reads.",
+            ),
+            Arg::new(LINKAGE).long(LINKAGE).takes_value(true).validator(validate::writable).long_help(
+                "Path to an additional TSV file with the linkage of edits between pairs of nearby reported sites: \
+                number of reads(or read pairs) edited at both sites, only at the first/second site, or at none, \
+                along with the phi coefficient and r² of the 2x2 table. \
+                Pairs are formed only between sites with a known homozygous reference and the same transcription \
+                strand. Only the A->I editing alleles (A->G, T->C) are counted as edited, reads with other bases \
+                at a site are skipped there. In stranded modes, reads are paired only with sites on their \
+                transcription strand. Fragments with discordant mates at any of the two sites are skipped. \
+                Requires an extra pass over the reads.",
+            ),
+            Arg::new(LINKAGE_WINDOW)
+                .long(LINKAGE_WINDOW)
+                .takes_value(true)
+                .validator(validate::numeric(1u64, u64::MAX))
+                .default_value("100")
+                .long_help("Maximum distance between two sites to be tested for the linkage"),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
+pub mod single_cell {
+    use super::*;
+
+    pub const WHITELIST: &str = "cb-whitelist";
+    pub const TAG: &str = "cb-tag";
+    pub const MATRIX: &str = "cb-matrix";
+
+    pub const SECTION_NAME: &str = "Single cell";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(WHITELIST)
+                .long(WHITELIST)
+                .takes_value(true)
+                .validator(validate::path)
+                .requires(MATRIX)
+                .conflicts_with(shared::args::core::PER_SAMPLE)
+                .long_help(
+                    "Path to the file with cell barcodes, one per line (e.g. barcodes.tsv from Cell Ranger). \
+                    Only reads from the listed cells are counted in the per-cell matrices.",
+                ),
+            Arg::new(TAG)
+                .long(TAG)
+                .takes_value(true)
+                .validator(validate::auxtag)
+                .default_value("CB")
+                .long_help("BAM tag that holds the cell barcode"),
+            Arg::new(MATRIX)
+                .long(MATRIX)
+                .takes_value(true)
+                .validator(validate::writable)
+                .requires(WHITELIST)
+                .long_help(
+                    "Prefix for the per-cell output in the Matrix Market format: <prefix>.ref.mtx and \
+                    <prefix>.alt.mtx hold reference and alternative counts for each reported site(rows, same order \
+                    as in <prefix>.sites.tsv) and each whitelisted cell(columns, same order as in the whitelist). \
+                    <prefix>.sites.tsv annotates the matrix rows with the contig, position, strand and predicted \
+                    nucleotide of each site. The full site table is written only if the output path (-o) is \
+                    given explicitly. Heterozygous sites (see --genotype) have empty rows.",
+                ),
+        ];
+        args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
+    }
+}
+
+pub mod hyperediting {
+    use super::*;
+
+    pub const READS: &str = "hyper-reads";
+    pub const SITES: &str = "hyper-sites";
+    pub const MIN_EDITS: &str = "hyper-min-edits";
+    pub const MIN_DENSITY: &str = "hyper-min-density";
+
+    pub const SECTION_NAME: &str = "Hyper-editing";
+
+    pub fn args<'a>() -> Vec<Arg<'a>> {
+        let args = vec![
+            Arg::new(READS).long(READS).takes_value(true).validator(validate::writable).long_help(
+                "Path to an additional TSV file with hyper-edited reads, i.e. reads with a dense cluster of A>G \
+                (forward transcript) or T>C (reverse transcript) mismatches against the FASTA reference: \
+                read name, contig, transcription strand, start and end of the edited cluster, \
+                number of edits, number of covered reference A(T) bases and the density of edits. \
+                Reads spanning several genomic bins are evaluated once per bin. \
+                Requires an extra pass over the reads.",
+            ),
+            Arg::new(SITES).long(SITES).takes_value(true).validator(validate::writable).long_help(
+                "Path to an additional TSV file with nucleotides sequenced only by the hyper-edited reads \
+                (a separate track, same columns as the main output without the predicted reference). \
+                Only sites with mismatches are reported. Requires an extra pass over the reads.",
+            ),
+            Arg::new(MIN_EDITS)
+                .long(MIN_EDITS)
+                .takes_value(true)
+                .validator(validate::numeric(1u32, u32::MAX))
+                .default_value("5")
+                .long_help("Minimum number of A>G(T>C) mismatches for the read to be considered hyper-edited"),
+            Arg::new(MIN_DENSITY)
+                .long(MIN_DENSITY)
+                .takes_value(true)
+                .validator(validate::numeric(0f32, 1f32))
+                .default_value("0.1")
+                .long_help(
+                    "Minimum fraction of the covered reference A(T) bases sequenced as G(C) \
+                    for the read to be considered hyper-edited",
+                ),
         ];
         args.into_iter().map(|x| x.help_heading(Some(SECTION_NAME))).collect()
     }
 }
 
 pub fn all<'a>() -> Vec<Arg<'a>> {
-    shared::args::all().into_iter().chain(output_filtering::args()).collect()
+    shared::args::all()
+        .into_iter()
+        .chain(output_filtering::args())
+        .chain(single_cell::args())
+        .chain(hyperediting::args())
+        .collect()
+}
+
+// Outputs for the hyper-edited reads and sites + thresholds
+pub struct HyperEditArgs {
+    pub reads: Option<csv::Writer<File>>,
+    pub sites: Option<csv::Writer<File>>,
+    pub minedits: u32,
+    pub mindensity: f32,
 }
 
 pub struct SiteArgs {
@@ -72,6 +214,16 @@ pub struct SiteArgs {
     pub prefilter: prefilters::ByMismatches,
     pub stranding: REATStrandingEngine<SiteMismatchesVec>,
     pub retain: Option<RetainSitesFromIntervals>,
+    // Cell barcodes + output prefix for the per-cell matrices
+    pub cells: Option<(CellBarcodes, String)>,
+    // Whether to write the site table, it is optional if the per-cell matrices are requested
+    pub table: bool,
+    pub readstats: bool,
+    // Output for the per-read mismatches
+    pub perread: Option<csv::Writer<File>>,
+    // Output for the linkage between sites + maximum distance between them
+    pub linkage: Option<(csv::Writer<File>, u64)>,
+    pub hyperedit: Option<HyperEditArgs>,
 }
 
 impl SiteArgs {
@@ -92,16 +244,39 @@ impl SiteArgs {
         let (pbarw, pbars, pbarf) = (factory(), factory(), factory());
         rayon::scope(|s| {
             s.spawn(|_| {
-                let (w, m) = parse::work(pbarw, &core.bamfiles, core.excluded.take(), args);
+                let (w, m) = parse::work(pbarw, &core.bamfiles, core.stream.as_deref(), core.excluded.take(), args);
                 workload = Some(w);
                 maxsize = Some(m)
             });
             s.spawn(|_| {
-                stranding = shared::parse::strandpred(pbars, args);
+                stranding = shared::parse::strandpred(pbars, args, &core.stranding);
             });
             s.spawn(|_| retain = parse::retain(pbarf, args));
         });
 
-        Self { workload: workload.unwrap(), maxwsize: maxsize.unwrap(), prefilter: filter, stranding, retain }
+        let cells = parse::cells(factory(), args);
+        let table = cells.is_none() || args.occurrences_of(shared::args::core::SAVETO) > 0;
+        let readstats = parse::readstats(factory(), args);
+        let perread = parse::perread(factory(), args);
+        let linkage = parse::linkage(factory(), args);
+        let hyperedit = parse::hyperedit(factory(), args);
+        assert!(
+            core.stream.is_none() || (perread.is_none() && linkage.is_none() && hyperedit.is_none()),
+            "Per-read, linkage and hyper-editing outputs require a second pass over the reads \
+            and can't be used with reads from stdin"
+        );
+        Self {
+            workload: workload.unwrap(),
+            maxwsize: maxsize.unwrap(),
+            prefilter: filter,
+            stranding,
+            retain,
+            cells,
+            table,
+            readstats,
+            perread,
+            linkage,
+            hyperedit,
+        }
     }
 }
@@ -1,21 +1,29 @@
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::sync::Mutex;
 
 use bio_types::genome::{AbstractInterval, Interval};
 use clap::ArgMatches;
 use indicatif::ProgressBar;
+use itertools::Itertools;
 
 use crate::cli::shared;
-use crate::cli::sites::args::output_filtering::{FORCE_LIST, REGIONS};
+use crate::cli::sites::args::output_filtering::{FORCE_LIST, LINKAGE, LINKAGE_WINDOW, PER_READ, READ_STATS, REGIONS};
+use crate::cli::sites::args::{hyperediting, single_cell, HyperEditArgs};
 use crate::core::io;
 use crate::core::io::bed;
 use crate::core::io::bed::BedRecord;
 use crate::core::mismatches::prefilters::retain::RetainSitesFromIntervals;
+use crate::core::rpileup::ncounter::cnt::CellBarcodes;
+use crate::core::rpileup::stream::HTSStream;
 use crate::core::workload::SiteWorkload;
 
 pub fn work(
     pbar: ProgressBar,
     bamfiles: &[impl AsRef<Path>],
+    stream: Option<&Mutex<HTSStream>>,
     exclude: Option<Vec<BedRecord>>,
     matches: &ArgMatches,
 ) -> (Vec<SiteWorkload>, usize) {
@@ -30,7 +38,10 @@ pub fn work(
     } else {
         pbar.set_message(format!("Splitting the genome into {}bp bins...", binsize));
 
-        let contigs = io::hts::contigs(bamfiles);
+        let contigs = match stream {
+            Some(x) => x.lock().unwrap().contigs().to_vec(),
+            None => io::hts::contigs(bamfiles),
+        };
         SiteWorkload::from_intervals(contigs, binsize, exclude)
     };
 
@@ -62,3 +73,93 @@ pub fn retain(pbar: ProgressBar, matches: &ArgMatches) -> Option<RetainSitesFrom
         }
     }
 }
+
+pub fn readstats(pbar: ProgressBar, matches: &ArgMatches) -> bool {
+    let readstats = matches.is_present(READ_STATS);
+    if readstats {
+        pbar.finish_with_message("Read strand and position statistics will be reported for each site.");
+    } else {
+        pbar.finish_with_message("Read strand and position statistics are disabled.");
+    }
+    readstats
+}
+
+pub fn perread(pbar: ProgressBar, matches: &ArgMatches) -> Option<csv::Writer<File>> {
+    let result = match matches.value_of(PER_READ) {
+        Some(x) => x,
+        None => {
+            pbar.finish_with_message("Per-read mismatches are not reported.");
+            return None;
+        }
+    };
+    let file = File::create(result).unwrap_or_else(|_| panic!("Failed to create per-read output file {}", result));
+    let file = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+    pbar.finish_with_message(format!("Per-read mismatches will be saved to {}", result));
+    Some(file)
+}
+
+pub fn linkage(pbar: ProgressBar, matches: &ArgMatches) -> Option<(csv::Writer<File>, u64)> {
+    let result = match matches.value_of(LINKAGE) {
+        Some(x) => x,
+        None => {
+            pbar.finish_with_message("Linkage between sites is not reported.");
+            return None;
+        }
+    };
+    let window = matches.value_of(LINKAGE_WINDOW).unwrap().parse().unwrap();
+    let file = File::create(result).unwrap_or_else(|_| panic!("Failed to create linkage output file {}", result));
+    let file = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+    pbar.finish_with_message(format!("Linkage between sites within {}bp will be saved to {}", window, result));
+    Some((file, window))
+}
+
+pub fn hyperedit(pbar: ProgressBar, matches: &ArgMatches) -> Option<HyperEditArgs> {
+    let writer = |path: &str| {
+        let file = File::create(path).unwrap_or_else(|_| panic!("Failed to create hyper-editing output file {}", path));
+        csv::WriterBuilder::new().delimiter(b'\t').from_writer(file)
+    };
+    let (reads, sites) = (matches.value_of(hyperediting::READS), matches.value_of(hyperediting::SITES));
+    if reads.is_none() && sites.is_none() {
+        pbar.finish_with_message("Hyper-edited reads are not reported.");
+        return None;
+    }
+    let minedits = matches.value_of(hyperediting::MIN_EDITS).unwrap().parse().unwrap();
+    let mindensity = matches.value_of(hyperediting::MIN_DENSITY).unwrap().parse().unwrap();
+
+    pbar.finish_with_message(format!(
+        "Reads with ≥ {} A>G(T>C) mismatches and density ≥ {} are considered hyper-edited",
+        minedits, mindensity
+    ));
+    Some(HyperEditArgs { reads: reads.map(writer), sites: sites.map(writer), minedits, mindensity })
+}
+
+pub fn cells(pbar: ProgressBar, matches: &ArgMatches) -> Option<(CellBarcodes, String)> {
+    pbar.set_message("Parsing cell barcodes...");
+
+    let (whitelist, prefix) = match (matches.value_of(single_cell::WHITELIST), matches.value_of(single_cell::MATRIX)) {
+        (Some(whitelist), Some(prefix)) => (whitelist, prefix),
+        _ => {
+            pbar.finish_with_message("Per-cell counting is disabled");
+            return None;
+        }
+    };
+    let tag = matches.value_of(single_cell::TAG).unwrap();
+
+    let file = File::open(whitelist).unwrap_or_else(|_| panic!("Failed to open cell barcodes file {}", whitelist));
+    let barcodes = BufReader::new(file)
+        .lines()
+        .map(|x| x.expect("Failed to read cell barcodes file"))
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.into_bytes())
+        .collect_vec();
+    assert!(barcodes.iter().all_unique(), "Cell barcodes must be unique");
+
+    pbar.finish_with_message(format!(
+        "Counting {} cells using {} tag, matrices will be saved to {}.{{ref,alt}}.mtx",
+        barcodes.len(),
+        tag,
+        prefix
+    ));
+    Some((CellBarcodes::new(tag.as_bytes().to_vec(), barcodes), prefix.to_owned()))
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fs::File;
 
 use clap::ArgMatches;
 use indicatif::ProgressBar;
@@ -7,46 +8,223 @@ use crate::cli::shared;
 use crate::cli::shared::args::CoreArgs;
 use crate::cli::shared::stranding::Stranding;
 use crate::cli::sites::args::SiteArgs;
+use crate::core::dna::Nucleotide;
 use crate::core::hooks::engine::REATHooksEngine;
-use crate::core::mismatches::site::{SiteMismatchesBuilder, SiteMismatchesVec};
+use crate::core::hooks::stats::EditingStatType;
+use crate::core::mismatches::site::{CellMatrices, SiteMismatchesBuilder, SiteMismatchesVec};
+use crate::core::mismatches::MismatchesVec;
+use crate::core::refpred::PredNucleotide;
+use crate::core::rpileup::downsample::DepthDownsampler;
 use crate::core::rpileup::hts::HTSPileupEngine;
-use crate::core::rpileup::ncounter::cnt::{BaseNucCounter, IntervalNucCounter, StrandedNucCounter};
+use crate::core::rpileup::hyperedit::{HyperEditBatch, HyperEditCollider};
+use crate::core::rpileup::linkage::{LinkageBatch, LinkageCollider};
+use crate::core::rpileup::ncounter::cnt::{
+    BaseNucCounter, IntervalNucCounter, PerSampleNucCounter, StrandedNucCounter, UMINucCounter,
+};
+use crate::core::rpileup::perread::{ReadEditsBatch, ReadEditsCollider};
 use crate::core::runner::REATRunner;
+use crate::core::stranding::deduce::{DeduceStrandByDesign, DeduceStrandByTags};
+use crate::core::workload::{LinkageSite, LinkageWorkload};
 
 pub fn run(args: &ArgMatches, mut core: CoreArgs, factory: impl Fn() -> ProgressBar) {
-    let args = SiteArgs::new(&mut core, args, &factory);
+    let mut args = SiteArgs::new(&mut core, args, &factory);
+    // Streamed reads are consumed once -> bins are processed sequentially in the order of reads
+    let sequential = core.stream.is_some();
+    if let Some(stream) = &core.stream {
+        stream.lock().unwrap().sort(&mut args.workload);
+    }
 
     // Strander & Hooks don't require any further processing
     let mut strander = args.stranding;
     let hooks: REATHooksEngine<SiteMismatchesVec> = REATHooksEngine::new();
 
     // Mismatchs builder. Always with prefilter since there are no site-level stats right now
-    let builder = SiteMismatchesBuilder::new(args.maxwsize, core.refnucpred, args.retain, Some(args.prefilter));
+    let samples = SiteMismatchesVec::columns(core.persample.as_ref().map_or(&[][..], |(_, names)| names.as_slice()));
+    let perref = args.perread.as_ref().map(|_| core.refnucpred.clone());
+    let hyperref = args.hyperedit.as_ref().map(|_| core.refnucpred.clone());
+    let builder = SiteMismatchesBuilder::new(args.maxwsize, core.refnucpred, args.retain, Some(args.prefilter))
+        .with_samples(samples)
+        .with_known_sites(core.knownsnps.clone())
+        .with_editing_db(core.editingdb.clone())
+        .with_indels(core.indels)
+        .with_read_stats(args.readstats)
+        .with_weighted(core.weights.is_some() || core.fracmulti)
+        .with_genotype_quality(core.genoqual);
+    // Per-cell matrices are collected from the results, after the main pass
+    let (barcodes, matrices) = match args.cells {
+        Some((barcodes, prefix)) => {
+            let cells = barcodes.len() as u32;
+            (Some(barcodes), Some((prefix, cells)))
+        }
+        None => (None, None),
+    };
+
+    // Reads are downsampled before counting, filtered ones don't take up the depth
+    let downsampler = core.maxdepth.map(|x| DepthDownsampler::new(x, core.readfilter.clone()));
 
     // Initialize basic counter
-    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3);
+    let counter = BaseNucCounter::new(args.maxwsize, core.readfilter, core.trim5, core.trim3)
+        .with_mates_overlap(core.mates_overlap)
+        .with_cigar_mask(core.cigarmask)
+        .with_long_reads(core.longreads)
+        .with_base_weights(core.weights)
+        .with_multimappers_fraction(core.fracmulti)
+        .with_indels(core.indels)
+        .with_read_stats(args.readstats);
+    // Per-read mismatches are collected in a separate pass over the same workload
+    let perread = args.perread.map(|saveto| {
+        let collider = ReadEditsCollider::new(counter.clone(), perref.unwrap());
+        let pileuper = HTSPileupEngine::new(core.bamfiles.clone(), core.reference.clone(), collider)
+            .with_downsampler(downsampler.clone());
+        (args.workload.clone(), pileuper, saveto)
+    });
+    let linkage = args.linkage.map(|(saveto, window)| {
+        // Reads are paired only with sites on their transcription strand, as deduced for the main counter
+        let collider = LinkageCollider::new(counter.clone(), window);
+        let collider = match &core.stranding {
+            Stranding::Unstranded => collider,
+            Stranding::Stranded(x) => collider.with_strand_deducer(DeduceStrandByDesign::new(*x)),
+            Stranding::AlignerTags => collider.with_strand_deducer(DeduceStrandByTags::default()),
+        };
+        let pileuper = HTSPileupEngine::new(core.bamfiles.clone(), core.reference.clone(), collider)
+            .with_downsampler(downsampler.clone());
+        (pileuper, saveto, window)
+    });
+    let hyperedit = args.hyperedit.map(|hyperargs| {
+        let collider =
+            HyperEditCollider::new(counter.clone(), hyperref.unwrap(), hyperargs.minedits, hyperargs.mindensity)
+                .with_track(hyperargs.sites.is_some());
+        let pileuper = HTSPileupEngine::new(core.bamfiles.clone(), core.reference.clone(), collider)
+            .with_downsampler(downsampler.clone());
+        (args.workload.clone(), pileuper, hyperargs)
+    });
+    // Only nucleotides are reported per sample
+    let sample = UMINucCounter::new(IntervalNucCounter::new(counter.nucleotides_only()), core.umi.clone());
+    // Per-cell counts are needed only for the main pass
+    let counter = counter.with_cell_barcodes(barcodes);
     let counter = IntervalNucCounter::new(counter);
+    let counter = UMINucCounter::new(counter, core.umi);
+    let split = core.persample.map(|x| x.0);
+    // Sites don't have any extra statistics
+    let statsto: HashMap<EditingStatType, csv::Writer<File>> = HashMap::new();
 
-    match core.stranding {
+    let results = match core.stranding {
         Stranding::Unstranded => {
             // Compose strander + pileuper
-            let pileuper = HTSPileupEngine::new(core.bamfiles, counter);
+            let counter = PerSampleNucCounter::new(counter, sample, split);
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
             // Launch the processing
             let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, HashMap::new()).unwrap();
+            shared::run(args.workload, runner, factory(), statsto, sequential)
         }
         Stranding::Stranded(x) => {
             // Remove all stranding algorithm -> they are not required
             strander.clear();
             // Compose strander + pileuper
-            let deductor = crate::core::stranding::deduce::DeduceStrandByDesign::new(x);
-            let pileuper = HTSPileupEngine::new(core.bamfiles, StrandedNucCounter::new(counter, deductor));
+            let deductor = DeduceStrandByDesign::new(x);
+            let counter = PerSampleNucCounter::new(
+                StrandedNucCounter::new(counter, deductor),
+                StrandedNucCounter::new(sample, deductor),
+                split,
+            );
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
 
             // Launch the processing
             let runner = REATRunner::new(builder, strander, pileuper, hooks);
-            shared::run(args.workload, runner, factory(), &mut core.saveto, HashMap::new()).unwrap();
+            shared::run(args.workload, runner, factory(), statsto, sequential)
+        }
+        Stranding::AlignerTags => {
+            // Heuristics would duplicate loci/ROIs already counted for the forward/reverse strands
+            strander.clear();
+            // Compose strander + pileuper
+            let deductor = DeduceStrandByTags::default();
+            let counter = PerSampleNucCounter::new(
+                StrandedNucCounter::new(counter, deductor),
+                StrandedNucCounter::new(sample, deductor),
+                split,
+            );
+            let pileuper = HTSPileupEngine::new(core.bamfiles, core.reference, counter)
+                .with_downsampler(downsampler)
+                .with_stream(core.stream);
+
+            // Launch the processing
+            let runner = REATRunner::new(builder, strander, pileuper, hooks);
+            shared::run(args.workload, runner, factory(), statsto, sequential)
         }
     };
+    if args.table {
+        shared::save(&results, &mut core.saveto);
+    }
+
+    if let Some((prefix, cells)) = matrices {
+        let mut matrices = CellMatrices::default();
+        for items in &results {
+            for (vec, data) in SiteMismatchesVec::in_contig_sorted(items) {
+                matrices.push(vec.contig(), *data.pos, vec.trstrand(), *data.prednuc, data.cells);
+            }
+        }
+        matrices.save(&prefix, cells).expect("Failed to write per-cell matrices");
+    }
+
+    if let Some((workload, pileuper, mut saveto)) = perread {
+        let pbar = factory();
+        let batches = shared::collide(workload, pileuper, &pbar, |x: &ReadEditsBatch| &x.contig);
+        let reads: usize = batches.iter().map(|x| x.reads.len()).sum();
+        pbar.finish_with_message(format!("Finished per-read mismatches, reported reads: {}", reads));
+
+        for batch in batches {
+            batch.to_csv(&mut saveto).expect("Failed to write per-read mismatches to the output TSV file.");
+        }
+    }
+
+    if let Some((pileuper, mut saveto, window)) = linkage {
+        // Sites are reported only after filtering the counts of all bins and pairs may cross the bin borders.
+        // Hence, linkage requires a second pass, but reads are fetched only around the reported sites.
+        let sites = results
+            .iter()
+            .flat_map(|items| SiteMismatchesVec::in_contig_sorted(items))
+            .filter_map(|(vec, data)| match *data.prednuc {
+                // Only sites with a known homozygous reference can be tested for the linkage
+                PredNucleotide::Homozygous(refnuc) if refnuc != Nucleotide::Unknown => Some(LinkageSite {
+                    contig: vec.contig().to_owned(),
+                    pos: *data.pos,
+                    trstrand: vec.trstrand(),
+                    refnuc,
+                }),
+                _ => None,
+            })
+            .collect();
+        let workload = LinkageWorkload::from_sites(sites, window, args.maxwsize as u64);
+
+        let pbar = factory();
+        let batches = shared::collide(workload, pileuper, &pbar, |x: &LinkageBatch| &x.contig);
+        let pairs: usize = batches.iter().map(|x| x.pairs.len()).sum();
+        pbar.finish_with_message(format!("Finished linkage between sites, reported pairs: {}", pairs));
+
+        for batch in batches {
+            batch.to_csv(&mut saveto).expect("Failed to write linkage between sites to the output TSV file.");
+        }
+    }
+
+    if let Some((workload, pileuper, mut saveto)) = hyperedit {
+        let pbar = factory();
+        let batches = shared::collide(workload, pileuper, &pbar, |x: &HyperEditBatch| &x.contig);
+        let reads: usize = batches.iter().map(|x| x.reads.len()).sum();
+        pbar.finish_with_message(format!("Finished hyper-editing, reported reads: {}", reads));
+
+        for batch in batches {
+            if let Some(writer) = &mut saveto.reads {
+                batch.reads_to_csv(writer).expect("Failed to write hyper-edited reads to the output TSV file.");
+            }
+            if let Some(writer) = &mut saveto.sites {
+                batch.sites_to_csv(writer).expect("Failed to write hyper-edited sites to the output TSV file.");
+            }
+        }
+    }
 }
 
 // #[cfg(test)]
@@ -0,0 +1,64 @@
+use itertools::Itertools;
+
+// Insertions and deletions observed at a single genomic position
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IndelCounts {
+    // Number of reads with the position deleted
+    pub deletions: u32,
+    // Sequences inserted right after the position + number of supporting reads
+    pub insertions: Vec<(Vec<u8>, u32)>,
+}
+
+impl IndelCounts {
+    pub fn insert(&mut self, sequence: &[u8]) {
+        match self.insertions.iter_mut().find(|x| x.0 == sequence) {
+            Some(x) => x.1 += 1,
+            None => self.insertions.push((sequence.to_vec(), 1)),
+        }
+    }
+
+    #[inline]
+    pub fn inserted(&self) -> u32 {
+        self.insertions.iter().map(|x| x.1).sum()
+    }
+
+    #[inline]
+    pub fn events(&self) -> u32 {
+        self.deletions + self.inserted()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.deletions == 0 && self.insertions.is_empty()
+    }
+
+    // Inserted sequences as "SEQ:count" separated by ";", the most frequent go first
+    pub fn inserted_sequences(&self) -> String {
+        self.insertions
+            .iter()
+            .sorted_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(&y.0)))
+            .map(|(seq, cnt)| format!("{}:{}", String::from_utf8_lossy(seq), cnt))
+            .join(";")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut dummy = IndelCounts { deletions: 2, insertions: vec![] };
+        assert_eq!(dummy.events(), 2);
+        assert_eq!(dummy.inserted_sequences(), "");
+
+        for seq in [b"AG".as_ref(), b"T", b"AG", b"C", b"T", b"AG"] {
+            dummy.insert(seq);
+        }
+        assert_eq!(dummy.inserted(), 6);
+        assert_eq!(dummy.events(), 8);
+        assert_eq!(dummy.inserted_sequences(), "AG:3;T:2;C:1");
+        assert!(!dummy.is_empty());
+        assert!(IndelCounts::default().is_empty());
+    }
+}
@@ -1,5 +1,9 @@
 pub use alphabet::{Nucleotide, ReqNucleotide};
+pub use indels::IndelCounts;
 pub use ncounts::{FracNucCounts, NucCounts};
+pub use readstats::ReadStats;
 
 mod alphabet;
+mod indels;
 pub mod ncounts;
+mod readstats;
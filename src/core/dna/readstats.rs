@@ -0,0 +1,137 @@
+use bio_types::strand::ReqStrand;
+
+use crate::core::dna::ncounts::InnerNucCounts;
+use crate::core::dna::{NucCounts, Nucleotide, ReqNucleotide};
+
+// Read-level statistics of nucleotides sequenced at a single genomic position
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReadStats {
+    // Nucleotides sequenced on the forward/reverse strand of reads
+    pub forward: NucCounts,
+    pub reverse: NucCounts,
+    // Total distance from each base to the nearest end of the aligned part of the read
+    pub distance: InnerNucCounts<u64>,
+}
+
+impl ReadStats {
+    #[inline]
+    pub fn add(&mut self, nuc: ReqNucleotide, strand: ReqStrand, distance: u32) {
+        match strand {
+            ReqStrand::Forward => self.forward[nuc] += 1,
+            ReqStrand::Reverse => self.reverse[nuc] += 1,
+        }
+        self.distance[nuc] += distance as u64;
+    }
+
+    #[inline]
+    pub fn remove(&mut self, nuc: ReqNucleotide, strand: ReqStrand, distance: u32) {
+        match strand {
+            ReqStrand::Forward => self.forward[nuc] -= 1,
+            ReqStrand::Reverse => self.reverse[nuc] -= 1,
+        }
+        self.distance[nuc] -= distance as u64;
+    }
+
+    // Mismatching bases on the forward and reverse strand of reads
+    #[inline]
+    pub fn mismatches(&self, reference: Nucleotide) -> (u32, u32) {
+        (self.forward.mismatches(reference), self.reverse.mismatches(reference))
+    }
+
+    // Mean distance to the nearest read end for the mismatching and matching bases
+    pub fn mean_distance(&self, reference: Nucleotide) -> (Option<f32>, Option<f32>) {
+        let (fwd, rev) = self.mismatches(reference);
+        let (mismatches, total) = (fwd + rev, self.forward.coverage() + self.reverse.coverage());
+        let mmdist = self.distance.mismatches(reference);
+        let mean = |dist: u64, cnt: u32| if cnt > 0 { Some(dist as f32 / cnt as f32) } else { None };
+        (mean(mmdist, mismatches), mean(self.distance.coverage() - mmdist, total - mismatches))
+    }
+
+    // Two-sided Fisher's exact test for the read strand of the mismatching vs matching bases
+    pub fn strand_bias(&self, reference: Nucleotide) -> f64 {
+        let (mmfwd, mmrev) = self.mismatches(reference);
+        let (fwd, rev) = (self.forward.coverage() - mmfwd, self.reverse.coverage() - mmrev);
+        fisher_exact([[mmfwd, mmrev], [fwd, rev]])
+    }
+
+    // Ratio of the mean distance to the read end for the mismatching vs matching bases.
+    // Values well below 1 indicate mismatches concentrated near read ends.
+    pub fn position_bias(&self, reference: Nucleotide) -> Option<f32> {
+        match self.mean_distance(reference) {
+            (Some(mm), Some(matched)) if matched > 0.0 => Some(mm / matched),
+            _ => None,
+        }
+    }
+}
+
+// Two-sided p-value for the 2x2 contingency table: sum of probabilities of all tables with the same margins
+// that are at most as likely as the observed one
+fn fisher_exact(table: [[u32; 2]; 2]) -> f64 {
+    let [[a, b], [c, d]] = table.map(|x| x.map(|x| x as f64));
+    let (row1, col1) = (a + b, a + c);
+    let (lo, hi) = ((a - d).max(0.0) as u32, row1.min(col1) as u32);
+    if lo == hi {
+        return 1.0;
+    }
+
+    // Hypergeometric log-probabilities up to a constant, P(x + 1) / P(x) = (row1 - x)(col1 - x) / (x + 1)(d - a + x + 1)
+    let mut logp = Vec::with_capacity((hi - lo + 1) as usize);
+    logp.push(0f64);
+    for x in lo..hi {
+        let x = x as f64;
+        let ratio = ((row1 - x) * (col1 - x)) / ((x + 1.0) * (d - a + x + 1.0));
+        logp.push(logp.last().unwrap() + ratio.ln());
+    }
+    let max = logp.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let probs: Vec<f64> = logp.into_iter().map(|x| (x - max).exp()).collect();
+
+    let observed = probs[(a as u32 - lo) as usize];
+    let total: f64 = probs.iter().sum();
+    let pvalue: f64 = probs.iter().filter(|&&x| x <= observed * (1.0 + 1e-7)).sum();
+    (pvalue / total).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fisher_exact() {
+        // Same as scipy.stats.fisher_exact
+        for (table, expected) in [
+            ([[0, 0], [0, 0]], 1.0),
+            ([[3, 1], [1, 3]], 0.4857142857142857),
+            ([[8, 2], [1, 5]], 0.03496503496503496),
+            ([[10, 0], [10, 10]], 0.010993737230618791),
+            ([[0, 12], [25, 25]], 0.0009363257976170692),
+        ] {
+            let pvalue = super::fisher_exact(table);
+            assert!((pvalue - expected).abs() < 1e-9, "{:?}: {} vs {}", table, pvalue, expected);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let mut dummy = ReadStats::default();
+        for (nuc, strand, distance) in [
+            (ReqNucleotide::A, ReqStrand::Forward, 10),
+            (ReqNucleotide::A, ReqStrand::Reverse, 20),
+            (ReqNucleotide::A, ReqStrand::Forward, 30),
+            (ReqNucleotide::G, ReqStrand::Forward, 2),
+            (ReqNucleotide::G, ReqStrand::Forward, 4),
+            (ReqNucleotide::T, ReqStrand::Reverse, 7),
+        ] {
+            dummy.add(nuc, strand, distance);
+        }
+        dummy.remove(ReqNucleotide::T, ReqStrand::Reverse, 7);
+
+        assert_eq!(dummy.forward, NucCounts::new(2, 0, 2, 0));
+        assert_eq!(dummy.reverse, NucCounts::new(1, 0, 0, 0));
+        assert_eq!(dummy.mismatches(Nucleotide::A), (2, 0));
+        assert_eq!(dummy.mean_distance(Nucleotide::A), (Some(3.0), Some(20.0)));
+        assert_eq!(dummy.position_bias(Nucleotide::A), Some(0.15));
+        assert_eq!(dummy.mean_distance(Nucleotide::C), (Some(13.2), None));
+        assert_eq!(dummy.position_bias(Nucleotide::C), None);
+        assert!((dummy.strand_bias(Nucleotide::A) - 0.4).abs() < 1e-9);
+    }
+}
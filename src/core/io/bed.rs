@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
@@ -68,6 +69,50 @@ pub fn parse(bed: impl AsRef<Path>) -> Vec<BedRecord> {
     utils::read_compressed!(bed, _parse)
 }
 
+// All positions covered by the BED intervals mapped together with the record name
+fn _positions<T: BufRead, V>(mut reader: T, mapping: impl Fn(u32, &str) -> V) -> HashMap<String, Vec<V>> {
+    let mut positions: HashMap<String, Vec<V>> = HashMap::new();
+
+    let mut buf = String::new();
+    while reader.read_line(&mut buf).expect("Failed to read BED file") != 0 {
+        let line = buf.trim_end();
+        if line.is_empty() {
+            buf.clear();
+            continue;
+        }
+        let mut split = line.split('\t');
+        let (contig, start, end) = match (split.next(), split.next(), split.next()) {
+            (Some(contig), Some(start), Some(end)) => (contig, start, end),
+            _ => panic!("Failed to parse BED line: {}", line),
+        };
+        let name = split.next().unwrap_or("");
+        let start: u32 = start.parse().expect("Failed to parse BED start (positions must be < 2^32)");
+        let end: u32 = end.parse().expect("Failed to parse BED end (positions must be < 2^32)");
+        assert!(end > start, "{}", line);
+
+        let values = (start..end).map(|x| mapping(x, name));
+        match positions.get_mut(contig) {
+            Some(x) => x.extend(values),
+            None => {
+                positions.insert(contig.to_owned(), values.collect());
+            }
+        }
+        buf.clear();
+    }
+    positions
+}
+
+pub fn positions(bed: impl AsRef<Path>) -> HashMap<String, Vec<u32>> {
+    let bed = bed.as_ref();
+    utils::read_compressed!(bed, _positions, |pos, _| pos)
+}
+
+// Positions covered by the BED intervals + record names (empty if missing)
+pub fn named_positions(bed: impl AsRef<Path>) -> HashMap<String, Vec<(u32, String)>> {
+    let bed = bed.as_ref();
+    utils::read_compressed!(bed, _positions, |pos, name: &str| (pos, name.to_owned()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::BufReader;
@@ -124,4 +169,21 @@ mod tests {
 
         assert_eq!(records, _parse(BufReader::new(bed.as_bytes())));
     }
+
+    #[test]
+    fn positions() {
+        let bed = "\
+        chr1\t10\t12\trs1\n\
+        \n\
+        chr2\t5\t6\n\
+        chr1\t3\t4\trs2\t.\t-\n";
+        let positions = _positions(BufReader::new(bed.as_bytes()), |pos, _| pos);
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions["chr1"], [10, 11, 3]);
+        assert_eq!(positions["chr2"], [5]);
+
+        let named = _positions(BufReader::new(bed.as_bytes()), |pos, name| (pos, name.to_owned()));
+        assert_eq!(named["chr1"], [(10, "rs1".to_owned()), (11, "rs1".to_owned()), (3, "rs2".to_owned())]);
+        assert_eq!(named["chr2"], [(5, "".to_owned())]);
+    }
 }
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use bio_types::genome::Position;
 use dyn_clone::DynClone;
@@ -78,3 +81,23 @@ impl Clone for BasicFastaReader {
         Self::new(self.path.clone())
     }
 }
+
+// Contig names and lengths from the fasta index (<path>.fai)
+pub fn contigs(path: &Path) -> HashMap<String, u64> {
+    let fai = PathBuf::from(format!("{}.fai", path.display()));
+    let file = File::open(&fai).unwrap_or_else(|_| {
+        panic!("Failed to open fasta index {}. Was the reference indexed (samtools faidx)?", fai.display())
+    });
+
+    let mut contigs = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap_or_else(|_| panic!("Failed to read fasta index {}", fai.display()));
+        let mut split = line.split('\t');
+        let (name, length) = match (split.next(), split.next().and_then(|x| x.parse().ok())) {
+            (Some(name), Some(length)) => (name, length),
+            _ => panic!("Failed to parse fasta index {}, malformed line: {}", fai.display(), line),
+        };
+        contigs.insert(name.to_string(), length);
+    }
+    contigs
+}
@@ -4,7 +4,16 @@ use std::path::Path;
 use bio_types::genome::Interval;
 use itertools::{zip, Itertools};
 pub use rust_htslib::bam::IndexedReader;
-use rust_htslib::bam::Read;
+use rust_htslib::bam::{Read, Reader};
+
+use crate::core::io::fasta;
+
+// Input path for the coordinate-sorted stream of reads from stdin
+pub const STDIN: &str = "-";
+
+pub fn is_stdin(hts: &Path) -> bool {
+    hts.as_os_str() == STDIN
+}
 
 pub fn contigs(hts: &[impl AsRef<Path>]) -> Vec<Interval> {
     let mut contigs = HashMap::new();
@@ -42,3 +51,84 @@ pub fn contigs(hts: &[impl AsRef<Path>]) -> Vec<Interval> {
 
     contigs.into_iter().map(|(name, length)| Interval::new(name.into(), 0..length)).collect()
 }
+
+// Unique read group IDs (@RG ID:<...>) in the order of appearance
+pub fn readgroups(hts: &[impl AsRef<Path>]) -> Vec<String> {
+    let mut groups = Vec::new();
+    for file in hts {
+        let file = file.as_ref();
+        let reader =
+            IndexedReader::from_path(file).unwrap_or_else(|_| panic!("Failed to open file {}", file.display()));
+        let header = String::from_utf8_lossy(reader.header().as_bytes()).to_string();
+
+        for line in header.lines().filter(|x| x.starts_with("@RG")) {
+            let id = line
+                .split('\t')
+                .find_map(|x| x.strip_prefix("ID:"))
+                .unwrap_or_else(|| panic!("Read group without ID in the {} header: {}", file.display(), line));
+            if !groups.iter().any(|x| x == id) {
+                groups.push(id.to_owned());
+            }
+        }
+    }
+    groups
+}
+
+// Sequential reader, the reference is required to decode CRAM files
+pub fn reader(hts: &Path, reference: &Path) -> Reader {
+    let mut reader = Reader::from_path(hts).unwrap_or_else(|_| panic!("Failed to open file {}", hts.display()));
+    reader
+        .set_reference(reference)
+        .unwrap_or_else(|_| panic!("Failed to set reference {} for file {}", reference.display(), hts.display()));
+    reader
+}
+
+pub fn is_cram(hts: &Path) -> bool {
+    hts.extension().map_or(false, |x| x.eq_ignore_ascii_case("cram"))
+}
+
+// CRAM files are decoded using the reference -> it must be exactly the one used during the compression
+pub fn validate_reference(hts: &[impl AsRef<Path>], reference: &Path) {
+    let crams = hts.iter().map(|x| x.as_ref()).filter(|x| is_cram(x)).collect_vec();
+    if crams.is_empty() {
+        return;
+    }
+    let expected = fasta::contigs(reference);
+
+    for file in crams {
+        let reader = IndexedReader::from_path(file).unwrap_or_else(|_| {
+            panic!(
+                "Failed to open file {}\n\
+                    Possible reasons: CRAM file was not indexed (samtools index); you don't have read permissions",
+                file.display()
+            )
+        });
+        let header = reader.header();
+
+        for tid in 0..header.target_count() {
+            let name = String::from_utf8_lossy(header.tid2name(tid));
+            let length =
+                header.target_len(tid).unwrap_or_else(|| panic!("Failed to parse header for {}", file.display()));
+
+            match expected.get(name.as_ref()) {
+                Some(x) if *x == length => {}
+                Some(x) => panic!(
+                    "CRAM file {} doesn't match the reference {}: contig {} has length {} in the CRAM header \
+                    and {} in the reference. Use the reference assembly that was used to create the CRAM file.",
+                    file.display(),
+                    reference.display(),
+                    name,
+                    length,
+                    x
+                ),
+                None => panic!(
+                    "CRAM file {} doesn't match the reference {}: contig {} is missing in the reference. \
+                    Use the reference assembly that was used to create the CRAM file.",
+                    file.display(),
+                    reference.display(),
+                    name
+                ),
+            }
+        }
+    }
+}
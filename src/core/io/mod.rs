@@ -1,5 +1,7 @@
 pub mod bed;
 pub mod fasta;
 pub mod hts;
+pub mod mtx;
+pub mod tsv;
 pub mod utils;
 pub mod vcf;
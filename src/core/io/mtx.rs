@@ -0,0 +1,29 @@
+use std::io::{Result, Write};
+
+// Save a sparse integer matrix in the Matrix Market coordinate format. Indices are 0-based, entries are (row, col, value)
+pub fn write<W: Write>(mut saveto: W, rows: u32, cols: u32, entries: &[(u32, u32, u32)]) -> Result<()> {
+    writeln!(saveto, "%%MatrixMarket matrix coordinate integer general")?;
+    writeln!(saveto, "{} {} {}", rows, cols, entries.len())?;
+    for (row, col, value) in entries {
+        debug_assert!(*row < rows && *col < cols);
+        writeln!(saveto, "{} {} {}", row + 1, col + 1, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn write() {
+        let mut saveto = Vec::new();
+        super::write(&mut saveto, 3, 2, &[(0, 1, 5), (2, 0, 1)]).unwrap();
+        assert_eq!(
+            String::from_utf8(saveto).unwrap(),
+            "%%MatrixMarket matrix coordinate integer general\n3 2 2\n1 2 5\n3 1 1\n"
+        );
+
+        let mut saveto = Vec::new();
+        super::write(&mut saveto, 0, 0, &[]).unwrap();
+        assert_eq!(String::from_utf8(saveto).unwrap(), "%%MatrixMarket matrix coordinate integer general\n0 0 0\n");
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+use super::utils;
+
+// REDIportal-style table: contig and 1-based position in the first two columns, other columns are ignored.
+// Comments and header lines(non-numeric position) are skipped. There are no IDs -> they are left empty.
+fn _named_positions<T: BufRead>(mut reader: T) -> HashMap<String, Vec<(u32, String)>> {
+    let mut positions: HashMap<String, Vec<(u32, String)>> = HashMap::new();
+
+    let mut buf = String::new();
+    while reader.read_line(&mut buf).expect("Failed to read TSV file") != 0 {
+        let line = buf.trim_end();
+        let mut split = line.split('\t');
+        let parsed = match (split.next(), split.next().map(|x| x.parse::<u32>())) {
+            (Some(contig), Some(Ok(pos))) if !contig.starts_with('#') => Some((contig, pos)),
+            _ => None,
+        };
+        if let Some((contig, pos)) = parsed {
+            assert!(pos > 0, "Positions must be 1-based: {}", line);
+            match positions.get_mut(contig) {
+                Some(x) => x.push((pos - 1, String::new())),
+                None => {
+                    positions.insert(contig.to_owned(), vec![(pos - 1, String::new())]);
+                }
+            }
+        }
+        buf.clear();
+    }
+    positions
+}
+
+pub fn named_positions(tsv: impl AsRef<Path>) -> HashMap<String, Vec<(u32, String)>> {
+    let tsv = tsv.as_ref();
+    utils::read_compressed!(tsv, _named_positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_positions() {
+        let tsv = "\
+        Region\tPosition\tRef\tEd\tStrand\n\
+        # comment\n\
+        \n\
+        chr1\t10\tA\tG\t+\n\
+        chr1\t1\tT\tC\t-\n\
+        chrM\t100\tA\tG\t+\n";
+        let positions = _named_positions(BufReader::new(tsv.as_bytes()));
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions["chr1"], [(9, String::new()), (0, String::new())]);
+        assert_eq!(positions["chrM"], [(99, String::new())]);
+    }
+}
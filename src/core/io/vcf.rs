@@ -1,11 +1,12 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use bio_types::genome::Position;
 use itertools::Itertools;
-use rust_htslib::bcf::header::Id;
+use rust_htslib::bcf::header::{HeaderView, Id};
 use rust_htslib::bcf::record::GenotypeAllele;
-use rust_htslib::bcf::{Read, Reader};
+use rust_htslib::bcf::{IndexedReader, Read, Reader, Record};
 
 use crate::core::dna::ReqNucleotide;
 
@@ -17,9 +18,12 @@ pub struct SimplisticSNV {
     pub heterozygous: Vec<Vec<(Position, ReqNucleotide, ReqNucleotide)>>,
 }
 
-pub fn parse(vcf: impl AsRef<Path>) -> SimplisticSNV {
+// VCF or BCF file, genotypes are taken from the given sample or the only one in the file
+pub fn parse(vcf: impl AsRef<Path>, sample: Option<&str>) -> SimplisticSNV {
     let mut reader = Reader::from_path(vcf).expect("Error opening file.");
 
+    let sample = sample_index(reader.header(), sample);
+
     // Header rid2ref / ref2rid
     let contigs = reader.header().contig_count() as usize;
     let mut rid2ref = Vec::with_capacity(contigs);
@@ -33,52 +37,343 @@ pub fn parse(vcf: impl AsRef<Path>) -> SimplisticSNV {
     // Parsed variants
     let (mut homozygous, mut heterozygous) = (vec![Vec::new(); contigs], vec![Vec::new(); contigs]);
 
+    let mut record = reader.empty_record();
+    while let Some(Ok(())) = reader.read(&mut record) {
+        if let Some((pos, first, second)) = snv(&record, sample) {
+            let rid = record.rid().unwrap() as usize;
+            match first == second {
+                true => homozygous[rid].push((pos, first)),
+                false => heterozygous[rid].push((pos, first, second)),
+            }
+        }
+    }
+    SimplisticSNV { rid2ref, ref2rid, homozygous, heterozygous }
+}
+
+// Variants with longer reference alleles (e.g. structural ones) are not masked, they would mask whole regions
+const MAX_MASKED_LEN: i64 = 50;
+
+// Positions covered by the reference alleles of all short variants regardless of genotypes and filters
+pub fn positions(vcf: impl AsRef<Path>) -> HashMap<String, Vec<u32>> {
+    let mut reader = Reader::from_path(vcf).expect("Error opening file.");
+
+    let mut positions: Vec<Vec<u32>> = vec![Vec::new(); reader.header().contig_count() as usize];
     let mut record = reader.empty_record();
     while let Some(Ok(())) = reader.read(&mut record) {
         assert!(record.pos() >= 0, "There are negative positions in the VCF file");
-        assert_eq!(record.sample_count(), 1, "Each vcf must contain exactly 1 sample");
+        let start: u32 = record.pos().try_into().expect("VCF positions must be < 2^32");
+        let rid = record.rid().expect("VCF record without contig") as usize;
+        let rlen = record.rlen();
+        if rlen <= MAX_MASKED_LEN {
+            positions[rid].extend(start..start + rlen.max(1) as u32);
+        }
+    }
+
+    by_contig_name(&reader, positions)
+}
+
+fn by_contig_name<T>(reader: &Reader, values: Vec<Vec<T>>) -> HashMap<String, Vec<T>> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(_, x)| !x.is_empty())
+        .map(|(rid, x)| {
+            let name = reader.header().rid2name(rid as u32).expect("Failed to parse VCF contig names");
+            (String::from_utf8_lossy(name).into_owned(), x)
+        })
+        .collect()
+}
+
+// Positions of all variants + their IDs (empty if missing) regardless of genotypes and filters
+pub fn named_positions(vcf: impl AsRef<Path>) -> HashMap<String, Vec<(u32, String)>> {
+    let mut reader = Reader::from_path(vcf).expect("Error opening file.");
+
+    let mut positions: Vec<Vec<(u32, String)>> = vec![Vec::new(); reader.header().contig_count() as usize];
+    let mut record = reader.empty_record();
+    while let Some(Ok(())) = reader.read(&mut record) {
+        assert!(record.pos() >= 0, "There are negative positions in the VCF file");
+        let pos: u32 = record.pos().try_into().expect("VCF positions must be < 2^32");
+        let rid = record.rid().expect("VCF record without contig") as usize;
+        let id = match record.id() {
+            x if x == b"." => String::new(),
+            x => String::from_utf8_lossy(&x).into_owned(),
+        };
+        positions[rid].push((pos, id));
+    }
+    by_contig_name(&reader, positions)
+}
+
+// Variants of the indexed (tabix/CSI) VCF/BCF file are fetched only for the requested regions
+pub struct IndexedVCF {
+    path: PathBuf,
+    reader: IndexedReader,
+    sample: usize,
+    samplename: Option<String>,
+}
+
+impl IndexedVCF {
+    pub fn new(path: PathBuf, sample: Option<String>) -> Self {
+        let reader = IndexedReader::from_path(&path).unwrap_or_else(|_| {
+            panic!(
+                "Failed to open file {}\n\
+                Possible reasons: VCF file was not indexed (bcftools index); you don't have read permissions",
+                path.display()
+            )
+        });
+        let samplename = sample;
+        let sample = sample_index(reader.header(), samplename.as_deref());
+        Self { path, reader, sample, samplename }
+    }
+
+    // Alleles of the selected sample for all SNVs in the region
+    pub fn fetch(
+        &mut self,
+        contig: &str,
+        range: Range<Position>,
+        mut callback: impl FnMut(Position, ReqNucleotide, ReqNucleotide),
+    ) {
+        // No variants for the contig
+        let rid = match self.reader.header().name2rid(contig.as_bytes()) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        // End coordinate is inclusive
+        self.reader.fetch(rid, range.start, Some(range.end - 1)).unwrap_or_else(|_| {
+            panic!("Failed to fetch variants for {}:{}-{} from {}", contig, range.start, range.end, self.path.display())
+        });
+
+        let mut record = self.reader.empty_record();
+        while let Some(Ok(())) = self.reader.read(&mut record) {
+            match snv(&record, self.sample) {
+                Some((pos, first, second)) if range.contains(&pos) => callback(pos, first, second),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Clone for IndexedVCF {
+    fn clone(&self) -> Self {
+        Self::new(self.path.clone(), self.samplename.clone())
+    }
+}
 
-        // Consider only records that passed all filters
-        if !record.has_filter(&Id(0)) {
-            continue;
+pub fn is_indexed(vcf: &Path) -> bool {
+    ["tbi", "csi"].iter().any(|ext| {
+        let mut index = vcf.as_os_str().to_owned();
+        index.push(".");
+        index.push(ext);
+        Path::new(&index).exists()
+    })
+}
+
+// Index of the sample to take genotypes from, the only one by default
+fn sample_index(header: &HeaderView, sample: Option<&str>) -> usize {
+    let samples = header.samples();
+    match sample {
+        Some(name) => samples.iter().position(|x| *x == name.as_bytes()).unwrap_or_else(|| {
+            panic!(
+                "Sample {} is missing in the VCF file, available samples: {}",
+                name,
+                samples.iter().map(|x| String::from_utf8_lossy(x)).join(", ")
+            )
+        }),
+        None => {
+            assert_eq!(samples.len(), 1, "VCF file must contain exactly 1 sample or the sample must be selected");
+            0
         }
+    }
+}
+
+// Sample alleles for the SNV that passed all filters.
+// Reference and unknown genotypes, indels and large variants are skipped.
+fn snv(record: &Record, sample: usize) -> Option<(Position, ReqNucleotide, ReqNucleotide)> {
+    assert!(record.pos() >= 0, "There are negative positions in the VCF file");
+
+    // Consider only records that passed all filters
+    if !record.has_filter(&Id(0)) {
+        return None;
+    }
+
+    let mut genotype = record
+        .genotypes()
+        .unwrap()
+        .get(sample)
+        .iter()
+        .filter_map(|g| match g {
+            GenotypeAllele::PhasedMissing | GenotypeAllele::UnphasedMissing => None,
+            GenotypeAllele::Unphased(i) | GenotypeAllele::Phased(i) => Some(*i as usize),
+        })
+        .collect_vec();
+    // Unknown genotype + known -> set to known only
+    if genotype.len() == 1 {
+        genotype.push(genotype[0])
+    }
+    // Skip reference or strange genotypes
+    if genotype.len() == 0 || (genotype[0] == 0 && genotype[1] == 0) || genotype.len() > 2 {
+        return None;
+    }
+
+    // Fetch alleles
+    let (first, second) = (record.alleles()[genotype[0]], record.alleles()[genotype[1]]);
+
+    // Skip indels or large variants
+    if first.len() != 1 || second.len() != 1 {
+        return None;
+    }
+    match (first[0].try_into(), second[0].try_into()) {
+        (Ok(a), Ok(b)) => Some((record.pos() as Position, a, b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use rust_htslib::bcf::{Format, Header, Writer};
+    use rust_htslib::htslib;
+    use tempfile::TempDir;
+
+    use crate::core::dna::ReqNucleotide::*;
+
+    use super::*;
+
+    const VCF: &str = "\
+##fileformat=VCFv4.2
+##FILTER=<ID=PASS,Description=\"All filters passed\">
+##FILTER=<ID=LowQual,Description=\"Low quality\">
+##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">
+##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##contig=<ID=chr1,length=1000>
+##contig=<ID=chr2,length=1000>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2
+chr1\t10\trs1\tA\tG\t.\tPASS\t.\tGT\t0/1\t1/1
+chr1\t20\trs2\tC\tT\t.\tPASS\t.\tGT\t1|1\t0/0
+chr1\t30\t.\tG\tA,T\t.\tPASS\t.\tGT\t1/2\t./.
+chr1\t40\t.\tACGT\tA\t.\tPASS\t.\tGT\t0/1\t0/1
+chr1\t50\t.\tT\tC\t.\tLowQual\t.\tGT\t1/1\t1/1
+chr1\t60\tsv1\tA\t<DEL>\t.\tPASS\tSVTYPE=DEL;END=900\tGT\t0/1\t0/0
+chr2\t5\t.\tA\tC\t.\tPASS\t.\tGT\t./1\t0/1
+";
 
-        let mut genotype = record
-            .genotypes()
-            .unwrap()
-            .get(0)
-            .iter()
-            .filter_map(|g| match g {
-                GenotypeAllele::PhasedMissing | GenotypeAllele::UnphasedMissing => None,
-                GenotypeAllele::Unphased(i) | GenotypeAllele::Phased(i) => Some(*i as usize),
-            })
-            .collect_vec();
-        // Unknown genotype + known -> set to known only
-        if genotype.len() == 1 {
-            genotype.push(genotype[0])
+    // Plain VCF + bgzipped VCF with a tabix index + BCF with a CSI index
+    fn fixtures(folder: &Path, text: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let vcf = folder.join("variants.vcf");
+        std::fs::write(&vcf, text).unwrap();
+
+        let (vcfgz, bcf) = (folder.join("variants.vcf.gz"), folder.join("variants.bcf"));
+        for (path, format, minshift) in [(&vcfgz, Format::Vcf, 0), (&bcf, Format::Bcf, 14)] {
+            let mut reader = Reader::from_path(&vcf).unwrap();
+            let header = Header::from_template(reader.header());
+            let mut writer = Writer::from_path(path, &header, false, format).unwrap();
+            for record in reader.records() {
+                let mut record = record.unwrap();
+                writer.translate(&mut record);
+                writer.write(&record).unwrap();
+            }
+            // Flush & close the file before indexing
+            drop(writer);
+
+            let path = CString::new(path.to_str().unwrap()).unwrap();
+            assert_eq!(unsafe { htslib::bcf_index_build3(path.as_ptr(), std::ptr::null(), minshift, 0) }, 0);
         }
-        // Skip reference or strange genotypes
-        if genotype.len() == 0 || (genotype[0] == 0 && genotype[1] == 0) || genotype.len() > 2 {
-            continue;
+        (vcf, vcfgz, bcf)
+    }
+
+    #[test]
+    fn sample_index() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, _, bcf) = fixtures(folder.path(), VCF);
+        for file in [vcf, bcf] {
+            let reader = Reader::from_path(&file).unwrap();
+            assert_eq!(super::sample_index(reader.header(), Some("S1")), 0);
+            assert_eq!(super::sample_index(reader.header(), Some("S2")), 1);
         }
 
-        // Fetch alleles
-        let (first, second) = (record.alleles()[genotype[0]], record.alleles()[genotype[1]]);
+        // The only sample is selected by default
+        let single = "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n\
+            #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n";
+        let (vcf, _, _) = fixtures(folder.path(), single);
+        let reader = Reader::from_path(&vcf).unwrap();
+        assert_eq!(super::sample_index(reader.header(), None), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sample S3 is missing in the VCF file, available samples: S1, S2")]
+    fn sample_index_missing() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, _, _) = fixtures(folder.path(), VCF);
+        super::sample_index(Reader::from_path(&vcf).unwrap().header(), Some("S3"));
+    }
+
+    #[test]
+    #[should_panic(expected = "VCF file must contain exactly 1 sample or the sample must be selected")]
+    fn sample_index_ambiguous() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, _, _) = fixtures(folder.path(), VCF);
+        super::sample_index(Reader::from_path(&vcf).unwrap().header(), None);
+    }
+
+    #[test]
+    fn snv() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, _, _) = fixtures(folder.path(), VCF);
 
-        // Skip indels or large variants
-        if first.len() != 1 || second.len() != 1 {
-            continue;
+        // Filtered, reference, missing genotypes, indels and structural variants are skipped
+        for (sample, expected) in [
+            (0, vec![Some((9, A, G)), Some((19, T, T)), Some((29, A, T)), None, None, None, Some((4, C, C))]),
+            (1, vec![Some((9, G, G)), None, None, None, None, None, Some((4, A, C))]),
+        ] {
+            let mut reader = Reader::from_path(&vcf).unwrap();
+            let snvs: Vec<_> = reader.records().map(|x| super::snv(&x.unwrap(), sample)).collect();
+            assert_eq!(snvs, expected);
         }
-        let (first, second): (ReqNucleotide, ReqNucleotide) = match (first[0].try_into(), second[0].try_into()) {
-            (Ok(a), Ok(b)) => (a, b),
-            _ => continue,
-        };
 
-        let rid = record.rid().unwrap() as usize;
-        match first == second {
-            true => homozygous[rid].push((record.pos() as Position, first)),
-            false => heterozygous[rid].push((record.pos() as Position, first, second)),
+        let parsed = parse(&vcf, Some("S1"));
+        assert_eq!(parsed.rid2ref, ["chr1", "chr2"]);
+        assert_eq!(parsed.ref2rid["chr2"], 1);
+        assert_eq!(parsed.homozygous, [vec![(19, T)], vec![(4, C)]]);
+        assert_eq!(parsed.heterozygous, [vec![(9, A, G), (29, A, T)], vec![]]);
+    }
+
+    #[test]
+    fn positions() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, _, bcf) = fixtures(folder.path(), VCF);
+
+        // Filters and genotypes are ignored, the structural variant is skipped
+        for file in [vcf, bcf] {
+            let positions = super::positions(&file);
+            assert_eq!(positions.len(), 2);
+            assert_eq!(positions["chr1"], [9, 19, 29, 39, 40, 41, 42, 49]);
+            assert_eq!(positions["chr2"], [4]);
+        }
+    }
+
+    #[test]
+    fn indexed() {
+        let folder = TempDir::new().unwrap();
+        let (vcf, vcfgz, bcf) = fixtures(folder.path(), VCF);
+        assert!(!is_indexed(&vcf));
+        assert!(is_indexed(&vcfgz));
+        assert!(is_indexed(&bcf));
+
+        for file in [vcfgz, bcf] {
+            let mut variants = IndexedVCF::new(file, Some("S1".into()));
+            let mut fetch = |contig: &str, range: Range<Position>| {
+                let mut fetched = vec![];
+                variants.fetch(contig, range, |pos, first, second| fetched.push((pos, first, second)));
+                fetched
+            };
+            // The end is exclusive even though the htslib end coordinate is inclusive
+            assert_eq!(fetch("chr1", 0..19), [(9, A, G)]);
+            assert_eq!(fetch("chr1", 9..20), [(9, A, G), (19, T, T)]);
+            assert_eq!(fetch("chr1", 10..1000), [(19, T, T), (29, A, T)]);
+            assert_eq!(fetch("chr2", 0..1000), [(4, C, C)]);
+            // Contigs missing from the header have no variants
+            assert!(fetch("chrX", 0..1000).is_empty());
         }
     }
-    SimplisticSNV { rid2ref, ref2rid, homozygous, heterozygous }
 }
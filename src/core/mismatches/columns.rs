@@ -0,0 +1,175 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::Arc;
+
+use csv::Writer;
+use serde::ser::{self, Impossible, SerializeStruct};
+use serde::{Serialize, Serializer};
+
+// Owned names of the per-sample output columns, shared by all vectors in the run
+pub type SampleColumns = Arc<[String]>;
+
+// Per-sample values are serialized as a trailing sequence under this field.
+// In the header, it is replaced by the per-sample column names.
+pub const PER_SAMPLE: &str = "samples";
+
+// Column names for each sample & suffix combination: S1:A, S1:C, ..., S2:T
+pub fn columns(samples: &[String], suffixes: &[impl Display]) -> SampleColumns {
+    samples.iter().flat_map(|name| suffixes.iter().map(move |x| format!("{}:{}", name, x))).collect()
+}
+
+// Serde requires static field names -> csv can't derive the header for rows with per-sample columns.
+// The output writer doesn't write headers, instead the header is written here before the first row.
+pub fn to_csv<F: Write, T: Serialize>(
+    rows: impl Iterator<Item = T>,
+    columns: &[String],
+    header: bool,
+    writer: &mut Writer<F>,
+) -> csv::Result<()> {
+    let mut rows = rows.peekable();
+    if let (true, Some(first)) = (header, rows.peek()) {
+        let mut fields = Vec::new();
+        first.serialize(Header(&mut fields))?;
+        writer.write_record(fields.into_iter().chain(columns.iter().map(String::as_str)))?;
+    }
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(())
+}
+
+const NOT_A_STRUCT: &str = "Output header can be derived only for structs";
+
+// Collects static field names of a struct, values are skipped
+struct Header<'a>(&'a mut Vec<&'static str>);
+
+impl SerializeStruct for Header<'_> {
+    type Ok = ();
+    type Error = csv::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, _: &T) -> csv::Result<()> {
+        if key != PER_SAMPLE {
+            self.0.push(key);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> csv::Result<()> {
+        Ok(())
+    }
+}
+
+macro_rules! not_a_struct {
+    ($($method:ident($($arg:ty),*) -> $ret:ty;)*) => {
+        $(fn $method(self, $(_: $arg),*) -> csv::Result<$ret> {
+            Err(ser::Error::custom(NOT_A_STRUCT))
+        })*
+    };
+}
+
+impl Serializer for Header<'_> {
+    type Ok = ();
+    type Error = csv::Error;
+    type SerializeSeq = Impossible<(), csv::Error>;
+    type SerializeTuple = Impossible<(), csv::Error>;
+    type SerializeTupleStruct = Impossible<(), csv::Error>;
+    type SerializeTupleVariant = Impossible<(), csv::Error>;
+    type SerializeMap = Impossible<(), csv::Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), csv::Error>;
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> csv::Result<Self> {
+        Ok(self)
+    }
+
+    not_a_struct! {
+        serialize_bool(bool) -> ();
+        serialize_i8(i8) -> ();
+        serialize_i16(i16) -> ();
+        serialize_i32(i32) -> ();
+        serialize_i64(i64) -> ();
+        serialize_u8(u8) -> ();
+        serialize_u16(u16) -> ();
+        serialize_u32(u32) -> ();
+        serialize_u64(u64) -> ();
+        serialize_f32(f32) -> ();
+        serialize_f64(f64) -> ();
+        serialize_char(char) -> ();
+        serialize_str(&str) -> ();
+        serialize_bytes(&[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(&'static str) -> ();
+        serialize_unit_variant(&'static str, u32, &'static str) -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> csv::Result<()> {
+        Err(ser::Error::custom(NOT_A_STRUCT))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, _: &T) -> csv::Result<()> {
+        Err(ser::Error::custom(NOT_A_STRUCT))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> csv::Result<()> {
+        Err(ser::Error::custom(NOT_A_STRUCT))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Row {
+        pos: u64,
+        samples: Vec<[u32; 2]>,
+    }
+
+    impl Serialize for Row {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut state = serializer.serialize_struct("Row", 2)?;
+            state.serialize_field("pos", &self.pos)?;
+            state.serialize_field(PER_SAMPLE, &self.samples)?;
+            state.end()
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let samples = ["s1".to_owned(), "s2".to_owned()];
+        let columns = super::columns(&samples, &["A", "C"]);
+        assert_eq!(&*columns, ["s1:A", "s1:C", "s2:A", "s2:C"]);
+        assert!(super::columns(&[], &["A"]).is_empty());
+    }
+
+    #[test]
+    fn to_csv() {
+        let columns = super::columns(&["s1".to_owned(), "s2".to_owned()], &["A", "C"]);
+        let rows =
+            || vec![Row { pos: 1, samples: vec![[1, 2], [3, 4]] }, Row { pos: 5, samples: vec![[0, 0], [7, 8]] }];
+
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        super::to_csv(rows().into_iter(), &columns, true, &mut writer).unwrap();
+        // The header is written only once
+        super::to_csv(rows().into_iter().take(1), &columns, false, &mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(written, "pos,s1:A,s1:C,s2:A,s2:C\n1,1,2,3,4\n5,0,0,7,8\n1,1,2,3,4\n");
+
+        // No rows -> no header
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        super::to_csv(std::iter::empty::<Row>(), &columns, true, &mut writer).unwrap();
+        assert!(writer.into_inner().unwrap().is_empty());
+    }
+}
@@ -3,6 +3,7 @@ use std::io::Write;
 
 use crate::core::strandutil::Stranded;
 
+pub mod columns;
 pub mod prefilters;
 pub mod roi;
 pub mod site;
@@ -16,7 +17,12 @@ pub trait MismatchesVec: Sized {
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool;
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut csv::Writer<F>) -> csv::Result<()>;
+    // Items of a single contig; the header must be requested only for the first contig in the output
+    fn ugly_in_contig_sort_and_to_csv<F: Write>(
+        items: &[Self],
+        header: bool,
+        writer: &mut csv::Writer<F>,
+    ) -> csv::Result<()>;
 }
 
 pub trait Builder<'a> {
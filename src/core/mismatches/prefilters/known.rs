@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bio_types::genome::Position;
+
+// Known polymorphic positions (e.g. dbSNP) that are masked regardless of the sample genotype.
+// Positions are stored as sorted offsets within contigs, 4 bytes per position.
+#[derive(Clone, Debug, Default)]
+pub struct KnownSites {
+    contigs: HashMap<String, Vec<u32>>,
+}
+
+impl KnownSites {
+    pub fn new(mut contigs: HashMap<String, Vec<u32>>) -> Self {
+        for positions in contigs.values_mut() {
+            positions.sort_unstable();
+            positions.dedup();
+            positions.shrink_to_fit();
+        }
+        Self { contigs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contigs.values().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contigs.values().all(|x| x.is_empty())
+    }
+
+    // Sorted known positions inside the range
+    pub fn within(&self, contig: &str, range: Range<Position>) -> &[u32] {
+        let positions = match self.contigs.get(contig) {
+            Some(x) => x,
+            None => return &[],
+        };
+        let start = positions.partition_point(|&x| (x as Position) < range.start);
+        let end = positions.partition_point(|&x| (x as Position) < range.end);
+        &positions[start..end]
+    }
+}
+
+// Known editing sites (e.g. REDIportal) with their database IDs, indexed like KnownSites
+#[derive(Clone, Debug, Default)]
+pub struct KnownEditingSites {
+    contigs: HashMap<String, (Vec<u32>, Vec<String>)>,
+}
+
+impl KnownEditingSites {
+    // IDs of duplicated positions are joined by ","
+    pub fn new(contigs: HashMap<String, Vec<(u32, String)>>) -> Self {
+        let contigs = contigs
+            .into_iter()
+            .map(|(contig, mut sites)| {
+                sites.sort_by_key(|x| x.0);
+                let (mut positions, mut ids): (Vec<u32>, Vec<String>) = (Vec::new(), Vec::new());
+                for (pos, id) in sites {
+                    match positions.last() {
+                        Some(&last) if last == pos => {
+                            let last = ids.last_mut().unwrap();
+                            if !id.is_empty() && last.split(',').all(|x| x != id) {
+                                if !last.is_empty() {
+                                    last.push(',');
+                                }
+                                last.push_str(&id);
+                            }
+                        }
+                        _ => {
+                            positions.push(pos);
+                            ids.push(id);
+                        }
+                    }
+                }
+                positions.shrink_to_fit();
+                ids.shrink_to_fit();
+                (contig, (positions, ids))
+            })
+            .collect();
+        Self { contigs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contigs.values().map(|x| x.0.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contigs.values().all(|x| x.0.is_empty())
+    }
+
+    // Sorted known positions inside the range and their IDs
+    pub fn within(&self, contig: &str, range: Range<Position>) -> (&[u32], &[String]) {
+        let (positions, ids) = match self.contigs.get(contig) {
+            Some(x) => x,
+            None => return (&[], &[]),
+        };
+        let start = positions.partition_point(|&x| (x as Position) < range.start);
+        let end = positions.partition_point(|&x| (x as Position) < range.end);
+        (&positions[start..end], &ids[start..end])
+    }
+
+    // Number of known sites inside the given intervals, intervals must not overlap
+    pub fn count(&self, contig: &str, intervals: &[Range<Position>]) -> u32 {
+        intervals.iter().map(|x| self.within(contig, x.clone()).0.len() as u32).sum()
+    }
+}
+
+// Known positions are sorted -> binary search
+#[inline]
+pub fn is_known(known: &[u32], pos: Position) -> bool {
+    !known.is_empty() && known.binary_search(&(pos as u32)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn within() {
+        let dummy =
+            KnownSites::new(HashMap::from([("1".to_owned(), vec![30, 10, 20, 10, 5]), ("2".to_owned(), vec![])]));
+        assert_eq!(dummy.len(), 4);
+        assert!(!dummy.is_empty());
+
+        assert_eq!(dummy.within("1", 0..100), [5, 10, 20, 30]);
+        assert_eq!(dummy.within("1", 10..30), [10, 20]);
+        assert!(dummy.within("1", 11..20).is_empty());
+        assert!(dummy.within("2", 0..100).is_empty());
+        assert!(dummy.within("3", 0..100).is_empty());
+
+        let known = dummy.within("1", 0..25);
+        assert!(is_known(known, 20));
+        assert!(!is_known(known, 21));
+        assert!(!is_known(&[], 20));
+    }
+
+    #[test]
+    fn editing_sites() {
+        let sites = vec![(20, "".to_owned()), (10, "id1".to_owned()), (20, "id2".to_owned()), (20, "id2".to_owned())];
+        let dummy = KnownEditingSites::new(HashMap::from([("1".to_owned(), sites)]));
+        assert_eq!(dummy.len(), 2);
+        assert!(!dummy.is_empty());
+
+        let (positions, ids) = dummy.within("1", 0..100);
+        assert_eq!(positions, [10, 20]);
+        assert_eq!(ids, ["id1", "id2"]);
+        assert_eq!(dummy.within("1", 11..20), (&[][..], &[][..]));
+        assert_eq!(dummy.within("2", 0..100), (&[][..], &[][..]));
+
+        assert_eq!(dummy.count("1", &[0..11, 15..21]), 2);
+        assert_eq!(dummy.count("1", &[0..10, 11..20]), 0);
+        assert_eq!(dummy.count("2", &[0..100]), 0);
+    }
+}
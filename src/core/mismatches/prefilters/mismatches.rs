@@ -1,4 +1,4 @@
-use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::dna::{IndelCounts, NucCounts, Nucleotide};
 use crate::core::mismatches::roi::{ROIData, ROINucCounts};
 use crate::core::mismatches::site::SiteData;
 use crate::core::refpred::PredNucleotide;
@@ -39,6 +39,14 @@ impl ByMismatches {
         cov >= self.mincov_u32 && mismatch >= self.minmismatches_u32 && mismatch as f32 / cov as f32 >= self.minfreq
     }
 
+    // Indels are treated as mismatches, deleted bases contribute to the coverage
+    #[inline]
+    pub fn enough_indels_per_site(&self, sequenced: &NucCounts, indels: &IndelCounts) -> bool {
+        let cov = sequenced.coverage() + indels.deletions;
+        let events = indels.events();
+        cov >= self.mincov_u32 && events >= self.minmismatches_u32 && events as f32 / cov as f32 >= self.minfreq
+    }
+
     #[inline]
     pub fn mincov(&self) -> u32 {
         self.mincov_u32
@@ -65,6 +73,9 @@ impl MismatchesPreFilter<ROIData> for ByMismatches {
 impl MismatchesPreFilter<SiteData> for ByMismatches {
     #[inline]
     fn is_ok(&self, preview: &SiteData) -> bool {
+        if !preview.indels.is_empty() && self.enough_indels_per_site(&preview.sequenced, &preview.indels) {
+            return true;
+        }
         match preview.prednuc {
             PredNucleotide::Homozygous(nuc) => self.enough_mismatches_per_site(nuc, &preview.sequenced),
             PredNucleotide::Heterozygous((n1, n2)) => {
@@ -147,4 +158,22 @@ mod tests {
             assert_eq!(filter.enough_mismatches_per_site(reference, &sequenced), expected);
         }
     }
+
+    #[test]
+    fn ok_indels() {
+        let sequenced = NucCounts { A: 6, C: 0, G: 0, T: 0 };
+        let indels = IndelCounts { deletions: 3, insertions: vec![(b"AC".to_vec(), 1)] };
+        // coverage = 9, events = 4, freq = 0.444
+
+        for (expected, minmismatches, minfreq, mincov) in [
+            (true, 4, 0f32, 0),
+            (false, 5, 0f32, 0),
+            (true, 4, 0.44f32, 9),
+            (false, 4, 0.45f32, 9),
+            (false, 4, 0.44f32, 10),
+        ] {
+            let filter = ByMismatches::new(minmismatches, minfreq, mincov);
+            assert_eq!(filter.enough_indels_per_site(&sequenced, &indels), expected);
+        }
+    }
 }
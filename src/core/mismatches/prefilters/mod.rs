@@ -1,5 +1,7 @@
+pub use known::{KnownEditingSites, KnownSites};
 pub use mismatches::ByMismatches;
 
+pub mod known;
 mod mismatches;
 pub mod retain;
 
@@ -1,15 +1,18 @@
 use std::iter::zip;
+use std::sync::Arc;
 
 use bio_types::genome::{AbstractInterval, Position};
 use bio_types::strand::Strand;
 
-use crate::core::dna::{NucCounts, Nucleotide};
+use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, Nucleotide};
+use crate::core::mismatches::columns::SampleColumns;
+use crate::core::mismatches::prefilters::known::is_known;
 use crate::core::mismatches::prefilters::retain::ROIRetainer;
-use crate::core::mismatches::prefilters::MismatchesPreFilter;
-use crate::core::mismatches::roi::{ROIData, ROIDataVec, ROIMismatchesVec, ROINucCounts};
+use crate::core::mismatches::prefilters::{KnownEditingSites, KnownSites, MismatchesPreFilter};
+use crate::core::mismatches::roi::{ROIData, ROIDataVec, ROIMismatchesVec, ROINucCounts, ROISampleData};
 use crate::core::mismatches::{Batch, Builder};
 use crate::core::refpred::{PredNucleotide, RefEngine, RefEngineResult};
-use crate::core::rpileup::ncounter::NucCounterResult;
+use crate::core::rpileup::ncounter::{NucCounterResult, SampleNucCounts};
 use crate::core::strandutil::Stranded;
 use crate::core::workload::ROI;
 
@@ -19,6 +22,11 @@ pub struct ROIMismatchesBuilder<RR: ROIRetainer, MP: MismatchesPreFilter<ROIData
     refpred: Box<dyn RefEngine>,
     retainer: Option<RR>,
     prefilter: Option<MP>,
+    samples: SampleColumns,
+    known: Option<Arc<KnownSites>>,
+    editingdb: Option<Arc<KnownEditingSites>>,
+    indels: bool,
+    fraccoverage: bool,
 }
 
 impl<'a, RR, MP> ROIMismatchesBuilder<RR, MP>
@@ -27,7 +35,44 @@ where
     MP: MismatchesPreFilter<ROIData>,
 {
     pub fn new(maxsize: usize, refpred: Box<dyn RefEngine>, retainer: Option<RR>, prefilter: Option<MP>) -> Self {
-        Self { buffer: Vec::with_capacity(maxsize), refpred, retainer, prefilter }
+        Self {
+            buffer: Vec::with_capacity(maxsize),
+            refpred,
+            retainer,
+            prefilter,
+            samples: Vec::new().into(),
+            known: None,
+            editingdb: None,
+            indels: false,
+            fraccoverage: false,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: SampleColumns) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    // Known polymorphic positions are excluded from the ROI summaries
+    pub fn with_known_sites(mut self, known: Option<Arc<KnownSites>>) -> Self {
+        self.known = known;
+        self
+    }
+
+    // Known editing sites are counted for each ROI
+    pub fn with_editing_db(mut self, editingdb: Option<Arc<KnownEditingSites>>) -> Self {
+        self.editingdb = editingdb;
+        self
+    }
+
+    pub fn with_indels(mut self, indels: bool) -> Self {
+        self.indels = indels;
+        self
+    }
+
+    pub fn with_fractional_coverage(mut self, fraccoverage: bool) -> Self {
+        self.fraccoverage = fraccoverage;
+        self
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -35,15 +80,46 @@ where
         &self,
         cntstart: Position,
         cnts: &'a [NucCounts],
+        weighted: Option<&'a [FracNucCounts]>,
+        indels: Option<&'a [IndelCounts]>,
+        samples: &[SampleNucCounts<'a>],
+        strand: Strand,
         refpred: &RefEngineResult<'_>,
         roi: &'a ROI,
         coverage: u32,
+        fraccoverage: Option<f32>,
         retain: &mut ROIDataVec,
         other: &mut ROIDataVec,
     ) {
-        // Get mismatches
-        let (prednuc, mismatches, heterozygous) = self.summarize(roi, cntstart, refpred.predicted, cnts);
-        let record = ROIData { roi: roi.into(), coverage, homozygous: prednuc, heterozygous, mismatches };
+        // Get mismatches, weighted by the base-call probability if available
+        let (prednuc, mismatches, heterozygous) = match weighted {
+            Some(weighted) => self.summarize(roi, cntstart, refpred.predicted, weighted),
+            None => self.summarize(roi, cntstart, refpred.predicted, cnts),
+        };
+        let samples = samples
+            .iter()
+            .map(|x| match x.cnts[strand] {
+                Some(cnts) => ROISampleData {
+                    coverage: x.coverage[strand],
+                    mismatches: self.summarize(roi, cntstart, refpred.predicted, cnts).1,
+                },
+                None => ROISampleData::default(),
+            })
+            .collect();
+        let (deletions, insertions) = indels.map_or((0, 0), |x| self.summarize_indels(roi, cntstart, x));
+        let known = self.editingdb.as_ref().map_or(0, |x| x.count(roi.contig(), roi.subintervals()));
+        let record = ROIData {
+            roi: roi.into(),
+            coverage,
+            fraccoverage: fraccoverage.unwrap_or_default(),
+            homozygous: prednuc,
+            heterozygous,
+            known,
+            mismatches,
+            deletions,
+            insertions,
+            samples,
+        };
         if self.retainer.as_ref().map_or(false, |x| x.retained(roi.contig(), &roi.range(), roi.strand(), roi.name())) {
             // Must be retained
             retain.push(record);
@@ -53,38 +129,42 @@ where
         }
     }
 
-    fn summarize(
+    fn summarize<Counts: Copy + Into<FracNucCounts>>(
         &self,
         roi: &'a ROI,
         cntstart: Position,
         prednuc: &'a [PredNucleotide],
-        cnts: &'a [NucCounts],
+        cnts: &'a [Counts],
     ) -> (NucCounts, ROINucCounts, u64) {
         debug_assert!(roi.range().start >= cntstart && roi.range().end <= (cntstart + cnts.len() as u64));
         let mut mismatches = ROINucCounts::zeros();
         let mut nuccnts = NucCounts::zeros();
         let mut heterozygous = 0;
+        let known = self.known.as_ref().map_or(&[][..], |x| x.within(roi.contig(), roi.range()));
 
         for sub in roi.subintervals() {
             let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
-            for (nuc, seq) in zip(&prednuc[idx.clone()], &cnts[idx]) {
+            for (pos, (nuc, seq)) in zip(sub.start.., zip(&prednuc[idx.clone()], &cnts[idx])) {
+                if is_known(known, pos) {
+                    continue;
+                }
                 match nuc {
                     PredNucleotide::Homozygous(nuc) => match nuc {
                         Nucleotide::A => {
                             nuccnts.A += 1;
-                            mismatches.A += seq.into();
+                            mismatches.A += (*seq).into();
                         }
                         Nucleotide::C => {
                             nuccnts.C += 1;
-                            mismatches.C += seq.into();
+                            mismatches.C += (*seq).into();
                         }
                         Nucleotide::G => {
                             nuccnts.G += 1;
-                            mismatches.G += seq.into();
+                            mismatches.G += (*seq).into();
                         }
                         Nucleotide::T => {
                             nuccnts.T += 1;
-                            mismatches.T += seq.into();
+                            mismatches.T += (*seq).into();
                         }
                         // Skip unknown nucleotides
                         Nucleotide::Unknown => {}
@@ -124,6 +204,18 @@ where
         (nuccnts, mismatches, heterozygous)
     }
 
+    fn summarize_indels(&self, roi: &'a ROI, cntstart: Position, indels: &'a [IndelCounts]) -> (u32, u32) {
+        let (mut deletions, mut insertions) = (0, 0);
+        for sub in roi.subintervals() {
+            let idx = (sub.start - cntstart) as usize..(sub.end - cntstart) as usize;
+            for x in &indels[idx] {
+                deletions += x.deletions;
+                insertions += x.inserted();
+            }
+        }
+        (deletions, insertions)
+    }
+
     #[inline]
     fn size_hint(&self, nc: &NucCounterResult<'a, &'a ROI>) -> Stranded<usize> {
         let mut size: Stranded<usize> = Stranded::default();
@@ -149,10 +241,18 @@ where
         let _hint = self.size_hint(&nc);
         let mut items = Stranded::with_fn(|strnd| {
             ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::new())
+                .with_samples(self.samples.clone())
+                .with_indels(self.indels)
+                .with_fractional_coverage(self.fraccoverage)
+                .with_editing_db(self.editingdb.is_some())
             // ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::with_capacity(hint[strnd]))
         });
         let mut retained = Stranded::with_fn(|strnd| {
             ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::new())
+                .with_samples(self.samples.clone())
+                .with_indels(self.indels)
+                .with_fractional_coverage(self.fraccoverage)
+                .with_editing_db(self.editingdb.is_some())
             // ROIMismatchesVec::new(contig.clone(), strnd, ROIDataVec::with_capacity(hint[strnd] / 10))
         });
 
@@ -170,9 +270,14 @@ where
                     self.process(
                         item.range.start,
                         cnts,
+                        item.weighted[strand],
+                        item.indels[strand],
+                        &item.samples,
+                        strand,
                         &refpred,
                         item.data,
                         item.coverage[strand],
+                        item.fraccoverage[strand],
                         &mut retained[strand].data,
                         &mut items[strand].data,
                     );
@@ -26,12 +26,27 @@ pub struct ROIData {
     pub roi: ROIDataRecord,
     // Number of unique fragments covering the ROI
     pub coverage: u32,
+    // Coverage with multi-mapping reads counted fractionally (zero if not tracked)
+    pub fraccoverage: f32,
     // Predicted homozygous nucleotides in the given ROI (after masking)
     pub homozygous: NucCounts,
     // Total heterozygous loci in the ROI (after masking)
     pub heterozygous: u64,
+    // Known editing sites inside the ROI subintervals (zero if not tracked)
+    pub known: u32,
     // Observed mismatches relative to the predicted reference
     pub mismatches: ROINucCounts,
+    // Total deleted bases and insertions in the ROI (zeros if indels are not tracked)
+    pub deletions: u32,
+    pub insertions: u32,
+    // Coverage & mismatches in each sample (empty if samples are not tracked)
+    pub samples: Vec<ROISampleData>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ROISampleData {
+    pub coverage: u32,
+    pub mismatches: ROINucCounts,
 }
 
 impl ROIDataRecordRef<'_> {
@@ -75,9 +90,14 @@ impl From<ROIDataRef<'_>> for ROIData {
         Self {
             roi: x.roi.into(),
             coverage: *x.coverage,
+            fraccoverage: *x.fraccoverage,
             homozygous: *x.homozygous,
             heterozygous: *x.heterozygous,
+            known: *x.known,
             mismatches: *x.mismatches,
+            deletions: *x.deletions,
+            insertions: *x.insertions,
+            samples: x.samples.clone(),
         }
     }
 }
@@ -1,5 +1,5 @@
 pub use builder::ROIMismatchesBuilder;
-pub use data::{ROIData, ROIDataRecord, ROIDataRecordRef, ROIDataRecordVec, ROIDataRef, ROIDataVec};
+pub use data::{ROIData, ROIDataRecord, ROIDataRecordRef, ROIDataRecordVec, ROIDataRef, ROIDataVec, ROISampleData};
 pub use msummary::ROINucCounts;
 
 pub use vec::ROIMismatchesVec;
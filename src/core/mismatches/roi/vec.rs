@@ -3,22 +3,62 @@ use std::io::Write;
 
 use bio_types::strand::Strand;
 use csv::Writer;
-use itertools::Itertools;
+use itertools::{iproduct, Itertools};
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
-use crate::core::mismatches::roi::{ROIDataRef, ROIDataVec};
+use crate::core::mismatches::columns::{self, SampleColumns, PER_SAMPLE};
+use crate::core::mismatches::roi::{ROIDataRef, ROIDataVec, ROISampleData};
 use crate::core::mismatches::MismatchesVec;
 
 pub struct ROIMismatchesVec {
     contig: String,
     trstrand: Strand,
+    samples: SampleColumns,
+    indels: bool,
+    fraccoverage: bool,
+    editingdb: bool,
     pub data: ROIDataVec,
 }
 
 impl ROIMismatchesVec {
     pub fn new(contig: String, trstrand: Strand, data: ROIDataVec) -> Self {
-        Self { contig, trstrand, data }
+        Self {
+            contig,
+            trstrand,
+            samples: Vec::new().into(),
+            indels: false,
+            fraccoverage: false,
+            editingdb: false,
+            data,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: SampleColumns) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_indels(mut self, indels: bool) -> Self {
+        self.indels = indels;
+        self
+    }
+
+    pub fn with_fractional_coverage(mut self, fraccoverage: bool) -> Self {
+        self.fraccoverage = fraccoverage;
+        self
+    }
+
+    pub fn with_editing_db(mut self, editingdb: bool) -> Self {
+        self.editingdb = editingdb;
+        self
+    }
+
+    // Per-sample output columns: coverage, A->A, A->C, ..., T->T
+    pub fn columns(samples: &[String]) -> SampleColumns {
+        let mismatches = iproduct!("ACGT".chars(), "ACGT".chars()).map(|(from, to)| format!("{}->{}", from, to));
+        let suffixes = std::iter::once("coverage".to_owned()).chain(mismatches).collect_vec();
+        columns::columns(samples, &suffixes)
     }
 }
 
@@ -39,7 +79,11 @@ impl MismatchesVec for ROIMismatchesVec {
         self.data.is_empty()
     }
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut Writer<F>) -> csv::Result<()> {
+    fn ugly_in_contig_sort_and_to_csv<F: Write>(
+        items: &[Self],
+        header: bool,
+        writer: &mut Writer<F>,
+    ) -> csv::Result<()> {
         fn pos_then_strand_then_name(first: &SerializeROIRef, second: &SerializeROIRef) -> Ordering {
             let mut ord = first.data.roi.premasked.start.cmp(&second.data.roi.premasked.start);
             if ord.is_eq() {
@@ -56,24 +100,51 @@ impl MismatchesVec for ROIMismatchesVec {
 
         let iter = items
             .iter()
-            .flat_map(|x| x.data.iter().map(|data| SerializeROIRef { contig: &x.contig, strand: x.trstrand, data }))
+            .flat_map(|x| {
+                x.data.iter().map(|data| SerializeROIRef {
+                    contig: &x.contig,
+                    strand: x.trstrand,
+                    indels: x.indels,
+                    fraccoverage: x.fraccoverage,
+                    editingdb: x.editingdb,
+                    data,
+                })
+            })
             .sorted_by(pos_then_strand_then_name);
-        for item in iter {
-            writer.serialize(item)?;
-        }
-        Ok(())
+        let samples = items.first().map_or(&[][..], |x| &x.samples);
+        columns::to_csv(iter, samples, header, writer)
     }
 }
 
 struct SerializeROIRef<'a> {
     contig: &'a str,
     strand: Strand,
+    indels: bool,
+    fraccoverage: bool,
+    editingdb: bool,
     data: ROIDataRef<'a>,
 }
 
+// Per-sample coverage & mismatches: A->A, A->C, ..., T->T for each sample
+struct SerializeSamples<'a>(&'a [ROISampleData]);
+
+impl Serialize for SerializeSamples<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|x| {
+            let mismatches = [x.mismatches.A, x.mismatches.C, x.mismatches.G, x.mismatches.T];
+            (x.coverage, mismatches.map(|cnts| [cnts.A, cnts.C, cnts.G, cnts.T]))
+        }))
+    }
+}
+
 impl Serialize for SerializeROIRef<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("ROIMismatches", 29)?;
+        let len = 29
+            + self.fraccoverage as usize
+            + self.editingdb as usize
+            + 2 * self.indels as usize
+            + !self.data.samples.is_empty() as usize;
+        let mut state = serializer.serialize_struct("ROIMismatches", len)?;
         state.serialize_field("contig", &self.contig)?;
         state.serialize_field("start", &self.data.roi.premasked.start)?;
         state.serialize_field("end", &self.data.roi.premasked.end)?;
@@ -81,8 +152,14 @@ impl Serialize for SerializeROIRef<'_> {
         state.serialize_field("name", &self.data.roi.name)?;
         state.serialize_field("trstrand", &self.strand.strand_symbol())?;
         state.serialize_field("coverage", &self.data.coverage)?;
+        if self.fraccoverage {
+            state.serialize_field("fraccoverage", &self.data.fraccoverage)?;
+        }
         state.serialize_field("nucmasked", &self.data.roi.nucmasked())?;
         state.serialize_field("heterozygous", &self.data.heterozygous)?;
+        if self.editingdb {
+            state.serialize_field("known", &self.data.known)?;
+        }
         state.serialize_field("#A", &self.data.homozygous.A)?;
         state.serialize_field("A->A", &self.data.mismatches.A.A)?;
         state.serialize_field("A->C", &self.data.mismatches.A.C)?;
@@ -103,6 +180,13 @@ impl Serialize for SerializeROIRef<'_> {
         state.serialize_field("T->C", &self.data.mismatches.T.C)?;
         state.serialize_field("T->G", &self.data.mismatches.T.G)?;
         state.serialize_field("T->T", &self.data.mismatches.T.T)?;
+        if self.indels {
+            state.serialize_field("del", &self.data.deletions)?;
+            state.serialize_field("ins", &self.data.insertions)?;
+        }
+        if !self.data.samples.is_empty() {
+            state.serialize_field(PER_SAMPLE, &SerializeSamples(self.data.samples))?;
+        }
         state.end()
     }
 }
@@ -116,6 +200,16 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn columns() {
+        let samples = vec!["s1".to_owned(), "s2".to_owned()];
+        let columns = ROIMismatchesVec::columns(&samples);
+        assert_eq!(columns.len(), 34);
+        assert_eq!(columns[..3], ["s1:coverage", "s1:A->A", "s1:A->C"]);
+        assert_eq!(columns[16..18], ["s1:T->T", "s2:coverage"]);
+        assert_eq!(columns[33], "s2:T->T");
+    }
+
     #[test]
     fn roi() {
         let record = ROIDataRecordRef {
@@ -134,13 +228,25 @@ mod test {
         let roi = ROIDataRef {
             roi: record,
             coverage: &13,
+            fraccoverage: &0.0,
             homozygous: &NucCounts::new(1, 12, 3, 5),
             heterozygous: &13,
+            known: &0,
             mismatches: &mm,
+            deletions: &0,
+            insertions: &0,
+            samples: &vec![],
         };
 
         assert_ser_tokens(
-            &SerializeROIRef { contig: "chr1", strand: Strand::Unknown, data: roi },
+            &SerializeROIRef {
+                contig: "chr1",
+                strand: Strand::Unknown,
+                indels: false,
+                fraccoverage: false,
+                editingdb: false,
+                data: roi,
+            },
             &[
                 Token::Struct { name: "ROIMismatches", len: 29 },
                 Token::Str("contig"),
@@ -1,16 +1,19 @@
 use std::ops::Range;
+use std::sync::Arc;
 
 use bio_types::genome::Position;
 use bio_types::strand::Strand;
-use itertools::izip;
+use itertools::{izip, Itertools};
 
-use crate::core::dna::NucCounts;
+use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, ReadStats};
+use crate::core::mismatches::columns::SampleColumns;
+use crate::core::mismatches::prefilters::known::is_known;
 use crate::core::mismatches::prefilters::retain::SitesRetainer;
-use crate::core::mismatches::prefilters::MismatchesPreFilter;
+use crate::core::mismatches::prefilters::{KnownEditingSites, KnownSites, MismatchesPreFilter};
 use crate::core::mismatches::site::{SiteData, SiteDataVec, SiteMismatchesVec};
 use crate::core::mismatches::Batch;
 use crate::core::refpred::{RefEngine, RefEngineResult};
-use crate::core::rpileup::ncounter::NucCounterResult;
+use crate::core::rpileup::ncounter::{CellNucCounts, NucCounterResult};
 use crate::core::strandutil::Stranded;
 
 use super::super::Builder;
@@ -21,6 +24,13 @@ pub struct SiteMismatchesBuilder<SR, MP> {
     refpred: Box<dyn RefEngine>,
     retainer: Option<SR>,
     prefilter: Option<MP>,
+    samples: SampleColumns,
+    known: Option<Arc<KnownSites>>,
+    editingdb: Option<Arc<KnownEditingSites>>,
+    indels: bool,
+    readstats: bool,
+    weighted: bool,
+    genoqual: bool,
 }
 
 impl<'a, SR, MP> SiteMismatchesBuilder<SR, MP>
@@ -29,14 +39,72 @@ where
     MP: MismatchesPreFilter<SiteData>,
 {
     pub fn new(maxsize: usize, refpred: Box<dyn RefEngine>, retainer: Option<SR>, prefilter: Option<MP>) -> Self {
-        Self { buffer: Vec::with_capacity(maxsize), refpred, retainer, prefilter }
+        Self {
+            buffer: Vec::with_capacity(maxsize),
+            refpred,
+            retainer,
+            prefilter,
+            samples: Vec::new().into(),
+            known: None,
+            editingdb: None,
+            indels: false,
+            readstats: false,
+            weighted: false,
+            genoqual: false,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: SampleColumns) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    // Known polymorphic positions are skipped unless they must be retained
+    pub fn with_known_sites(mut self, known: Option<Arc<KnownSites>>) -> Self {
+        self.known = known;
+        self
     }
 
+    // Known editing sites are annotated with their database IDs
+    pub fn with_editing_db(mut self, editingdb: Option<Arc<KnownEditingSites>>) -> Self {
+        self.editingdb = editingdb;
+        self
+    }
+
+    pub fn with_indels(mut self, indels: bool) -> Self {
+        self.indels = indels;
+        self
+    }
+
+    pub fn with_read_stats(mut self, readstats: bool) -> Self {
+        self.readstats = readstats;
+        self
+    }
+
+    pub fn with_weighted(mut self, weighted: bool) -> Self {
+        self.weighted = weighted;
+        self
+    }
+
+    // Report the quality of genotypes predicted by the reference engine
+    pub fn with_genotype_quality(mut self, genoqual: bool) -> Self {
+        self.genoqual = genoqual;
+        self
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn process(
         &self,
         retained: &[Range<Position>],
+        known: &[u32],
+        editingdb: (&[u32], &[String]),
         cntrange: Range<Position>,
         cnts: &[NucCounts],
+        weighted: Option<&[FracNucCounts]>,
+        indels: Option<&[IndelCounts]>,
+        readstats: Option<&[ReadStats]>,
+        samples: &[Option<&[NucCounts]>],
+        cells: &[CellNucCounts],
         refngn: &RefEngineResult,
         retbuilder: &mut SiteDataVec,
         othbuilder: &mut SiteDataVec,
@@ -45,6 +113,11 @@ where
         debug_assert_eq!(cnts.len(), refngn.reference.len());
         debug_assert_eq!(cnts.len(), refngn.predicted.len());
         debug_assert!(retained.iter().all(|x| cntrange.contains(&x.start) && cntrange.contains(&x.end)));
+        debug_assert!(weighted.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(indels.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(readstats.map_or(true, |x| x.len() == cnts.len()));
+        debug_assert!(samples.iter().flatten().all(|x| x.len() == cnts.len()));
+        debug_assert!(cells.windows(2).all(|x| x[0].offset <= x[1].offset));
 
         let retsize = retained.iter().map(|x| x.end - x.start).sum::<Position>() as usize;
         debug_assert!(retsize <= cnts.len());
@@ -52,19 +125,52 @@ where
         // Retain iterator
         let mut reiter = retained.iter();
         let mut retrange = reiter.next();
+        // Cells at the current position and beyond
+        let mut cells = cells;
 
-        for (pos, (&cnt, &refnuc, &prednuc)) in izip!(cnts, refngn.reference, refngn.predicted).enumerate() {
-            let pos = pos as Position + cntrange.start;
+        for (ind, (&cnt, &refnuc, &prednuc)) in izip!(cnts, refngn.reference, refngn.predicted).enumerate() {
+            let pos = ind as Position + cntrange.start;
             // Do we need to move the iterator?
             if retrange.map_or(false, |x| x.end <= pos) {
                 retrange = reiter.next();
             }
 
+            // Cells must be consumed even for skipped positions
+            let sitecells = cells.iter().take_while(|x| x.offset as usize == ind).count();
+            let (sitecells, rest) = cells.split_at(sitecells);
+            cells = rest;
+
             // Are we inside the retained region?
-            let data = SiteData { pos, refnuc, prednuc, sequenced: cnt };
-            if retrange.map_or(false, |x| x.contains(&pos)) {
+            let isretained = retrange.map_or(false, |x| x.contains(&pos));
+            if !isretained && is_known(known, pos) {
+                continue;
+            }
+            let indels = indels.map(|x| x[ind].clone()).unwrap_or_default();
+            let readstats = readstats.map(|x| x[ind]).unwrap_or_default();
+            let dbid = editingdb.0.binary_search(&(pos as u32)).ok().map(|x| editingdb.1[x].clone());
+            let mut data = SiteData {
+                pos,
+                refnuc,
+                prednuc,
+                genoqual: refngn.quality.map_or(0, |x| x[ind]),
+                sequenced: cnt,
+                weighted: weighted.map(|x| x[ind]).unwrap_or_default(),
+                indels,
+                readstats,
+                samples: Vec::new(),
+                cells: Vec::new(),
+                known: dbid,
+            };
+            if !isretained && !self.prefilter.as_ref().map_or(true, |x| x.is_ok(&data)) {
+                continue;
+            }
+
+            // Per-sample and per-cell counts are collected only for the reported sites
+            data.samples = samples.iter().map(|x| x.map_or(NucCounts::zeros(), |x| x[ind])).collect();
+            data.cells = sitecells.iter().map(|x| (x.cell, x.cnts)).collect();
+            if isretained {
                 retbuilder.push(data);
-            } else if self.prefilter.as_ref().map_or(true, |x| x.is_ok(&data)) {
+            } else {
                 othbuilder.push(data);
             }
         }
@@ -98,9 +204,21 @@ where
         let mut items = Stranded::with_fn(|strnd| {
             // SiteMismatchesVec::new(contig.to_owned(), strnd, SiteDataVec::with_capacity(hint[strnd]))
             SiteMismatchesVec::new(contig.to_owned(), strnd, SiteDataVec::new())
+                .with_samples(self.samples.clone())
+                .with_indels(self.indels)
+                .with_read_stats(self.readstats)
+                .with_weighted(self.weighted)
+                .with_editing_db(self.editingdb.is_some())
+                .with_genotype_quality(self.genoqual)
         });
         let mut retained = Stranded::with_fn(|strnd| {
             SiteMismatchesVec::new(contig.to_owned(), strnd, SiteDataVec::new())
+                .with_samples(self.samples.clone())
+                .with_indels(self.indels)
+                .with_read_stats(self.readstats)
+                .with_weighted(self.weighted)
+                .with_editing_db(self.editingdb.is_some())
+                .with_genotype_quality(self.genoqual)
             // SiteMismatchesVec::new(contig.to_owned(), strnd, SiteDataVec::with_capacity(hint[strnd] / 10))
         });
 
@@ -112,13 +230,24 @@ where
 
             // Find loci that must be retained
             let mustloci = self.retainer.as_ref().map_or(vec![], |r| r.retained(contig, item.range.clone()));
+            let known = self.known.as_ref().map_or(&[][..], |x| x.within(contig, item.range.clone()));
+            let editingdb =
+                self.editingdb.as_ref().map_or((&[][..], &[][..]), |x| x.within(contig, item.range.clone()));
             for strand in [Strand::Forward, Strand::Reverse, Strand::Unknown] {
                 if let Some(cnt) = item.cnts[strand] {
                     // debug_assert!(item.coverage[strand] > 0);
+                    let samples = item.samples.iter().map(|x| x.cnts[strand]).collect_vec();
                     self.process(
                         &mustloci,
+                        known,
+                        editingdb,
                         item.range.clone(),
                         cnt,
+                        item.weighted[strand],
+                        item.indels[strand],
+                        item.readstats[strand],
+                        &samples,
+                        item.cells[strand],
                         &reference,
                         &mut retained[strand].data,
                         &mut items[strand].data,
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+use bio_types::strand::Strand;
+
+use crate::core::dna::NucCounts;
+use crate::core::io::mtx;
+use crate::core::refpred::PredNucleotide;
+
+// Sparse sites x cells matrices with reference and alternative counts.
+// Rows are appended in the output order of sites and annotated in a separate table, columns are cell indices.
+// Rows of heterozygous sites are left empty: there is no single reference nucleotide to split the counts.
+#[derive(Clone, Debug, Default)]
+pub struct CellMatrices {
+    contigs: Vec<String>,
+    // Row annotation: contig index, position, transcription strand, predicted nucleotide
+    sites: Vec<(u32, u64, Strand, PredNucleotide)>,
+    refcnts: Vec<(u32, u32, u32)>,
+    altcnts: Vec<(u32, u32, u32)>,
+}
+
+impl CellMatrices {
+    pub fn push(
+        &mut self,
+        contig: &str,
+        pos: u64,
+        trstrand: Strand,
+        prednuc: PredNucleotide,
+        cells: &[(u32, NucCounts)],
+    ) {
+        if self.contigs.last().map(String::as_str) != Some(contig) {
+            self.contigs.push(contig.to_owned());
+        }
+        let row = self.sites.len() as u32;
+        self.sites.push((self.contigs.len() as u32 - 1, pos, trstrand, prednuc));
+
+        let refnuc = match prednuc {
+            PredNucleotide::Homozygous(nuc) => nuc,
+            PredNucleotide::Heterozygous(_) => return,
+        };
+        for (cell, cnts) in cells {
+            let alt = cnts.mismatches(refnuc);
+            let reference = cnts.coverage() - alt;
+            if reference > 0 {
+                self.refcnts.push((row, *cell, reference));
+            }
+            if alt > 0 {
+                self.altcnts.push((row, *cell, alt));
+            }
+        }
+    }
+
+    pub fn save(&self, prefix: &str, cells: u32) -> Result<()> {
+        for (suffix, entries) in [("ref.mtx", &self.refcnts), ("alt.mtx", &self.altcnts)] {
+            let path = format!("{}.{}", prefix, suffix);
+            let saveto = BufWriter::new(File::create(Path::new(&path))?);
+            mtx::write(saveto, self.sites.len() as u32, cells, entries)?;
+        }
+
+        let path = format!("{}.sites.tsv", prefix);
+        let mut saveto = BufWriter::new(File::create(Path::new(&path))?);
+        for (contig, pos, trstrand, prednuc) in &self.sites {
+            let contig = &self.contigs[*contig as usize];
+            writeln!(saveto, "{}\t{}\t{}\t{}", contig, pos, trstrand.strand_symbol(), prednuc.symbol())?;
+        }
+        saveto.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::core::dna::Nucleotide;
+    use crate::core::refpred::PredNucleotide::{Heterozygous, Homozygous};
+
+    use super::*;
+
+    fn filled() -> CellMatrices {
+        let (fwd, rev) = (Strand::Forward, Strand::Reverse);
+        let mut matrices = CellMatrices::default();
+        matrices.push(
+            "1",
+            10,
+            fwd,
+            Homozygous(Nucleotide::A),
+            &[(0, NucCounts::new(3, 0, 2, 0)), (4, NucCounts::G(1))],
+        );
+        matrices.push("1", 10, rev, Homozygous(Nucleotide::C), &[]);
+        matrices.push("1", 12, fwd, Homozygous(Nucleotide::Unknown), &[(1, NucCounts::new(1, 1, 0, 0))]);
+        // Heterozygous sites are skipped
+        matrices.push("2", 3, fwd, Heterozygous((Nucleotide::A, Nucleotide::G)), &[(2, NucCounts::new(5, 0, 4, 0))]);
+        // Counts are split by the predicted nucleotide, not the assembly one
+        matrices.push("3", 7, rev, Homozygous(Nucleotide::G), &[(3, NucCounts::new(1, 0, 6, 0))]);
+        matrices
+    }
+
+    #[test]
+    fn push() {
+        let matrices = filled();
+        assert_eq!(matrices.contigs, ["1", "2", "3"]);
+        assert_eq!(
+            matrices.sites.iter().map(|x| (x.0, x.1)).collect::<Vec<_>>(),
+            [(0, 10), (0, 10), (0, 12), (1, 3), (2, 7)]
+        );
+        assert_eq!(matrices.refcnts, vec![(0, 0, 3), (4, 3, 6)]);
+        assert_eq!(matrices.altcnts, vec![(0, 0, 2), (0, 4, 1), (2, 1, 2), (4, 3, 1)]);
+    }
+
+    #[test]
+    fn save() {
+        let tmp = TempDir::new().unwrap();
+        let prefix = tmp.path().join("cells");
+        let prefix = prefix.to_str().unwrap();
+        filled().save(prefix, 5).unwrap();
+
+        let sites = std::fs::read_to_string(format!("{}.sites.tsv", prefix)).unwrap();
+        assert_eq!(sites, "1\t10\t+\tA\n1\t10\t-\tC\n1\t12\t+\tN\n2\t3\t+\tA/G\n3\t7\t-\tG\n");
+        let refcnts = std::fs::read_to_string(format!("{}.ref.mtx", prefix)).unwrap();
+        assert_eq!(refcnts.lines().nth(1), Some("5 5 2"));
+    }
+}
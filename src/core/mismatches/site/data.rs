@@ -1,8 +1,7 @@
 use bio_types::genome::Position;
 use soa_derive::StructOfArray;
 
-use crate::core::dna::NucCounts;
-use crate::core::dna::Nucleotide;
+use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, Nucleotide, ReadStats};
 use crate::core::refpred::PredNucleotide;
 
 #[derive(Clone, Debug, Default, StructOfArray)]
@@ -14,12 +13,38 @@ pub struct SiteData {
     pub refnuc: Nucleotide,
     // Auto corrected reference nucleotide
     pub prednuc: PredNucleotide,
+    // Phred-scaled quality of the predicted genotype (zero if not estimated)
+    pub genoqual: u8,
     // Sequenced nucleotides
     pub sequenced: NucCounts,
+    // Sequenced nucleotides weighted by the base-call probability (empty if weights are not tracked)
+    pub weighted: FracNucCounts,
+    // Insertions & deletions (empty if indels are not tracked)
+    pub indels: IndelCounts,
+    // Read strand & position statistics (empty if they are not tracked)
+    pub readstats: ReadStats,
+    // Sequenced nucleotides in each sample (empty if samples are not tracked)
+    pub samples: Vec<NucCounts>,
+    // Non-zero sequenced nucleotides for each cell index (empty if cells are not tracked)
+    pub cells: Vec<(u32, NucCounts)>,
+    // Database ID of the known editing site (None if the site is unknown or known sites are not tracked)
+    pub known: Option<String>,
 }
 
 impl From<SiteDataRef<'_>> for SiteData {
     fn from(x: SiteDataRef<'_>) -> Self {
-        Self { pos: *x.pos, refnuc: *x.refnuc, prednuc: *x.prednuc, sequenced: *x.sequenced }
+        Self {
+            pos: *x.pos,
+            refnuc: *x.refnuc,
+            prednuc: *x.prednuc,
+            genoqual: *x.genoqual,
+            sequenced: *x.sequenced,
+            weighted: *x.weighted,
+            indels: x.indels.clone(),
+            readstats: *x.readstats,
+            samples: x.samples.clone(),
+            cells: x.cells.clone(),
+            known: x.known.clone(),
+        }
     }
 }
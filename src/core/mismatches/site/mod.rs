@@ -1,7 +1,9 @@
 pub use builder::SiteMismatchesBuilder;
+pub use cells::CellMatrices;
 pub use data::{SiteData, SiteDataRef, SiteDataVec};
 pub use vec::SiteMismatchesVec;
 
 mod builder;
+mod cells;
 mod data;
 mod vec;
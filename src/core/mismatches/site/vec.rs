@@ -7,8 +7,11 @@ use itertools::Itertools;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
+use crate::core::dna::NucCounts;
+use crate::core::mismatches::columns::{self, SampleColumns, PER_SAMPLE};
 use crate::core::mismatches::site::SiteDataRef;
 use crate::core::mismatches::MismatchesVec;
+use crate::core::refpred::PredNucleotide;
 
 use super::data::SiteDataVec;
 
@@ -16,12 +19,79 @@ use super::data::SiteDataVec;
 pub struct SiteMismatchesVec {
     contig: String,
     trstrand: Strand,
+    samples: SampleColumns,
+    indels: bool,
+    readstats: bool,
+    weighted: bool,
+    editingdb: bool,
+    genoqual: bool,
     pub data: SiteDataVec,
 }
 
 impl SiteMismatchesVec {
     pub fn new(contig: String, trstrand: Strand, data: SiteDataVec) -> Self {
-        Self { contig, trstrand, data }
+        Self {
+            contig,
+            trstrand,
+            samples: Vec::new().into(),
+            indels: false,
+            readstats: false,
+            weighted: false,
+            editingdb: false,
+            genoqual: false,
+            data,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: SampleColumns) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_indels(mut self, indels: bool) -> Self {
+        self.indels = indels;
+        self
+    }
+
+    pub fn with_read_stats(mut self, readstats: bool) -> Self {
+        self.readstats = readstats;
+        self
+    }
+
+    pub fn with_weighted(mut self, weighted: bool) -> Self {
+        self.weighted = weighted;
+        self
+    }
+
+    pub fn with_editing_db(mut self, editingdb: bool) -> Self {
+        self.editingdb = editingdb;
+        self
+    }
+
+    pub fn with_genotype_quality(mut self, genoqual: bool) -> Self {
+        self.genoqual = genoqual;
+        self
+    }
+
+    // Per-sample output columns: A, C, G, T
+    pub fn columns(samples: &[String]) -> SampleColumns {
+        columns::columns(samples, &["A", "C", "G", "T"])
+    }
+
+    // Sites of a single contig in the output order: by position, then by strand
+    pub fn in_contig_sorted(items: &[Self]) -> impl Iterator<Item = (&Self, SiteDataRef<'_>)> {
+        fn pos_then_strand(
+            first: &(&SiteMismatchesVec, SiteDataRef),
+            second: &(&SiteMismatchesVec, SiteDataRef),
+        ) -> Ordering {
+            let mut ord = first.1.pos.cmp(second.1.pos);
+            if ord.is_eq() {
+                ord = first.0.trstrand.strand_symbol().cmp(second.0.trstrand.strand_symbol());
+            }
+            ord
+        }
+
+        items.iter().flat_map(|x| x.data.iter().map(move |data| (x, data))).sorted_by(pos_then_strand)
     }
 }
 
@@ -42,44 +112,100 @@ impl MismatchesVec for SiteMismatchesVec {
         self.data.is_empty()
     }
 
-    fn ugly_in_contig_sort_and_to_csv<F: Write>(items: Vec<Self>, writer: &mut Writer<F>) -> csv::Result<()> {
-        fn pos_then_strand(first: &SerializeSiteRef, second: &SerializeSiteRef) -> Ordering {
-            let mut ord = first.data.pos.cmp(second.data.pos);
-            if ord.is_eq() {
-                ord = first.strand.strand_symbol().cmp(second.strand.strand_symbol());
-            }
-            ord
-        }
-
-        let iter = items
-            .iter()
-            .flat_map(|x| x.data.iter().map(|data| SerializeSiteRef { contig: &x.contig, strand: x.trstrand, data }))
-            .sorted_by(pos_then_strand);
-        for item in iter {
-            writer.serialize(item)?;
-        }
-        Ok(())
+    fn ugly_in_contig_sort_and_to_csv<F: Write>(
+        items: &[Self],
+        header: bool,
+        writer: &mut Writer<F>,
+    ) -> csv::Result<()> {
+        let iter = Self::in_contig_sorted(items).map(|(x, data)| SerializeSiteRef {
+            contig: &x.contig,
+            strand: x.trstrand,
+            indels: x.indels,
+            readstats: x.readstats,
+            weighted: x.weighted,
+            editingdb: x.editingdb,
+            genoqual: x.genoqual,
+            data,
+        });
+        let samples = items.first().map_or(&[][..], |x| &x.samples);
+        columns::to_csv(iter, samples, header, writer)
     }
 }
 
 struct SerializeSiteRef<'a> {
     contig: &'a str,
     strand: Strand,
+    indels: bool,
+    readstats: bool,
+    weighted: bool,
+    editingdb: bool,
+    genoqual: bool,
     data: SiteDataRef<'a>,
 }
 
+// Per-sample counts: A, C, G, T for each sample
+struct SerializeSamples<'a>(&'a [NucCounts]);
+
+impl Serialize for SerializeSamples<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|x| [x.A, x.C, x.G, x.T]))
+    }
+}
+
 impl Serialize for SerializeSiteRef<'_> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut state = serializer.serialize_struct("SiteMismatches", 9)?;
+        let len = 9
+            + 4 * self.weighted as usize
+            + 3 * self.indels as usize
+            + 5 * self.readstats as usize
+            + 2 * self.editingdb as usize
+            + self.genoqual as usize
+            + !self.data.samples.is_empty() as usize;
+        let mut state = serializer.serialize_struct("SiteMismatches", len)?;
         state.serialize_field("contig", self.contig)?;
         state.serialize_field("pos", &self.data.pos)?;
         state.serialize_field("trstrand", self.strand.strand_symbol())?;
         state.serialize_field("refnuc", self.data.refnuc.symbol())?;
         state.serialize_field("prednuc", self.data.prednuc.symbol())?;
+        if self.genoqual {
+            state.serialize_field("gq", self.data.genoqual)?;
+        }
         state.serialize_field("A", &self.data.sequenced.A)?;
         state.serialize_field("C", &self.data.sequenced.C)?;
         state.serialize_field("G", &self.data.sequenced.G)?;
         state.serialize_field("T", &self.data.sequenced.T)?;
+        if self.weighted {
+            state.serialize_field("wA", &self.data.weighted.A)?;
+            state.serialize_field("wC", &self.data.weighted.C)?;
+            state.serialize_field("wG", &self.data.weighted.G)?;
+            state.serialize_field("wT", &self.data.weighted.T)?;
+        }
+        if self.indels {
+            state.serialize_field("del", &self.data.indels.deletions)?;
+            state.serialize_field("ins", &self.data.indels.inserted())?;
+            state.serialize_field("insseq", &self.data.indels.inserted_sequences())?;
+        }
+        if self.readstats {
+            // Mismatches are relative to the predicted nucleotide, heterozygous sites are left empty
+            let stats = self.data.readstats;
+            let refnuc = match *self.data.prednuc {
+                PredNucleotide::Homozygous(nuc) => Some(nuc),
+                PredNucleotide::Heterozygous(_) => None,
+            };
+            let mismatches = refnuc.map(|x| stats.mismatches(x));
+            state.serialize_field("mmfwd", &mismatches.map(|x| x.0))?;
+            state.serialize_field("mmrev", &mismatches.map(|x| x.1))?;
+            state.serialize_field("mmdist", &refnuc.and_then(|x| stats.mean_distance(x).0))?;
+            state.serialize_field("sbpval", &refnuc.map(|x| stats.strand_bias(x)))?;
+            state.serialize_field("posbias", &refnuc.and_then(|x| stats.position_bias(x)))?;
+        }
+        if self.editingdb {
+            state.serialize_field("in_db", &self.data.known.is_some())?;
+            state.serialize_field("db_id", self.data.known.as_deref().unwrap_or(""))?;
+        }
+        if !self.data.samples.is_empty() {
+            state.serialize_field(PER_SAMPLE, &SerializeSamples(self.data.samples))?;
+        }
         state.end()
     }
 }
@@ -88,21 +214,46 @@ impl Serialize for SerializeSiteRef<'_> {
 mod test {
     use serde_test::{assert_ser_tokens, Token};
 
-    use crate::core::dna::{NucCounts, Nucleotide};
+    use crate::core::dna::ncounts::InnerNucCounts;
+    use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, Nucleotide, ReadStats};
     use crate::core::refpred::PredNucleotide;
 
     use super::*;
 
+    #[test]
+    fn columns() {
+        let samples = vec!["s1".to_owned(), "s2".to_owned()];
+        let columns = SiteMismatchesVec::columns(&samples);
+        assert_eq!(&*columns, ["s1:A", "s1:C", "s1:G", "s1:T", "s2:A", "s2:C", "s2:G", "s2:T"]);
+        assert!(SiteMismatchesVec::columns(&[]).is_empty());
+    }
+
     #[test]
     fn loci() {
         let data = SiteDataRef {
             pos: &13,
             refnuc: &Nucleotide::A,
             prednuc: &PredNucleotide::Heterozygous((Nucleotide::T, Nucleotide::G)),
+            genoqual: &0,
             sequenced: &NucCounts::new(1, 2, 3, 4),
+            weighted: &FracNucCounts::zeros(),
+            indels: &IndelCounts::default(),
+            readstats: &ReadStats::default(),
+            samples: &vec![],
+            cells: &vec![],
+            known: &None,
         };
         assert_ser_tokens(
-            &SerializeSiteRef { contig: "MySuperContig", strand: Strand::Unknown, data },
+            &SerializeSiteRef {
+                contig: "MySuperContig",
+                strand: Strand::Unknown,
+                indels: false,
+                readstats: false,
+                weighted: false,
+                editingdb: false,
+                genoqual: false,
+                data,
+            },
             &[
                 Token::Struct { name: "SiteMismatches", len: 9 },
                 Token::Str("contig"),
@@ -127,4 +278,377 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn samples() {
+        let data = SiteDataRef {
+            pos: &1,
+            refnuc: &Nucleotide::C,
+            prednuc: &PredNucleotide::Homozygous(Nucleotide::C),
+            genoqual: &0,
+            sequenced: &NucCounts::new(1, 2, 0, 0),
+            weighted: &FracNucCounts::zeros(),
+            indels: &IndelCounts::default(),
+            readstats: &ReadStats::default(),
+            samples: &vec![NucCounts::A(1), NucCounts::C(2)],
+            cells: &vec![],
+            known: &None,
+        };
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chr1",
+                strand: Strand::Forward,
+                indels: false,
+                readstats: false,
+                weighted: false,
+                editingdb: false,
+                genoqual: false,
+                data,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 10 },
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("pos"),
+                Token::U64(1),
+                Token::Str("trstrand"),
+                Token::Str("+"),
+                Token::Str("refnuc"),
+                Token::Str("C"),
+                Token::Str("prednuc"),
+                Token::Str("C"),
+                Token::Str("A"),
+                Token::U32(1),
+                Token::Str("C"),
+                Token::U32(2),
+                Token::Str("G"),
+                Token::U32(0),
+                Token::Str("T"),
+                Token::U32(0),
+                Token::Str("samples"),
+                Token::Seq { len: Some(2) },
+                Token::Tuple { len: 4 },
+                Token::U32(1),
+                Token::U32(0),
+                Token::U32(0),
+                Token::U32(0),
+                Token::TupleEnd,
+                Token::Tuple { len: 4 },
+                Token::U32(0),
+                Token::U32(2),
+                Token::U32(0),
+                Token::U32(0),
+                Token::TupleEnd,
+                Token::SeqEnd,
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn indels() {
+        let data = SiteDataRef {
+            pos: &7,
+            refnuc: &Nucleotide::G,
+            prednuc: &PredNucleotide::Homozygous(Nucleotide::G),
+            genoqual: &0,
+            sequenced: &NucCounts::G(5),
+            weighted: &FracNucCounts::zeros(),
+            indels: &IndelCounts { deletions: 2, insertions: vec![(b"A".to_vec(), 1), (b"TT".to_vec(), 3)] },
+            readstats: &ReadStats::default(),
+            samples: &vec![],
+            cells: &vec![],
+            known: &None,
+        };
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chrM",
+                strand: Strand::Reverse,
+                indels: true,
+                readstats: false,
+                weighted: false,
+                editingdb: false,
+                genoqual: false,
+                data,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 12 },
+                Token::Str("contig"),
+                Token::Str("chrM"),
+                Token::Str("pos"),
+                Token::U64(7),
+                Token::Str("trstrand"),
+                Token::Str("-"),
+                Token::Str("refnuc"),
+                Token::Str("G"),
+                Token::Str("prednuc"),
+                Token::Str("G"),
+                Token::Str("A"),
+                Token::U32(0),
+                Token::Str("C"),
+                Token::U32(0),
+                Token::Str("G"),
+                Token::U32(5),
+                Token::Str("T"),
+                Token::U32(0),
+                Token::Str("del"),
+                Token::U32(2),
+                Token::Str("ins"),
+                Token::U32(4),
+                Token::Str("insseq"),
+                Token::Str("TT:3;A:1"),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn readstats() {
+        let mut readstats = ReadStats::default();
+        readstats.forward = NucCounts::new(2, 0, 2, 0);
+        readstats.reverse = NucCounts::new(2, 0, 2, 0);
+        readstats.distance = InnerNucCounts::new(40, 0, 8, 0);
+
+        for (prednuc, symbol, mmdist, sbpval, posbias) in [
+            (PredNucleotide::Homozygous(Nucleotide::A), "A", Some(2.0), Some(1.0), Some(0.2)),
+            // Statistics are computed against the predicted nucleotide
+            (PredNucleotide::Homozygous(Nucleotide::G), "G", Some(10.0), Some(1.0), Some(5.0)),
+            // And are not available for heterozygous sites
+            (PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G)), "A/G", None, None, None),
+        ] {
+            let data = SiteDataRef {
+                pos: &3,
+                refnuc: &Nucleotide::A,
+                prednuc: &prednuc,
+                genoqual: &0,
+                sequenced: &NucCounts::new(4, 0, 4, 0),
+                weighted: &FracNucCounts::zeros(),
+                indels: &IndelCounts::default(),
+                readstats: &readstats,
+                samples: &vec![],
+                cells: &vec![],
+                known: &None,
+            };
+
+            let mut expected = vec![
+                Token::Struct { name: "SiteMismatches", len: 14 },
+                Token::Str("contig"),
+                Token::Str("chr2"),
+                Token::Str("pos"),
+                Token::U64(3),
+                Token::Str("trstrand"),
+                Token::Str("+"),
+                Token::Str("refnuc"),
+                Token::Str("A"),
+                Token::Str("prednuc"),
+                Token::Str(symbol),
+                Token::Str("A"),
+                Token::U32(4),
+                Token::Str("C"),
+                Token::U32(0),
+                Token::Str("G"),
+                Token::U32(4),
+                Token::Str("T"),
+                Token::U32(0),
+            ];
+            let homozygous = mmdist.is_some();
+            for (column, value) in [
+                ("mmfwd", homozygous.then(|| Token::U32(2))),
+                ("mmrev", homozygous.then(|| Token::U32(2))),
+                ("mmdist", mmdist.map(Token::F32)),
+                ("sbpval", sbpval.map(Token::F64)),
+                ("posbias", posbias.map(Token::F32)),
+            ] {
+                expected.push(Token::Str(column));
+                match value {
+                    Some(value) => expected.extend([Token::Some, value]),
+                    None => expected.push(Token::None),
+                }
+            }
+            expected.push(Token::StructEnd);
+
+            assert_ser_tokens(
+                &SerializeSiteRef {
+                    contig: "chr2",
+                    strand: Strand::Forward,
+                    indels: false,
+                    readstats: true,
+                    weighted: false,
+                    editingdb: false,
+                    genoqual: false,
+                    data,
+                },
+                &expected,
+            );
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        let data = SiteDataRef {
+            pos: &11,
+            refnuc: &Nucleotide::T,
+            prednuc: &PredNucleotide::Homozygous(Nucleotide::T),
+            genoqual: &0,
+            sequenced: &NucCounts::new(0, 2, 0, 3),
+            weighted: &FracNucCounts::new(0.0, 1.5, 0.0, 2.75),
+            indels: &IndelCounts::default(),
+            readstats: &ReadStats::default(),
+            samples: &vec![],
+            cells: &vec![],
+            known: &None,
+        };
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chr3",
+                strand: Strand::Unknown,
+                indels: false,
+                readstats: false,
+                weighted: true,
+                editingdb: false,
+                genoqual: false,
+                data,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 13 },
+                Token::Str("contig"),
+                Token::Str("chr3"),
+                Token::Str("pos"),
+                Token::U64(11),
+                Token::Str("trstrand"),
+                Token::Str("."),
+                Token::Str("refnuc"),
+                Token::Str("T"),
+                Token::Str("prednuc"),
+                Token::Str("T"),
+                Token::Str("A"),
+                Token::U32(0),
+                Token::Str("C"),
+                Token::U32(2),
+                Token::Str("G"),
+                Token::U32(0),
+                Token::Str("T"),
+                Token::U32(3),
+                Token::Str("wA"),
+                Token::F32(0.0),
+                Token::Str("wC"),
+                Token::F32(1.5),
+                Token::Str("wG"),
+                Token::F32(0.0),
+                Token::Str("wT"),
+                Token::F32(2.75),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn editing_db() {
+        for (known, indb, dbid) in [(None, false, ""), (Some("rs1".to_owned()), true, "rs1")] {
+            let data = SiteDataRef {
+                pos: &5,
+                refnuc: &Nucleotide::A,
+                prednuc: &PredNucleotide::Homozygous(Nucleotide::A),
+                genoqual: &0,
+                sequenced: &NucCounts::new(3, 0, 1, 0),
+                weighted: &FracNucCounts::zeros(),
+                indels: &IndelCounts::default(),
+                readstats: &ReadStats::default(),
+                samples: &vec![],
+                cells: &vec![],
+                known: &known,
+            };
+            assert_ser_tokens(
+                &SerializeSiteRef {
+                    contig: "chr1",
+                    strand: Strand::Forward,
+                    indels: false,
+                    readstats: false,
+                    weighted: false,
+                    editingdb: true,
+                    genoqual: false,
+                    data,
+                },
+                &[
+                    Token::Struct { name: "SiteMismatches", len: 11 },
+                    Token::Str("contig"),
+                    Token::Str("chr1"),
+                    Token::Str("pos"),
+                    Token::U64(5),
+                    Token::Str("trstrand"),
+                    Token::Str("+"),
+                    Token::Str("refnuc"),
+                    Token::Str("A"),
+                    Token::Str("prednuc"),
+                    Token::Str("A"),
+                    Token::Str("A"),
+                    Token::U32(3),
+                    Token::Str("C"),
+                    Token::U32(0),
+                    Token::Str("G"),
+                    Token::U32(1),
+                    Token::Str("T"),
+                    Token::U32(0),
+                    Token::Str("in_db"),
+                    Token::Bool(indb),
+                    Token::Str("db_id"),
+                    Token::Str(dbid),
+                    Token::StructEnd,
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn genotype_quality() {
+        let data = SiteDataRef {
+            pos: &7,
+            refnuc: &Nucleotide::A,
+            prednuc: &PredNucleotide::Heterozygous((Nucleotide::A, Nucleotide::G)),
+            genoqual: &42,
+            sequenced: &NucCounts::new(10, 0, 9, 0),
+            weighted: &FracNucCounts::zeros(),
+            indels: &IndelCounts::default(),
+            readstats: &ReadStats::default(),
+            samples: &vec![],
+            cells: &vec![],
+            known: &None,
+        };
+        assert_ser_tokens(
+            &SerializeSiteRef {
+                contig: "chr2",
+                strand: Strand::Reverse,
+                indels: false,
+                readstats: false,
+                weighted: false,
+                editingdb: false,
+                genoqual: true,
+                data,
+            },
+            &[
+                Token::Struct { name: "SiteMismatches", len: 10 },
+                Token::Str("contig"),
+                Token::Str("chr2"),
+                Token::Str("pos"),
+                Token::U64(7),
+                Token::Str("trstrand"),
+                Token::Str("-"),
+                Token::Str("refnuc"),
+                Token::Str("A"),
+                Token::Str("prednuc"),
+                Token::Str("A/G"),
+                Token::Str("gq"),
+                Token::U8(42),
+                Token::Str("A"),
+                Token::U32(10),
+                Token::Str("C"),
+                Token::U32(0),
+                Token::Str("G"),
+                Token::U32(9),
+                Token::Str("T"),
+                Token::U32(0),
+                Token::StructEnd,
+            ],
+        );
+    }
 }
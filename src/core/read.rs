@@ -2,7 +2,7 @@ use bio_types::genome;
 use bio_types::strand::ReqStrand;
 #[cfg(test)]
 use mockall::{mock, predicate::*};
-use rust_htslib::bam::record::CigarStringView;
+use rust_htslib::bam::record::{Aux, CigarStringView};
 use rust_htslib::bam::Record;
 
 #[allow(clippy::len_without_is_empty)]
@@ -31,6 +31,14 @@ pub trait AlignedRead: SequencedRead {
     fn pos(&self) -> i64;
     fn contig(&self) -> &str;
     fn flags(&self) -> u16;
+    // Mate start, only if the read is paired and its mate is aligned to the same contig
+    fn mate_pos(&self) -> Option<i64>;
+    // Value of the string aux tag or an empty slice if there is no such tag
+    fn aux_str(&self, tag: &[u8]) -> &[u8];
+    // Value of the character aux tag, if any
+    fn aux_char(&self, tag: &[u8]) -> Option<u8>;
+    // Value of the integer aux tag, if any
+    fn aux_int(&self, tag: &[u8]) -> Option<i64>;
 }
 
 #[cfg(test)]
@@ -42,6 +50,10 @@ mock! {
         fn pos(&self) -> i64;
         fn contig(&self) -> &str;
         fn flags(&self) -> u16;
+        fn mate_pos(&self) -> Option<i64>;
+        fn aux_str(&self, tag: &[u8]) -> &[u8];
+        fn aux_char(&self, tag: &[u8]) -> Option<u8>;
+        fn aux_int(&self, tag: &[u8]) -> Option<i64>;
     }
 
     impl SequencedRead for Read {
@@ -119,4 +131,42 @@ impl AlignedRead for Record {
     fn flags(&self) -> u16 {
         self.flags()
     }
+
+    #[inline]
+    fn mate_pos(&self) -> Option<i64> {
+        if self.is_paired() && !self.is_mate_unmapped() && self.mtid() == self.tid() {
+            Some(self.mpos())
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn aux_str(&self, tag: &[u8]) -> &[u8] {
+        match self.aux(tag) {
+            Ok(Aux::String(x)) => x.as_bytes(),
+            _ => &[],
+        }
+    }
+
+    #[inline]
+    fn aux_char(&self, tag: &[u8]) -> Option<u8> {
+        match self.aux(tag) {
+            Ok(Aux::Char(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn aux_int(&self, tag: &[u8]) -> Option<i64> {
+        match self.aux(tag) {
+            Ok(Aux::I8(x)) => Some(x as i64),
+            Ok(Aux::U8(x)) => Some(x as i64),
+            Ok(Aux::I16(x)) => Some(x as i64),
+            Ok(Aux::U16(x)) => Some(x as i64),
+            Ok(Aux::I32(x)) => Some(x as i64),
+            Ok(Aux::U32(x)) => Some(x as i64),
+            _ => None,
+        }
+    }
 }
@@ -62,7 +62,7 @@ impl RefEngine for AutoRef {
     }
 
     fn results(&self) -> RefEngineResult<'_> {
-        RefEngineResult { predicted: &self.cache, reference: self.reader.result() }
+        RefEngineResult { predicted: &self.cache, reference: self.reader.result(), quality: None }
     }
 }
 
@@ -0,0 +1,294 @@
+use std::ops::Range;
+
+use bio_types::genome::Position;
+use itertools::zip;
+
+use crate::core::dna::NucCounts;
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::io::fasta::FastaReader;
+use crate::core::refpred::PredNucleotide::{Heterozygous, Homozygous};
+use crate::core::refpred::{PredNucleotide, RefEngineResult};
+
+use super::RefEngine;
+
+// All diploid genotypes, each unordered pair of alleles is listed once
+const GENOTYPES: [(ReqNucleotide, ReqNucleotide); 10] = [
+    (ReqNucleotide::A, ReqNucleotide::A),
+    (ReqNucleotide::A, ReqNucleotide::C),
+    (ReqNucleotide::A, ReqNucleotide::G),
+    (ReqNucleotide::A, ReqNucleotide::T),
+    (ReqNucleotide::C, ReqNucleotide::C),
+    (ReqNucleotide::C, ReqNucleotide::G),
+    (ReqNucleotide::C, ReqNucleotide::T),
+    (ReqNucleotide::G, ReqNucleotide::G),
+    (ReqNucleotide::G, ReqNucleotide::T),
+    (ReqNucleotide::T, ReqNucleotide::T),
+];
+
+pub const MAX_QUALITY: u8 = 99;
+
+// Diploid genotype caller: the most probable genotype given sequenced nucleotides, a uniform sequencing
+// error rate and a simple prior favoring the assembly nucleotide. Calls below the quality cutoff
+// fall back to the assembly nucleotide.
+#[derive(Clone)]
+pub struct GenotypeRef {
+    errorrate: f64,
+    heterozygosity: f64,
+    minquality: u8,
+    // Prior multiplier for genotypes that could be explained by A->G/T->C editing (None to disable)
+    editing: Option<f64>,
+    cache: Vec<PredNucleotide>,
+    quality: Vec<u8>,
+    reader: Box<dyn FastaReader>,
+}
+
+impl GenotypeRef {
+    pub fn new(errorrate: f32, heterozygosity: f32, minquality: u8, reader: Box<dyn FastaReader>) -> Self {
+        debug_assert!(errorrate > 0.0 && errorrate < 1.0);
+        Self {
+            errorrate: errorrate as f64,
+            heterozygosity: heterozygosity as f64,
+            minquality,
+            editing: None,
+            cache: Vec::new(),
+            quality: Vec::new(),
+            reader,
+        }
+    }
+
+    pub fn with_editing_prior(mut self, editing: Option<f32>) -> Self {
+        self.editing = editing.map(|x| x as f64);
+        self
+    }
+
+    // Predicted genotype and its Phred-scaled quality
+    pub fn infer(&self, assembly: Nucleotide, sequenced: &NucCounts) -> (PredNucleotide, u8) {
+        // Nothing to call
+        if sequenced.coverage() == 0 {
+            return (Homozygous(assembly), 0);
+        }
+
+        let logpost = GENOTYPES.map(|x| self.loglikelihood(x, sequenced) + self.logprior(assembly, x));
+        let best = (0..GENOTYPES.len()).max_by(|&x, &y| logpost[x].total_cmp(&logpost[y])).unwrap();
+        let quality = genotype_quality(&logpost, best);
+        if quality >= self.minquality {
+            let (first, second) = GENOTYPES[best];
+            let predicted = match first == second {
+                true => Homozygous(first.into()),
+                false => Heterozygous((first.into(), second.into())),
+            };
+            return (predicted, quality);
+        }
+
+        // Uncertain call -> keep the assembly nucleotide
+        match GENOTYPES.iter().position(|&(first, second)| first == second && Nucleotide::from(first) == assembly) {
+            Some(ind) => (Homozygous(assembly), genotype_quality(&logpost, ind)),
+            None => (Homozygous(assembly), 0),
+        }
+    }
+
+    // Log10 probability to sequence the given nucleotides from the genotype
+    fn loglikelihood(&self, genotype: (ReqNucleotide, ReqNucleotide), sequenced: &NucCounts) -> f64 {
+        let emission = |allele: ReqNucleotide, nuc: ReqNucleotide| match allele == nuc {
+            true => 1.0 - self.errorrate,
+            false => self.errorrate / 3.0,
+        };
+
+        let mut result = 0.0;
+        for (nuc, count) in [
+            (ReqNucleotide::A, sequenced.A),
+            (ReqNucleotide::C, sequenced.C),
+            (ReqNucleotide::G, sequenced.G),
+            (ReqNucleotide::T, sequenced.T),
+        ] {
+            if count > 0 {
+                let prob = 0.5 * (emission(genotype.0, nuc) + emission(genotype.1, nuc));
+                result += count as f64 * prob.log10();
+            }
+        }
+        result
+    }
+
+    // Log10 prior of the genotype (not normalized)
+    fn logprior(&self, assembly: Nucleotide, genotype: (ReqNucleotide, ReqNucleotide)) -> f64 {
+        let homozygous = genotype.0 == genotype.1;
+        let mut prior = match ReqNucleotide::try_from(assembly) {
+            Ok(refnuc) => match ((genotype.0 == refnuc) as u8 + (genotype.1 == refnuc) as u8, homozygous) {
+                (2, _) => 1.0,
+                (1, _) => self.heterozygosity,
+                (0, true) => self.heterozygosity / 2.0,
+                (0, false) => self.heterozygosity * self.heterozygosity,
+            },
+            // Unknown assembly nucleotide -> all homozygous genotypes are equally likely
+            Err(_) => match homozygous {
+                true => 1.0,
+                false => self.heterozygosity,
+            },
+        };
+
+        let editable = match assembly {
+            Nucleotide::A => Some((ReqNucleotide::A, ReqNucleotide::G)),
+            Nucleotide::T => Some((ReqNucleotide::T, ReqNucleotide::C)),
+            _ => None,
+        };
+        if let (Some(editing), Some((refnuc, edited))) = (self.editing, editable) {
+            // Only A/G, G/G for A and C/T, C/C for T: genotypes made of the assembly & edited nucleotides
+            let explained = [genotype.0, genotype.1].iter().all(|x| *x == refnuc || *x == edited);
+            if explained && (genotype.0 == edited || genotype.1 == edited) {
+                prior *= editing;
+            }
+        }
+        prior.log10()
+    }
+}
+
+// Phred-scaled probability that the given genotype is wrong
+fn genotype_quality(logpost: &[f64], ind: usize) -> u8 {
+    let others: f64 =
+        logpost.iter().enumerate().filter(|(x, _)| *x != ind).map(|(_, x)| 10f64.powf(x - logpost[ind])).sum();
+    if others <= 0.0 {
+        return MAX_QUALITY;
+    }
+    let error = others / (1.0 + others);
+    (-10.0 * error.log10()).min(MAX_QUALITY as f64) as u8
+}
+
+impl RefEngine for GenotypeRef {
+    fn run(&mut self, contig: &str, range: Range<Position>, sequenced: &[NucCounts]) {
+        self.cache.clear();
+        self.cache.reserve(sequenced.len());
+        self.quality.clear();
+        self.quality.reserve(sequenced.len());
+
+        self.reader.fetch(contig, range);
+        let reference = self.reader.result();
+        debug_assert!(reference.len() == sequenced.len());
+
+        for (r, s) in zip(sequenced, reference) {
+            let (predicted, quality) = self.infer(*s, r);
+            self.cache.push(predicted);
+            self.quality.push(quality);
+        }
+    }
+
+    fn results(&self) -> RefEngineResult<'_> {
+        RefEngineResult { predicted: &self.cache, reference: self.reader.result(), quality: Some(&self.quality) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::{AbstractInterval, Interval};
+
+    use crate::core::io::fasta::MockFastaReader;
+
+    use super::*;
+
+    fn dummy(editing: Option<f32>) -> GenotypeRef {
+        GenotypeRef::new(0.01, 0.001, 20, Box::new(MockFastaReader::new())).with_editing_prior(editing)
+    }
+
+    #[test]
+    fn homozygous() {
+        let (predicted, quality) = dummy(None).infer(Nucleotide::A, &NucCounts::new(50, 0, 0, 0));
+        assert!(matches!(predicted, Homozygous(Nucleotide::A)));
+        assert_eq!(quality, MAX_QUALITY);
+
+        let (predicted, quality) = dummy(None).infer(Nucleotide::A, &NucCounts::new(0, 0, 0, 30));
+        assert!(matches!(predicted, Homozygous(Nucleotide::T)));
+        assert!(quality >= 20);
+
+        let (predicted, quality) = dummy(None).infer(Nucleotide::Unknown, &NucCounts::new(0, 0, 12, 0));
+        assert!(matches!(predicted, Homozygous(Nucleotide::G)));
+        assert!(quality >= 20);
+    }
+
+    #[test]
+    fn heterozygous() {
+        let (predicted, quality) = dummy(None).infer(Nucleotide::A, &NucCounts::new(25, 25, 0, 0));
+        assert!(matches!(predicted, Heterozygous((Nucleotide::A, Nucleotide::C))));
+        assert_eq!(quality, MAX_QUALITY);
+
+        let (predicted, _) = dummy(None).infer(Nucleotide::T, &NucCounts::new(0, 0, 20, 18));
+        assert!(matches!(predicted, Heterozygous((Nucleotide::G, Nucleotide::T))));
+    }
+
+    #[test]
+    fn uncertain() {
+        // No coverage
+        let (predicted, quality) = dummy(None).infer(Nucleotide::C, &NucCounts::zeros());
+        assert!(matches!(predicted, Homozygous(Nucleotide::C)));
+        assert_eq!(quality, 0);
+
+        // A single mismatch is not enough to call a variant
+        let (predicted, quality) = dummy(None).infer(Nucleotide::C, &NucCounts::new(1, 0, 0, 0));
+        assert!(matches!(predicted, Homozygous(Nucleotide::C)));
+        assert!(quality < 20);
+
+        let (predicted, quality) = dummy(None).infer(Nucleotide::Unknown, &NucCounts::new(1, 1, 0, 0));
+        assert!(matches!(predicted, Homozygous(Nucleotide::Unknown)));
+        assert_eq!(quality, 0);
+    }
+
+    #[test]
+    fn editing_prior() {
+        // Partially edited A->G site
+        let sequenced = NucCounts::new(40, 0, 8, 0);
+        let (predicted, quality) = dummy(None).infer(Nucleotide::A, &sequenced);
+        assert!(matches!(predicted, Heterozygous((Nucleotide::A, Nucleotide::G))));
+        assert!(quality >= 20);
+
+        let (predicted, quality) = dummy(Some(0.001)).infer(Nucleotide::A, &sequenced);
+        assert!(matches!(predicted, Homozygous(Nucleotide::A)));
+        assert!(quality < 20);
+
+        // Same for T->C
+        let sequenced = NucCounts::new(0, 8, 0, 40);
+        let (predicted, _) = dummy(Some(0.001)).infer(Nucleotide::T, &sequenced);
+        assert!(matches!(predicted, Homozygous(Nucleotide::T)));
+
+        // Other mismatches are not affected
+        let sequenced = NucCounts::new(40, 8, 0, 0);
+        let (predicted, _) = dummy(Some(0.001)).infer(Nucleotide::A, &sequenced);
+        assert!(matches!(predicted, Heterozygous((Nucleotide::A, Nucleotide::C))));
+
+        // Only genotypes made of the assembly & edited nucleotides are penalized
+        let (plain, edited) = (dummy(None), dummy(Some(0.001)));
+        for (assembly, genotype, penalized) in [
+            (Nucleotide::A, (ReqNucleotide::A, ReqNucleotide::G), true),
+            (Nucleotide::A, (ReqNucleotide::G, ReqNucleotide::G), true),
+            (Nucleotide::A, (ReqNucleotide::C, ReqNucleotide::G), false),
+            (Nucleotide::A, (ReqNucleotide::G, ReqNucleotide::T), false),
+            (Nucleotide::A, (ReqNucleotide::A, ReqNucleotide::A), false),
+            (Nucleotide::T, (ReqNucleotide::C, ReqNucleotide::T), true),
+            (Nucleotide::T, (ReqNucleotide::A, ReqNucleotide::C), false),
+            (Nucleotide::G, (ReqNucleotide::A, ReqNucleotide::G), false),
+        ] {
+            let delta = plain.logprior(assembly, genotype) - edited.logprior(assembly, genotype);
+            let expected = if penalized { 3.0 } else { 0.0 };
+            assert!((delta - expected).abs() < 1e-6, "{:?} {:?}", assembly, genotype);
+        }
+    }
+
+    #[test]
+    fn results() {
+        let interval = Interval::new("chr1".into(), 10..13);
+        let sequenced = vec![NucCounts::A(30), NucCounts::new(15, 15, 0, 0), NucCounts::zeros()];
+        let assembly = vec![Nucleotide::A, Nucleotide::A, Nucleotide::G];
+
+        let mut reader = MockFastaReader::new();
+        reader.expect_fetch().once().return_const(());
+        reader.expect_result().return_const(assembly.clone());
+
+        let mut dummy = GenotypeRef::new(0.01, 0.001, 20, Box::new(reader));
+        dummy.run(interval.contig(), interval.range(), &sequenced);
+
+        let result = dummy.results();
+        assert_eq!(result.reference, assembly);
+        assert!(matches!(
+            result.predicted,
+            [Homozygous(Nucleotide::A), Heterozygous((Nucleotide::A, Nucleotide::C)), Homozygous(Nucleotide::G)]
+        ));
+        assert_eq!(result.quality.unwrap(), [MAX_QUALITY, MAX_QUALITY, 0]);
+    }
+}
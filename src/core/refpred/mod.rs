@@ -4,12 +4,14 @@ use bio_types::genome::Position;
 use dyn_clone::DynClone;
 
 pub use autoref::AutoRef;
+pub use genotype::GenotypeRef;
 pub use vcf::VCFCorrectedReference;
 
 use crate::core::dna::NucCounts;
 use crate::core::dna::Nucleotide;
 
 mod autoref;
+mod genotype;
 mod vcf;
 
 #[derive(Clone, Copy, Debug)]
@@ -57,6 +59,8 @@ impl Default for PredNucleotide {
 pub struct RefEngineResult<'a> {
     pub predicted: &'a [PredNucleotide],
     pub reference: &'a [Nucleotide],
+    // Phred-scaled quality of the predicted genotypes (None if not estimated)
+    pub quality: Option<&'a [u8]>,
 }
 
 pub trait RefEngine: Send + DynClone {
@@ -15,11 +15,21 @@ use crate::core::refpred::{PredNucleotide, RefEngineResult};
 
 use super::RefEngine;
 
+#[derive(Clone)]
+enum Variants {
+    // All variants are loaded in memory
+    Loaded {
+        ref2rid: HashMap<String, u32>,
+        homozygotes: AnnotMap<u32, ReqNucleotide>,
+        heterozygotes: AnnotMap<u32, (ReqNucleotide, ReqNucleotide)>,
+    },
+    // Variants are fetched for each workload from the indexed file -> memory doesn't depend on the VCF size
+    Indexed(vcf::IndexedVCF),
+}
+
 #[derive(Clone)]
 pub struct VCFCorrectedReference {
-    ref2rid: HashMap<String, u32>,
-    homozygotes: AnnotMap<u32, ReqNucleotide>,
-    heterozygotes: AnnotMap<u32, (ReqNucleotide, ReqNucleotide)>,
+    variants: Variants,
     cache: Vec<PredNucleotide>,
     reader: Box<dyn FastaReader>,
 }
@@ -45,7 +55,12 @@ impl VCFCorrectedReference {
                 heterozygotes.insert_at((nuc1, nuc2), &record);
             }
         }
-        Self { ref2rid: variants.ref2rid, homozygotes, heterozygotes, cache: vec![], reader }
+        let variants = Variants::Loaded { ref2rid: variants.ref2rid, homozygotes, heterozygotes };
+        Self { variants, cache: vec![], reader }
+    }
+
+    pub fn indexed(variants: vcf::IndexedVCF, reader: Box<dyn FastaReader>) -> Self {
+        Self { variants: Variants::Indexed(variants), cache: vec![], reader }
     }
 }
 
@@ -62,16 +77,30 @@ impl RefEngine for VCFCorrectedReference {
             self.cache.push(Homozygous(*refn));
         }
 
-        let rid = *self.ref2rid.get(contig).expect("Failed to fetch VCF data variants for a contig.");
+        let (ref2rid, homozygotes, heterozygotes) = match &mut self.variants {
+            Variants::Loaded { ref2rid, homozygotes, heterozygotes } => (ref2rid, homozygotes, heterozygotes),
+            Variants::Indexed(variants) => {
+                let cache = &mut self.cache;
+                variants.fetch(contig, range.clone(), |loc, f, s| {
+                    cache[(loc - range.start) as usize] = match f == s {
+                        true => Homozygous(f.into()),
+                        false => Heterozygous((f.into(), s.into())),
+                    }
+                });
+                return;
+            }
+        };
+
+        let rid = *ref2rid.get(contig).expect("Failed to fetch VCF data variants for a contig.");
         let query = Contig::new(rid, range.start as isize, length, Strand::Unknown);
         // Fill in homozygous positions
-        for hit in self.homozygotes.find(&query) {
+        for hit in homozygotes.find(&query) {
             let loc = hit.interval().start as Position;
             debug_assert!(range.contains(&loc));
             self.cache[(loc - range.start) as usize] = Homozygous((*hit.data()).into())
         }
         // Fill in heterozygous positions
-        for hit in self.heterozygotes.find(&query) {
+        for hit in heterozygotes.find(&query) {
             let loc = hit.interval().start as Position;
             debug_assert!(range.contains(&loc));
             let (f, s) = hit.data();
@@ -80,6 +109,6 @@ impl RefEngine for VCFCorrectedReference {
     }
 
     fn results(&self) -> RefEngineResult<'_> {
-        RefEngineResult { predicted: &self.cache, reference: self.reader.result() }
+        RefEngineResult { predicted: &self.cache, reference: self.reader.result(), quality: None }
     }
 }
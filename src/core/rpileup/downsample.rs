@@ -0,0 +1,284 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::filters::ReadsFilter;
+
+// Caps the read depth: reads starting at a position already covered by `maxdepth` kept reads are skipped.
+// Reads starting at the same position compete for the remaining slots by the hash of their name, so that
+// the selection is reproducible. The rightmost mate follows the decision made for the leftmost one, even if
+// it exceeds the depth. Reads rejected by the filter are passed through and don't take up the depth.
+// Each read is tagged with its source (input file), reads from all sources share the same depth.
+pub struct DepthDownsampler<R: AlignedRead> {
+    maxdepth: u32,
+    filter: Arc<dyn ReadsFilter<R> + Send + Sync>,
+    // End positions of the kept reads that might still cover the current position
+    ends: BinaryHeap<Reverse<i64>>,
+    // Reads starting at the current position and their sources
+    pending: Vec<(usize, R)>,
+    // Released reads that can be reused by the caller
+    spare: Vec<R>,
+    // Decisions made for the leftmost mates whose rightmost mates are not seen yet
+    mates: HashMap<Vec<u8>, bool>,
+    // Caches
+    followers: Vec<Option<bool>>,
+    priority: Vec<(u64, usize)>,
+    keep: Vec<bool>,
+}
+
+impl<R: AlignedRead> DepthDownsampler<R> {
+    pub fn new<Filter: ReadsFilter<R> + Send + Sync + 'static>(maxdepth: u32, filter: Filter) -> Self {
+        assert!(maxdepth > 0, "Maximum depth must be positive");
+        Self {
+            maxdepth,
+            filter: Arc::new(filter),
+            ends: BinaryHeap::new(),
+            pending: Vec::new(),
+            spare: Vec::new(),
+            mates: HashMap::new(),
+            followers: Vec::new(),
+            priority: Vec::new(),
+            keep: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn maxdepth(&self) -> u32 {
+        self.maxdepth
+    }
+
+    pub fn reset(&mut self) {
+        self.ends.clear();
+        self.pending.clear();
+        self.mates.clear();
+    }
+
+    // A released read to be overwritten by the caller, if any
+    #[inline]
+    pub fn recycle(&mut self) -> Option<R> {
+        self.spare.pop()
+    }
+
+    // Reads must be sorted by the start position, kept reads are passed to the callback with their source
+    pub fn push(&mut self, source: usize, read: R, mut callback: impl FnMut(usize, &R)) {
+        if matches!(self.pending.first(), Some((_, x)) if x.pos() != read.pos()) {
+            self.flush(&mut callback);
+        }
+        if self.filter.is_read_ok(&read) {
+            self.pending.push((source, read));
+        } else {
+            callback(source, &read);
+            self.release(read);
+        }
+    }
+
+    // Select and release all pending reads
+    pub fn flush(&mut self, mut callback: impl FnMut(usize, &R)) {
+        let start = match self.pending.first() {
+            Some((_, x)) => x.pos(),
+            None => return,
+        };
+        while matches!(self.ends.peek(), Some(Reverse(end)) if *end <= start) {
+            self.ends.pop();
+        }
+
+        // Rightmost mates don't compete for the slots, but take up the depth if kept
+        self.followers.clear();
+        self.followers.extend(self.pending.iter().map(|(_, x)| self.mates.remove(x.name())));
+        let followers = self.followers.iter().filter(|x| x.is_some()).count();
+        let kept = self.followers.iter().filter(|x| **x == Some(true)).count();
+
+        let slots = (self.maxdepth as usize).saturating_sub(self.ends.len() + kept);
+        let downsample = self.pending.len() - followers > slots;
+        if downsample {
+            self.priority.clear();
+            self.priority.extend(
+                self.pending
+                    .iter()
+                    .enumerate()
+                    .filter(|(ind, _)| self.followers[*ind].is_none())
+                    .map(|(ind, (_, x))| (priority(x.name()), ind)),
+            );
+            self.priority.sort_unstable();
+
+            self.keep.clear();
+            self.keep.resize(self.pending.len(), false);
+            for &(_, ind) in &self.priority[..slots] {
+                self.keep[ind] = true;
+            }
+        }
+
+        // Kept reads are released in the original order
+        let mut pending = std::mem::take(&mut self.pending);
+        for (ind, (source, read)) in pending.drain(..).enumerate() {
+            // Both mates might start at the same position
+            let keep = match self.followers[ind].or_else(|| self.mates.remove(read.name())) {
+                Some(keep) => keep,
+                None => {
+                    let keep = !downsample || self.keep[ind];
+                    if matches!(read.mate_pos(), Some(x) if x >= read.pos()) {
+                        self.mates.insert(read.name().to_vec(), keep);
+                    }
+                    keep
+                }
+            };
+            if keep {
+                self.ends.push(Reverse(read.cigar().end_pos()));
+                callback(source, &read);
+            }
+            self.release(read);
+        }
+        self.pending = pending;
+    }
+
+    // There is no need to keep more spare reads than the depth
+    fn release(&mut self, read: R) {
+        if self.spare.len() < self.maxdepth as usize {
+            self.spare.push(read);
+        }
+    }
+}
+
+impl<R: AlignedRead> Clone for DepthDownsampler<R> {
+    fn clone(&self) -> Self {
+        Self {
+            maxdepth: self.maxdepth,
+            filter: self.filter.clone(),
+            ends: BinaryHeap::new(),
+            pending: Vec::new(),
+            spare: Vec::new(),
+            mates: HashMap::new(),
+            followers: Vec::new(),
+            priority: Vec::new(),
+            keep: Vec::new(),
+        }
+    }
+}
+
+// FNV-1a + MurmurHash3 finalizer: stable across platforms and releases, unlike the std hasher
+fn priority(name: &[u8]) -> u64 {
+    let mut hash = name.iter().fold(0xcbf29ce484222325u64, |hash, &x| (hash ^ x as u64).wrapping_mul(0x100000001b3));
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    use crate::core::read::{MockRead, SequencedRead};
+    use crate::core::rpileup::ncounter::filters::ByFlags;
+
+    use super::*;
+
+    fn mock(name: &str, pos: i64, len: u32, flags: u16, matepos: Option<i64>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_name().return_const(name.as_bytes().to_vec());
+        read.expect_pos().return_const(pos);
+        read.expect_flags().return_const(flags);
+        read.expect_mate_pos().return_const(matepos);
+        read.expect_cigar().return_once(move || CigarString(vec![Cigar::Match(len)]).into_view(pos));
+        read
+    }
+
+    fn release(dummy: &mut DepthDownsampler<MockRead>, reads: Vec<MockRead>) -> Vec<String> {
+        let mut kept = Vec::new();
+        let mut callback = |_, x: &MockRead| kept.push(String::from_utf8(x.name().to_vec()).unwrap());
+        dummy.reset();
+        for read in reads {
+            dummy.push(0, read, &mut callback);
+        }
+        dummy.flush(&mut callback);
+        kept
+    }
+
+    fn run(dummy: &mut DepthDownsampler<MockRead>, reads: Vec<(&str, i64, u32, u16)>) -> Vec<String> {
+        let reads = reads.into_iter().map(|(name, pos, len, flags)| mock(name, pos, len, flags, None)).collect();
+        release(dummy, reads)
+    }
+
+    #[test]
+    fn priority() {
+        assert_eq!(super::priority(b"read1"), super::priority(b"read1"));
+        assert_ne!(super::priority(b"read1"), super::priority(b"read2"));
+    }
+
+    #[test]
+    fn downsample() {
+        let mut dummy = DepthDownsampler::new(2, ByFlags::new(0, 4));
+        assert_eq!(dummy.maxdepth(), 2);
+
+        // Depth is never exceeded
+        let reads = vec![("a", 0, 10, 0), ("b", 0, 10, 0), ("c", 5, 10, 0), ("d", 10, 5, 0), ("e", 12, 5, 0)];
+        assert_eq!(run(&mut dummy, reads), ["a", "b", "d", "e"]);
+
+        // Reads that don't pass the filter are released as is
+        let reads = vec![("a", 0, 10, 0), ("x", 0, 10, 4), ("b", 0, 10, 0), ("y", 5, 10, 4), ("c", 5, 10, 0)];
+        assert_eq!(run(&mut dummy, reads), ["x", "a", "b", "y"]);
+    }
+
+    #[test]
+    fn deterministic() {
+        let names = ["r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8"];
+        let mut dummy = DepthDownsampler::new(3, ByFlags::new(0, 0));
+
+        let reads = names.iter().map(|x| (*x, 0, 10, 0)).collect();
+        let kept = run(&mut dummy, reads);
+        assert_eq!(kept.len(), 3);
+
+        // Same reads in a different order -> same selection in the given order
+        let reads = names.iter().rev().map(|x| (*x, 0, 10, 0)).collect();
+        let mut reversed = run(&mut dummy.clone(), reads);
+        reversed.reverse();
+        assert_eq!(kept, reversed);
+
+        // The same mates are kept downstream
+        let reads = names.iter().map(|x| (*x, 20, 10, 0)).collect();
+        assert_eq!(run(&mut dummy, reads), kept);
+    }
+
+    #[test]
+    fn mates() {
+        let names = ["r1", "r2", "r3", "r4", "r5", "r6"];
+        let mut dummy = DepthDownsampler::new(2, ByFlags::new(0, 0));
+
+        // Rightmost mates follow the leftmost ones and take up the depth
+        let mut reads = names.iter().map(|x| mock(x, 0, 10, 1, Some(50))).collect::<Vec<_>>();
+        reads.extend(names.iter().rev().map(|x| mock(x, 50, 10, 1, Some(0))));
+        reads.extend(["x1", "x2"].iter().map(|x| mock(x, 50, 10, 0, None)));
+        let kept = release(&mut dummy, reads);
+        assert_eq!(kept.len(), 4);
+        assert_eq!(kept[..2], [kept[3].clone(), kept[2].clone()]);
+
+        // Mates starting at the same position
+        let reads = names.iter().flat_map(|x| [mock(x, 0, 10, 1, Some(0)), mock(x, 0, 10, 1, Some(0))]).collect();
+        let kept = release(&mut dummy, reads);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0], kept[1]);
+    }
+    #[test]
+    fn sources() {
+        let mut dummy = DepthDownsampler::new(2, ByFlags::new(0, 4));
+        let mut kept = Vec::new();
+        let mut callback = |source, x: &MockRead| kept.push((source, String::from_utf8(x.name().to_vec()).unwrap()));
+
+        // Reads from all sources share the depth
+        dummy.reset();
+        dummy.push(0, mock("a", 0, 10, 0, None), &mut callback);
+        dummy.push(1, mock("x", 0, 10, 4, None), &mut callback);
+        dummy.push(1, mock("b", 0, 10, 0, None), &mut callback);
+        dummy.push(0, mock("c", 5, 10, 0, None), &mut callback);
+        dummy.push(1, mock("d", 10, 10, 0, None), &mut callback);
+        dummy.flush(&mut callback);
+        assert_eq!(kept, [(1, "x".to_owned()), (0, "a".to_owned()), (1, "b".to_owned()), (1, "d".to_owned())]);
+
+        // Released reads are recycled, up to the depth
+        assert!(dummy.recycle().is_some());
+        assert!(dummy.recycle().is_some());
+        assert!(dummy.recycle().is_none());
+    }
+}
@@ -1,43 +1,104 @@
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use bio_types::genome::AbstractInterval;
 use itertools::Itertools;
 use rust_htslib::bam::{IndexedReader, Read, Record};
 
+use crate::core::io;
+use crate::core::rpileup::downsample::DepthDownsampler;
+use crate::core::rpileup::stream::HTSStream;
 use crate::core::rpileup::{ReadsCollider, ReadsCollidingEngine};
 
 pub struct HTSPileupEngine<Collider> {
     collider: Collider,
     htsreaders: Vec<IndexedReader>,
     htsfiles: Vec<PathBuf>,
+    reference: PathBuf,
+    downsampler: Option<DepthDownsampler<Record>>,
+    stream: Option<Arc<Mutex<HTSStream>>>,
     success: bool,
 }
 
 impl<Collider: for<'a> ReadsCollider<'a, Record>> HTSPileupEngine<Collider> {
-    pub fn new(htsfiles: Vec<PathBuf>, collider: Collider) -> Self {
+    pub fn new(htsfiles: Vec<PathBuf>, reference: PathBuf, collider: Collider) -> Self {
         let htsreaders: Vec<IndexedReader> = htsfiles
             .iter()
+            // Streamed input is attached separately, see with_stream
+            .filter(|hts| !io::hts::is_stdin(hts))
             .map(|hts| {
-                IndexedReader::from_path(&hts).unwrap_or_else(|_| {
+                let mut reader = IndexedReader::from_path(&hts).unwrap_or_else(|_| {
                     panic!(
                         "Failed to open file {}\n\
                         Possible reasons: BAM file was not indexed (samtools index); you don't have read permissions",
                         hts.display()
                     )
-                })
+                });
+                // Required to decode CRAM files
+                reader.set_reference(&reference).unwrap_or_else(|_| {
+                    panic!("Failed to set reference {} for file {}", reference.display(), hts.display())
+                });
+                reader
             })
             .collect();
 
-        Self { collider, htsreaders, htsfiles, success: false }
+        Self { collider, htsreaders, htsfiles, reference, downsampler: None, stream: None, success: false }
+    }
+
+    // Cap the read depth across all input files
+    pub fn with_downsampler(mut self, downsampler: Option<DepthDownsampler<Record>>) -> Self {
+        self.downsampler = downsampler;
+        self
+    }
+
+    // Read from the coordinate-sorted stream instead of the indexed files.
+    // The stream is shared between all clones, workloads must be processed sequentially in the stream order.
+    pub fn with_stream(mut self, stream: Option<Arc<Mutex<HTSStream>>>) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    fn run_stream(&mut self, stream: &Mutex<HTSStream>, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) {
+        let mut stream = stream.lock().unwrap();
+        let mut reads = stream.fetch(cwork.contig(), cwork.range()).peekable();
+
+        // Nothing to do
+        if reads.peek().is_none() {
+            self.success = false;
+            return;
+        }
+
+        self.collider.reset(cwork);
+        self.collider.source(0);
+        match self.downsampler.as_mut() {
+            None => reads.for_each(|x| self.collider.collide(x)),
+            Some(downsampler) => {
+                // Reads might overlap the next workloads -> they stay in the stream buffer
+                let collider = &mut self.collider;
+                downsampler.reset();
+                for read in reads {
+                    downsampler.push(0, read.clone(), |_, x| collider.collide(x));
+                }
+                downsampler.flush(|_, x| collider.collide(x));
+            }
+        }
+        self.collider.finalize();
+        self.success = true;
     }
 }
 
 impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, Collider> for HTSPileupEngine<Collider> {
     fn run(&mut self, cwork: <Collider as ReadsCollider<'_, Record>>::Workload) {
+        if let Some(stream) = self.stream.clone() {
+            self.run_stream(&stream, cwork);
+            return;
+        }
+
         let toread = self
             .htsreaders
             .iter_mut()
-            .filter_map(|reader| {
+            .enumerate()
+            .filter_map(|(ind, reader)| {
                 // No such contig in the BAM file
                 if !reader.header().target_names().contains(&cwork.contig().as_bytes()) {
                     return None;
@@ -55,7 +116,7 @@ impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, C
                 let mut record = Record::new();
                 let isavailable = reader.read(&mut record);
                 match isavailable {
-                    Some(Ok(())) => Some((reader, record)),
+                    Some(Ok(())) => Some((ind, reader, record)),
                     _ => None,
                 }
             })
@@ -70,10 +131,41 @@ impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, C
         // Something to do, trigger the reset -> collide -> finalize
         self.collider.reset(cwork);
 
-        for (reader, mut record) in toread.into_iter() {
-            self.collider.collide(&record);
-            while let Some(Ok(())) = reader.read(&mut record) {
-                self.collider.collide(&record);
+        match self.downsampler.as_mut() {
+            None => {
+                for (ind, reader, mut record) in toread.into_iter() {
+                    self.collider.source(ind);
+                    self.collider.collide(&record);
+                    while let Some(Ok(())) = reader.read(&mut record) {
+                        self.collider.collide(&record);
+                    }
+                }
+            }
+            Some(downsampler) => {
+                // The depth is shared by all files -> reads are merged by the start position.
+                // Records are buffered by the downsampler -> released ones are reused for the next reads.
+                let collider = &mut self.collider;
+                let mut toread = toread;
+                downsampler.reset();
+                while let Some(next) = toread.iter().position_min_by_key(|(_, _, record)| record.pos()) {
+                    let (ind, reader, record) = &mut toread[next];
+                    let mut nextrec = downsampler.recycle().unwrap_or_else(Record::new);
+                    let isavailable = matches!(reader.read(&mut nextrec), Some(Ok(())));
+
+                    let ind = *ind;
+                    let current = std::mem::replace(record, nextrec);
+                    downsampler.push(ind, current, |source, x| {
+                        collider.source(source);
+                        collider.collide(x)
+                    });
+                    if !isavailable {
+                        toread.remove(next);
+                    }
+                }
+                downsampler.flush(|source, x| {
+                    collider.source(source);
+                    collider.collide(x)
+                });
             }
         }
         self.collider.finalize();
@@ -90,6 +182,8 @@ impl<Collider: for<'a> ReadsCollider<'a, Record>> ReadsCollidingEngine<Record, C
 
 impl<Collider: for<'a> ReadsCollider<'a, Record> + Clone> Clone for HTSPileupEngine<Collider> {
     fn clone(&self) -> Self {
-        Self::new(self.htsfiles.clone(), self.collider.clone())
+        Self::new(self.htsfiles.clone(), self.reference.clone(), self.collider.clone())
+            .with_downsampler(self.downsampler.clone())
+            .with_stream(self.stream.clone())
     }
 }
@@ -0,0 +1,425 @@
+use std::io::Write;
+use std::ops::Range;
+
+use bio_types::genome::{AbstractInterval, Position};
+use bio_types::strand::ReqStrand;
+use csv::Writer;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::core::dna::{NucCounts, Nucleotide, ReqNucleotide};
+use crate::core::read::AlignedRead;
+use crate::core::refpred::RefEngine;
+use crate::core::rpileup::ncounter::cnt::BaseNucCounter;
+use crate::core::rpileup::ncounter::filters::ReadsFilter;
+use crate::core::rpileup::ReadsCollider;
+use crate::core::workload::SiteWorkload;
+
+// Read with a dense cluster of A>G (forward transcript) or T>C (reverse transcript) mismatches
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HyperEditedRead {
+    pub name: Vec<u8>,
+    pub trstrand: ReqStrand,
+    // From the first to the last edited position
+    pub cluster: Range<Position>,
+    pub edits: u32,
+    // Covered reference A (forward) or T (reverse) bases
+    pub covered: u32,
+}
+
+impl HyperEditedRead {
+    #[inline]
+    pub fn density(&self) -> f32 {
+        self.edits as f32 / self.covered as f32
+    }
+}
+
+// Nucleotides sequenced by the hyper-edited reads at a single site
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HyperEditedSite {
+    pub pos: Position,
+    pub trstrand: ReqStrand,
+    pub refnuc: Nucleotide,
+    pub sequenced: NucCounts,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HyperEditBatch {
+    pub contig: String,
+    pub reads: Vec<HyperEditedRead>,
+    pub sites: Vec<HyperEditedSite>,
+}
+
+impl HyperEditBatch {
+    pub fn reads_to_csv<W: Write>(&self, writer: &mut Writer<W>) -> csv::Result<()> {
+        for read in &self.reads {
+            writer.serialize(SerializeHyperEditedRead { contig: &self.contig, read })?;
+        }
+        Ok(())
+    }
+
+    pub fn sites_to_csv<W: Write>(&self, writer: &mut Writer<W>) -> csv::Result<()> {
+        for site in &self.sites {
+            writer.serialize(SerializeHyperEditedSite { contig: &self.contig, site })?;
+        }
+        Ok(())
+    }
+}
+
+struct SerializeHyperEditedRead<'a> {
+    contig: &'a str,
+    read: &'a HyperEditedRead,
+}
+
+impl Serialize for SerializeHyperEditedRead<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("HyperEditedRead", 8)?;
+        state.serialize_field("name", &String::from_utf8_lossy(&self.read.name))?;
+        state.serialize_field("contig", self.contig)?;
+        state.serialize_field("trstrand", self.read.trstrand.strand_symbol())?;
+        state.serialize_field("start", &self.read.cluster.start)?;
+        state.serialize_field("end", &self.read.cluster.end)?;
+        state.serialize_field("edits", &self.read.edits)?;
+        state.serialize_field("covered", &self.read.covered)?;
+        state.serialize_field("density", &self.read.density())?;
+        state.end()
+    }
+}
+
+struct SerializeHyperEditedSite<'a> {
+    contig: &'a str,
+    site: &'a HyperEditedSite,
+}
+
+impl Serialize for SerializeHyperEditedSite<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("HyperEditedSite", 8)?;
+        state.serialize_field("contig", self.contig)?;
+        state.serialize_field("pos", &self.site.pos)?;
+        state.serialize_field("trstrand", self.site.trstrand.strand_symbol())?;
+        state.serialize_field("refnuc", self.site.refnuc.symbol())?;
+        state.serialize_field("A", &self.site.sequenced.A)?;
+        state.serialize_field("C", &self.site.sequenced.C)?;
+        state.serialize_field("G", &self.site.sequenced.G)?;
+        state.serialize_field("T", &self.site.sequenced.T)?;
+        state.end()
+    }
+}
+
+// Flags reads with at least `minedits` A>G (T>C) mismatches against the FASTA reference that make up
+// at least `mindensity` of the covered reference A (T) bases. The assembly is used instead of the predicted
+// reference, which might be "corrected" to G (C) in densely edited regions.
+// Optionally, nucleotides sequenced by the flagged reads are counted in a separate track of sites.
+#[derive(Clone)]
+pub struct HyperEditCollider<R: AlignedRead, Filter: ReadsFilter<R>> {
+    base: BaseNucCounter<R, Filter>,
+    refnucpred: Box<dyn RefEngine>,
+    minedits: u32,
+    mindensity: f32,
+    track: bool,
+    ranges: Vec<Range<u64>>,
+    // Counted reads: name and the range of their bases in the cache
+    reads: Vec<(Vec<u8>, Range<usize>)>,
+    bases: Vec<(u32, ReqNucleotide)>,
+    // Nucleotides sequenced by the hyper-edited reads on the forward/reverse transcript strand
+    counts: [Vec<NucCounts>; 2],
+    batch: HyperEditBatch,
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> HyperEditCollider<R, Filter> {
+    pub fn new(
+        base: BaseNucCounter<R, Filter>,
+        refnucpred: Box<dyn RefEngine>,
+        minedits: u32,
+        mindensity: f32,
+    ) -> Self {
+        assert!(minedits > 0, "Minimum number of edits must be positive");
+        Self {
+            base: base.with_read_bases(true),
+            refnucpred,
+            minedits,
+            mindensity,
+            track: false,
+            ranges: vec![],
+            reads: vec![],
+            bases: vec![],
+            counts: Default::default(),
+            batch: Default::default(),
+        }
+    }
+
+    pub fn with_track(mut self, track: bool) -> Self {
+        self.track = track;
+        self
+    }
+}
+
+impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for HyperEditCollider<R, Filter> {
+    type ColliderResult = &'a HyperEditBatch;
+    type Workload = SiteWorkload;
+
+    fn reset(&mut self, info: Self::Workload) {
+        let (interval, ranges) = info.dissolve();
+        self.base.reset(interval);
+        self.ranges = ranges;
+        self.reads.clear();
+        self.bases.clear();
+        self.batch.reads.clear();
+        self.batch.sites.clear();
+    }
+
+    fn source(&mut self, index: usize) {
+        self.base.source(index);
+    }
+
+    fn collide(&mut self, read: &R) {
+        self.base.count(read);
+
+        // Bases outside of the workload ranges are ignored
+        let start = self.base.interval().range().start;
+        let first = self.bases.len();
+        for &(pos, nuc) in self.base.read_bases() {
+            if self.ranges.iter().any(|x| x.contains(&(start + pos as u64))) {
+                self.bases.push((pos, nuc));
+            }
+        }
+        if self.bases.len() > first {
+            self.reads.push((read.name().to_vec(), first..self.bases.len()));
+        }
+    }
+
+    fn finalize(&mut self) {
+        let interval = self.base.interval();
+        self.refnucpred.run(interval.contig(), interval.range(), self.base.counted());
+        let reference = self.refnucpred.results().reference;
+
+        let start = interval.range().start;
+        self.batch.contig = interval.contig().to_owned();
+        if self.track {
+            for cnts in &mut self.counts {
+                cnts.clear();
+                cnts.resize(reference.len(), NucCounts::zeros());
+            }
+        }
+
+        let editing = [
+            (ReqStrand::Forward, Nucleotide::A, ReqNucleotide::G),
+            (ReqStrand::Reverse, Nucleotide::T, ReqNucleotide::C),
+        ];
+        for (name, range) in self.reads.drain(..) {
+            let bases = &self.bases[range];
+            for (ind, &(trstrand, refnuc, edited)) in editing.iter().enumerate() {
+                let (mut covered, mut edits, mut cluster) = (0, 0, None);
+                for &(pos, nuc) in bases.iter().filter(|(pos, _)| reference[*pos as usize] == refnuc) {
+                    covered += 1;
+                    if nuc == edited {
+                        edits += 1;
+                        let pos = start + pos as u64;
+                        cluster = Some(cluster.map_or(pos..pos + 1, |x: Range<Position>| x.start..pos + 1));
+                    }
+                }
+                if edits < self.minedits || (edits as f32) < self.mindensity * covered as f32 {
+                    continue;
+                }
+
+                if self.track {
+                    for &(pos, nuc) in bases {
+                        self.counts[ind][pos as usize][nuc] += 1;
+                    }
+                }
+                let cluster = cluster.unwrap();
+                self.batch.reads.push(HyperEditedRead { name: name.clone(), trstrand, cluster, edits, covered });
+            }
+        }
+
+        // Only sites with mismatches are reported
+        if self.track {
+            for (pos, refnuc) in reference.iter().enumerate() {
+                for (ind, &(trstrand, ..)) in editing.iter().enumerate() {
+                    let sequenced = self.counts[ind][pos];
+                    if sequenced.mismatches(*refnuc) > 0 {
+                        let pos = start + pos as u64;
+                        self.batch.sites.push(HyperEditedSite { pos, trstrand, refnuc: *refnuc, sequenced });
+                    }
+                }
+            }
+        }
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        &self.batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+    use serde_test::{assert_ser_tokens, Token};
+
+    use crate::core::read::MockRead;
+    use crate::core::refpred::{PredNucleotide, RefEngineResult};
+    use crate::core::rpileup::ncounter::filters::MockReadsFilter;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct DummyRef(Vec<PredNucleotide>, Vec<Nucleotide>);
+
+    impl RefEngine for DummyRef {
+        fn run(&mut self, _: &str, range: Range<Position>, sequenced: &[NucCounts]) {
+            assert_eq!(range.end - range.start, sequenced.len() as u64);
+        }
+
+        fn results(&self) -> RefEngineResult<'_> {
+            RefEngineResult { predicted: &self.0, reference: &self.1, quality: None }
+        }
+    }
+
+    fn mock(name: &'static str, pos: i64, seq: &'static str) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_name().return_const(name.as_bytes().to_vec());
+        read.expect_contig().return_const("1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(vec![Cigar::Match(seq.len() as u32)]).into_view(pos));
+        read.expect_strand().return_const(ReqStrand::Forward);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read
+    }
+
+    #[test]
+    fn collide() {
+        use Nucleotide::*;
+        // The predicted reference is ignored
+        let reference = vec![A, A, C, A, T, A, A, T, G, A];
+        let predicted = vec![PredNucleotide::Homozygous(G); 10];
+
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_read_ok().return_const(true);
+        filter.expect_is_base_ok().return_const(true);
+        let base = BaseNucCounter::new(10, filter, 0, 0);
+        let mut dummy =
+            HyperEditCollider::new(base, Box::new(DummyRef(predicted, reference)), 3, 0.55).with_track(true);
+
+        let mut workload =
+            SiteWorkload::from_intervals(vec![Interval::new("1".into(), 100..110)], 10, None::<Vec<Interval>>);
+        dummy.reset(workload.pop().unwrap());
+
+        // Reference:  AACATAATGA
+        for read in [
+            // 4 of 6 A's are edited
+            mock("read1", 100, "GACGTGGTGA"),
+            // 3 of 5 A's are edited, a single T>C is ignored
+            mock("read2", 100, "AGCGTGAC"),
+            // Not enough edits
+            mock("read3", 100, "GGCAT"),
+            // Low density: 3 of 6 A's are edited
+            mock("read4", 100, "GGCGTAATGA"),
+            // Too few edits inside the workload
+            mock("read5", 105, "GGTGAGGG"),
+        ] {
+            dummy.collide(&read);
+        }
+        dummy.finalize();
+
+        let result = dummy.result();
+        assert_eq!(result.contig, "1");
+        assert_eq!(
+            result.reads,
+            [
+                HyperEditedRead {
+                    name: b"read1".to_vec(),
+                    trstrand: ReqStrand::Forward,
+                    cluster: 100..107,
+                    edits: 4,
+                    covered: 6
+                },
+                HyperEditedRead {
+                    name: b"read2".to_vec(),
+                    trstrand: ReqStrand::Forward,
+                    cluster: 101..106,
+                    edits: 3,
+                    covered: 5
+                },
+            ]
+        );
+
+        let site = |pos, refnuc, sequenced| HyperEditedSite { pos, trstrand: ReqStrand::Forward, refnuc, sequenced };
+        assert_eq!(
+            result.sites,
+            [
+                site(100, A, NucCounts::new(1, 0, 1, 0)),
+                site(101, A, NucCounts::new(1, 0, 1, 0)),
+                site(103, A, NucCounts::new(1, 0, 1, 0)),
+                site(105, A, NucCounts::new(0, 0, 2, 0)),
+                site(106, A, NucCounts::new(1, 0, 1, 0)),
+                site(107, T, NucCounts::new(0, 1, 0, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize() {
+        let read = HyperEditedRead {
+            name: b"read".to_vec(),
+            trstrand: ReqStrand::Reverse,
+            cluster: 10..25,
+            edits: 5,
+            covered: 20,
+        };
+        assert_ser_tokens(
+            &SerializeHyperEditedRead { contig: "chr1", read: &read },
+            &[
+                Token::Struct { name: "HyperEditedRead", len: 8 },
+                Token::Str("name"),
+                Token::Str("read"),
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("trstrand"),
+                Token::Str("-"),
+                Token::Str("start"),
+                Token::U64(10),
+                Token::Str("end"),
+                Token::U64(25),
+                Token::Str("edits"),
+                Token::U32(5),
+                Token::Str("covered"),
+                Token::U32(20),
+                Token::Str("density"),
+                Token::F32(0.25),
+                Token::StructEnd,
+            ],
+        );
+
+        let site = HyperEditedSite {
+            pos: 10,
+            trstrand: ReqStrand::Reverse,
+            refnuc: Nucleotide::T,
+            sequenced: NucCounts::new(0, 3, 0, 1),
+        };
+        assert_ser_tokens(
+            &SerializeHyperEditedSite { contig: "chr1", site: &site },
+            &[
+                Token::Struct { name: "HyperEditedSite", len: 8 },
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("pos"),
+                Token::U64(10),
+                Token::Str("trstrand"),
+                Token::Str("-"),
+                Token::Str("refnuc"),
+                Token::Str("T"),
+                Token::Str("A"),
+                Token::U32(0),
+                Token::Str("C"),
+                Token::U32(3),
+                Token::Str("G"),
+                Token::U32(0),
+                Token::Str("T"),
+                Token::U32(1),
+                Token::StructEnd,
+            ],
+        );
+    }
+}
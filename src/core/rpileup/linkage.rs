@@ -0,0 +1,425 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::Arc;
+
+use bio_types::genome::{AbstractInterval, Position};
+use bio_types::strand::{Same, Strand};
+use csv::Writer;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::core::dna::Nucleotide;
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::cnt::BaseNucCounter;
+use crate::core::rpileup::ncounter::filters::ReadsFilter;
+use crate::core::rpileup::ReadsCollider;
+use crate::core::stranding::deduce::StrandDeducer;
+use crate::core::workload::{LinkageSite, LinkageWorkload};
+
+// 2x2 table of fragments edited/unedited at the first and the second site
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LinkageCounts {
+    // [first edited, first unedited] x [second edited, second unedited]
+    pub table: [[u32; 2]; 2],
+}
+
+impl LinkageCounts {
+    #[inline]
+    pub fn add(&mut self, first: bool, second: bool) {
+        self.table[!first as usize][!second as usize] += 1;
+    }
+
+    #[inline]
+    pub fn total(&self) -> u32 {
+        self.table.iter().flatten().sum()
+    }
+
+    // Phi coefficient, None if any of the table margins is zero
+    pub fn phi(&self) -> Option<f64> {
+        let [[a, b], [c, d]] = self.table.map(|x| x.map(|x| x as f64));
+        let margins = (a + b) * (c + d) * (a + c) * (b + d);
+        if margins == 0.0 {
+            return None;
+        }
+        Some((a * d - b * c) / margins.sqrt())
+    }
+
+    #[inline]
+    pub fn r2(&self) -> Option<f64> {
+        self.phi().map(|x| x * x)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SiteLinkage {
+    pub first: Position,
+    pub second: Position,
+    pub trstrand: Strand,
+    pub counts: LinkageCounts,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkageBatch {
+    pub contig: String,
+    pub pairs: Vec<SiteLinkage>,
+}
+
+impl LinkageBatch {
+    pub fn to_csv<W: Write>(&self, writer: &mut Writer<W>) -> csv::Result<()> {
+        for pair in &self.pairs {
+            writer.serialize(SerializeSiteLinkage { contig: &self.contig, pair })?;
+        }
+        Ok(())
+    }
+}
+
+struct SerializeSiteLinkage<'a> {
+    contig: &'a str,
+    pair: &'a SiteLinkage,
+}
+
+impl Serialize for SerializeSiteLinkage<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let [[both, first], [second, none]] = self.pair.counts.table;
+        let mut state = serializer.serialize_struct("SiteLinkage", 10)?;
+        state.serialize_field("contig", self.contig)?;
+        state.serialize_field("pos1", &self.pair.first)?;
+        state.serialize_field("pos2", &self.pair.second)?;
+        state.serialize_field("trstrand", self.pair.trstrand.strand_symbol())?;
+        state.serialize_field("edited:both", &both)?;
+        state.serialize_field("edited:pos1", &first)?;
+        state.serialize_field("edited:pos2", &second)?;
+        state.serialize_field("edited:none", &none)?;
+        state.serialize_field("phi", &self.pair.counts.phi())?;
+        state.serialize_field("r2", &self.pair.counts.r2())?;
+        state.end()
+    }
+}
+
+// Editing allele of the site (A->G, T->C), None if the site can't be edited on its transcription strand
+fn edited(site: &LinkageSite) -> Option<Nucleotide> {
+    match (site.refnuc, site.trstrand) {
+        (Nucleotide::A, Strand::Forward | Strand::Unknown) => Some(Nucleotide::G),
+        (Nucleotide::T, Strand::Reverse | Strand::Unknown) => Some(Nucleotide::C),
+        _ => None,
+    }
+}
+
+// Co-occurrence of edits at pairs of nearby sites on the same read or read pair (fragment).
+// Bases are either unedited (reference) or edited (editing allele), other bases and fragments discordant
+// at a site are skipped. With a strand deducer, reads are paired only with sites on the same strand.
+#[derive(Clone)]
+pub struct LinkageCollider<R: AlignedRead, Filter: ReadsFilter<R>> {
+    base: BaseNucCounter<R, Filter>,
+    window: u64,
+    deductor: Option<Arc<dyn Fn(&R) -> Strand + Send + Sync>>,
+    sites: Vec<LinkageSite>,
+    owned: usize,
+    // Observed sites for each fragment: site index + whether it is edited
+    fragments: HashMap<Vec<u8>, Vec<(u32, bool)>>,
+    observed: Vec<(u32, bool)>,
+    batch: LinkageBatch,
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> LinkageCollider<R, Filter> {
+    pub fn new(base: BaseNucCounter<R, Filter>, window: u64) -> Self {
+        Self {
+            base: base.with_read_bases(true),
+            window,
+            deductor: None,
+            sites: vec![],
+            owned: 0,
+            fragments: HashMap::new(),
+            observed: vec![],
+            batch: Default::default(),
+        }
+    }
+
+    pub fn with_strand_deducer<Deductor>(mut self, deductor: Deductor) -> Self
+    where
+        R: 'static,
+        Deductor: StrandDeducer + Send + Sync + 'static,
+    {
+        self.deductor = Some(Arc::new(move |read: &R| deductor.deduce(read)));
+        self
+    }
+}
+
+impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for LinkageCollider<R, Filter> {
+    type ColliderResult = &'a LinkageBatch;
+    type Workload = LinkageWorkload;
+
+    fn reset(&mut self, info: Self::Workload) {
+        let (interval, sites, owned) = info.dissolve();
+        self.batch.contig = interval.contig().to_owned();
+        self.batch.pairs.clear();
+        self.base.reset(interval);
+        self.sites = sites;
+        self.owned = owned;
+        self.fragments.clear();
+    }
+
+    fn source(&mut self, index: usize) {
+        self.base.source(index);
+    }
+
+    fn collide(&mut self, read: &R) {
+        self.base.count(read);
+
+        let start = self.base.interval().range().start;
+        let strand = self.deductor.as_ref().map(|x| x(read));
+        self.observed.clear();
+        for &(pos, nuc) in self.base.read_bases() {
+            let (pos, nuc) = (start + pos as u64, Nucleotide::from(nuc));
+            let first = self.sites.partition_point(|x| x.pos < pos);
+            for (ind, site) in self.sites.iter().enumerate().skip(first).take_while(|(_, x)| x.pos == pos) {
+                if strand.map_or(false, |x| x != site.trstrand) {
+                    continue;
+                }
+                match edited(site) {
+                    Some(edited) if nuc == edited => self.observed.push((ind as u32, true)),
+                    Some(_) if nuc == site.refnuc => self.observed.push((ind as u32, false)),
+                    _ => {}
+                }
+            }
+        }
+        if !self.observed.is_empty() {
+            self.fragments.entry(read.name().to_vec()).or_default().extend(self.observed.drain(..));
+        }
+    }
+
+    fn finalize(&mut self) {
+        let mut tables: BTreeMap<(u32, u32), LinkageCounts> = BTreeMap::new();
+        for (_, mut observed) in self.fragments.drain() {
+            // Sites covered by both mates must be concordant
+            observed.sort_unstable();
+            observed.dedup();
+            let discordant = |ind: usize, x: &[(u32, bool)]| {
+                (ind > 0 && x[ind - 1].0 == x[ind].0) || (ind + 1 < x.len() && x[ind + 1].0 == x[ind].0)
+            };
+            let observed: Vec<(u32, bool)> =
+                (0..observed.len()).filter(|&ind| !discordant(ind, &observed)).map(|ind| observed[ind]).collect();
+
+            for (ind, &(first, edited)) in observed.iter().enumerate() {
+                let site = &self.sites[first as usize];
+                if first as usize >= self.owned {
+                    break;
+                }
+                for &(second, coedited) in &observed[ind + 1..] {
+                    let other = &self.sites[second as usize];
+                    if other.pos - site.pos > self.window {
+                        break;
+                    }
+                    if other.pos != site.pos && other.trstrand.same(&site.trstrand) {
+                        tables.entry((first, second)).or_default().add(edited, coedited);
+                    }
+                }
+            }
+        }
+
+        self.batch.pairs = tables
+            .into_iter()
+            .map(|((first, second), counts)| {
+                let (first, second) = (&self.sites[first as usize], &self.sites[second as usize]);
+                SiteLinkage { first: first.pos, second: second.pos, trstrand: first.trstrand, counts }
+            })
+            .collect();
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        &self.batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::strand::ReqStrand;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+    use serde_test::{assert_ser_tokens, Token};
+
+    use crate::core::read::MockRead;
+    use crate::core::rpileup::ncounter::filters::MockReadsFilter;
+    use crate::core::stranding::deduce::{DeduceStrandByDesign, StrandSpecificExperimentDesign};
+
+    use super::*;
+
+    fn mock(name: &'static str, pos: i64, seq: &'static str, strand: ReqStrand) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_name().return_const(name.as_bytes().to_vec());
+        read.expect_contig().return_const("1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(vec![Cigar::Match(seq.len() as u32)]).into_view(pos));
+        read.expect_strand().return_const(strand);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read
+    }
+
+    #[test]
+    fn counts() {
+        let mut dummy = LinkageCounts::default();
+        assert_eq!(dummy.phi(), None);
+        for (first, second) in [(true, true), (true, true), (false, false), (false, false), (true, false)] {
+            dummy.add(first, second);
+        }
+        assert_eq!(dummy.table, [[2, 1], [0, 2]]);
+        assert_eq!(dummy.total(), 5);
+        // (2 * 2 - 1 * 0) / sqrt(3 * 2 * 2 * 3)
+        assert!((dummy.phi().unwrap() - 4.0 / 6.0).abs() < 1e-9);
+        assert!((dummy.r2().unwrap() - 4.0 / 9.0).abs() < 1e-9);
+
+        // Perfect negative linkage
+        let dummy = LinkageCounts { table: [[0, 3], [3, 0]] };
+        assert_eq!(dummy.phi(), Some(-1.0));
+        assert_eq!(dummy.r2(), Some(1.0));
+    }
+
+    fn site(pos: Position, trstrand: Strand, refnuc: Nucleotide) -> LinkageSite {
+        LinkageSite { contig: "1".into(), pos, trstrand, refnuc }
+    }
+
+    fn filter() -> MockReadsFilter<MockRead> {
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_read_ok().return_const(true);
+        filter.expect_is_base_ok().return_const(true);
+        filter
+    }
+
+    #[test]
+    fn edited() {
+        for (refnuc, trstrand, expected) in [
+            (Nucleotide::A, Strand::Forward, Some(Nucleotide::G)),
+            (Nucleotide::A, Strand::Unknown, Some(Nucleotide::G)),
+            (Nucleotide::A, Strand::Reverse, None),
+            (Nucleotide::T, Strand::Reverse, Some(Nucleotide::C)),
+            (Nucleotide::T, Strand::Unknown, Some(Nucleotide::C)),
+            (Nucleotide::T, Strand::Forward, None),
+            (Nucleotide::C, Strand::Forward, None),
+            (Nucleotide::Unknown, Strand::Unknown, None),
+        ] {
+            assert_eq!(super::edited(&site(10, trstrand, refnuc)), expected);
+        }
+    }
+
+    #[test]
+    fn collide() {
+        let sites = vec![
+            site(10, Strand::Forward, Nucleotide::A),
+            site(12, Strand::Forward, Nucleotide::A),
+            site(12, Strand::Reverse, Nucleotide::A),
+            site(15, Strand::Forward, Nucleotide::A),
+            // Can't be edited on the forward strand
+            site(16, Strand::Forward, Nucleotide::T),
+            site(30, Strand::Forward, Nucleotide::A),
+        ];
+        let mut workload = LinkageWorkload::from_sites(sites, 10, 100);
+        assert_eq!(workload.len(), 1);
+
+        let mut dummy = LinkageCollider::new(BaseNucCounter::new(21, filter(), 0, 0), 10);
+        dummy.reset(workload.pop().unwrap());
+
+        // Sites: 10(A), 12(A), 15(A)
+        let fwd = ReqStrand::Forward;
+        for read in [
+            mock("1", 10, "GAGAAGC", fwd),
+            mock("2", 10, "GAGAAAC", fwd),
+            mock("3", 10, "AAAAAAT", fwd),
+            mock("4", 10, "AAGAAGC", fwd),
+            // Mates: concordant at 12, discordant at 15
+            mock("5", 10, "GAGAAA", fwd),
+            mock("5", 12, "GAAG", fwd),
+            // Neither reference nor editing allele at 10 and 12
+            mock("7", 10, "CATAAG", fwd),
+            // Too far from other sites
+            mock("6", 25, "GAGAAGGG", fwd),
+        ] {
+            dummy.collide(&read);
+        }
+        dummy.finalize();
+
+        let result = dummy.result();
+        assert_eq!(result.contig, "1");
+        let pair =
+            |first, second, trstrand, table| SiteLinkage { first, second, trstrand, counts: LinkageCounts { table } };
+        assert_eq!(
+            result.pairs,
+            [
+                pair(10, 12, Strand::Forward, [[3, 0], [1, 1]]),
+                pair(10, 15, Strand::Forward, [[1, 1], [1, 1]]),
+                pair(12, 15, Strand::Forward, [[2, 1], [0, 1]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn stranded() {
+        let sites = vec![
+            site(10, Strand::Forward, Nucleotide::A),
+            site(10, Strand::Reverse, Nucleotide::T),
+            site(12, Strand::Forward, Nucleotide::A),
+            site(12, Strand::Reverse, Nucleotide::T),
+        ];
+        let mut workload = LinkageWorkload::from_sites(sites, 10, 100);
+
+        let deductor = DeduceStrandByDesign::new(StrandSpecificExperimentDesign::Same);
+        let mut dummy = LinkageCollider::new(BaseNucCounter::new(3, filter(), 0, 0), 10).with_strand_deducer(deductor);
+        dummy.reset(workload.pop().unwrap());
+
+        // Reverse reads are not paired with the forward sites and vice versa
+        for read in [
+            mock("1", 10, "GAG", ReqStrand::Forward),
+            mock("2", 10, "AAA", ReqStrand::Forward),
+            mock("3", 10, "CAC", ReqStrand::Reverse),
+            mock("4", 10, "GAG", ReqStrand::Reverse),
+            mock("5", 10, "TAC", ReqStrand::Forward),
+        ] {
+            dummy.collide(&read);
+        }
+        dummy.finalize();
+
+        let pair = |trstrand, table| SiteLinkage { first: 10, second: 12, trstrand, counts: LinkageCounts { table } };
+        assert_eq!(
+            dummy.result().pairs,
+            [pair(Strand::Forward, [[1, 0], [0, 1]]), pair(Strand::Reverse, [[1, 0], [0, 0]])]
+        );
+    }
+
+    #[test]
+    fn serialize() {
+        let pair = SiteLinkage {
+            first: 10,
+            second: 25,
+            trstrand: Strand::Reverse,
+            counts: LinkageCounts { table: [[1, 0], [0, 1]] },
+        };
+        assert_ser_tokens(
+            &SerializeSiteLinkage { contig: "chr1", pair: &pair },
+            &[
+                Token::Struct { name: "SiteLinkage", len: 10 },
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("pos1"),
+                Token::U64(10),
+                Token::Str("pos2"),
+                Token::U64(25),
+                Token::Str("trstrand"),
+                Token::Str("-"),
+                Token::Str("edited:both"),
+                Token::U32(1),
+                Token::Str("edited:pos1"),
+                Token::U32(0),
+                Token::Str("edited:pos2"),
+                Token::U32(0),
+                Token::Str("edited:none"),
+                Token::U32(1),
+                Token::Str("phi"),
+                Token::Some,
+                Token::F64(1.0),
+                Token::Str("r2"),
+                Token::Some,
+                Token::F64(1.0),
+                Token::StructEnd,
+            ],
+        );
+    }
+}
@@ -2,8 +2,13 @@ use bio_types::genome::AbstractInterval;
 
 use crate::core::read::AlignedRead;
 
+pub mod downsample;
 pub mod hts;
+pub mod hyperedit;
+pub mod linkage;
 pub mod ncounter;
+pub mod perread;
+pub mod stream;
 
 // Pileup engine
 pub trait ReadsCollidingEngine<R: AlignedRead, Collider>
@@ -24,6 +29,8 @@ pub trait ReadsCollider<'a, R: AlignedRead> {
 
     // Reset the collider using the given Workload
     fn reset(&mut self, info: Self::Workload);
+    // Notify the collider that the following reads come from the given source (i.e. input file)
+    fn source(&mut self, _index: usize) {}
     // Run the collider
     fn collide(&mut self, read: &R);
     // Calculate the result
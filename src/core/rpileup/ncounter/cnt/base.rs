@@ -1,25 +1,233 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::ops::Range;
 
 use bio_types::genome::{AbstractInterval, Interval};
 use bio_types::strand::ReqStrand;
-use rust_htslib::bam::record::Cigar;
+use rust_htslib::bam::record::{Cigar, CigarStringView};
+use rust_htslib::htslib::{BAM_FPAIRED, BAM_FSECONDARY, BAM_FSUPPLEMENTARY};
 
-use crate::core::dna::NucCounts;
+use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, ReadStats, ReqNucleotide};
 use crate::core::read::AlignedRead;
 use crate::core::rpileup::ncounter::filters::ReadsFilter;
 
+use super::cellcnt::{CellBarcodes, CellNucCounter};
+
+// How to treat bases covered by both mates of the same fragment
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MatesOverlap {
+    // Count each mate independently (i.e. overlapping bases are counted twice)
+    Count,
+    // Count the overlapping base once, keeping the mate base with the higher quality
+    BestQuality,
+    // Count concordant bases once and drop discordant ones altogether
+    DropDiscordant,
+}
+
+// Weight of each counted base, i.e. the probability that the base is correct
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BaseWeights {
+    // Base-call probability derived from the Phred quality
+    Phred,
+    // Base-call probability multiplied by the mapping probability of the read
+    PhredMapq,
+}
+
+impl BaseWeights {
+    #[inline]
+    fn probability(phred: u8) -> f32 {
+        // 255 means that the quality is not available
+        if phred == 255 {
+            1.0
+        } else {
+            1.0 - 10f32.powf(-(phred as f32) / 10.0)
+        }
+    }
+
+    #[inline]
+    pub fn weight<R: AlignedRead>(&self, read: &R, seqpos: usize) -> f32 {
+        let weight = BaseWeights::probability(read.base_qual(seqpos));
+        match self {
+            BaseWeights::Phred => weight,
+            BaseWeights::PhredMapq => weight * BaseWeights::probability(read.mapq()),
+        }
+    }
+}
+
+// Number of aligned bases to skip on each side of the CIGAR events, zero to disable
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct CigarMask {
+    // Splice junctions (N)
+    pub splice: u16,
+    // Insertions and deletions (I/D)
+    pub indels: u16,
+    // Soft clipped read ends (S)
+    pub softclip: u16,
+}
+
+impl CigarMask {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.splice == 0 && self.indels == 0 && self.softclip == 0
+    }
+
+    // Mark aligned (M/=/X) bases of the read that must be skipped
+    fn apply(&self, cigar: &CigarStringView, masked: &mut Vec<bool>) {
+        let total = cigar
+            .iter()
+            .map(|x| match x {
+                Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => *ops as usize,
+                _ => 0,
+            })
+            .sum();
+        masked.clear();
+        masked.resize(total, false);
+
+        let mut aligned = 0;
+        for block in cigar.iter() {
+            let flank = match block {
+                Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => {
+                    aligned += *ops as usize;
+                    continue;
+                }
+                Cigar::RefSkip(_) => self.splice,
+                Cigar::Ins(_) | Cigar::Del(_) => self.indels,
+                Cigar::SoftClip(_) => self.softclip,
+                Cigar::HardClip(_) | Cigar::Pad(_) => continue,
+            } as usize;
+            let (start, end) = (aligned.saturating_sub(flank), min(aligned + flank, total));
+            masked[start..end].iter_mut().for_each(|x| *x = true);
+        }
+    }
+}
+
+// Query positions of the first and past-the-last bases that are not soft clipped
+fn aligned_span(cigar: &CigarStringView, len: usize) -> (usize, usize) {
+    let clipped = |iter: &mut dyn Iterator<Item = &Cigar>| {
+        iter.find_map(|x| match x {
+            Cigar::HardClip(_) => None,
+            Cigar::SoftClip(ops) => Some(*ops as usize),
+            _ => Some(0),
+        })
+        .unwrap_or(0)
+    };
+    (clipped(&mut cigar.iter()), len.saturating_sub(clipped(&mut cigar.iter().rev())))
+}
+
+// Decoded CIGAR of a read along with the derived per-read data
+#[derive(Clone, Default)]
+struct ReadCigar {
+    ops: Vec<Cigar>,
+    // Aligned (M/=/X) bases that must be skipped, empty if masking is disabled
+    masked: Vec<bool>,
+    // Aligned part of the read, i.e. without soft clipped bases
+    span: (usize, usize),
+    // State before each operation: (reference position, read position, aligned base index). Long reads only.
+    offsets: Vec<(i64, usize, usize)>,
+    // Past-the-end reference position of the alignment
+    end: i64,
+}
+
+impl ReadCigar {
+    fn fill<R: AlignedRead>(&mut self, read: &R, mask: &CigarMask, index: bool) {
+        let cigar = read.cigar();
+        self.ops.clear();
+        self.ops.extend(cigar.iter().copied());
+        self.masked.clear();
+        if !mask.is_empty() {
+            mask.apply(&cigar, &mut self.masked);
+        }
+        self.span = aligned_span(&cigar, read.len());
+        self.end = cigar.end_pos();
+
+        self.offsets.clear();
+        if index {
+            let (mut refpos, mut seqpos, mut alnpos) = (read.pos(), 0usize, 0usize);
+            for block in &self.ops {
+                self.offsets.push((refpos, seqpos, alnpos));
+                match block {
+                    Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => {
+                        refpos += *ops as i64;
+                        seqpos += *ops as usize;
+                        alnpos += *ops as usize;
+                    }
+                    Cigar::Del(ops) | Cigar::RefSkip(ops) => refpos += *ops as i64,
+                    Cigar::Ins(ops) | Cigar::SoftClip(ops) => seqpos += *ops as usize,
+                    Cigar::HardClip(_) | Cigar::Pad(_) => {}
+                }
+            }
+        }
+    }
+}
+
+// Long read identity: name, position, flags and length
+type ReadKey = (Vec<u8>, i64, u16, usize);
+
+// Single counted base of the read
+#[derive(Clone, Copy)]
+struct CountedBase {
+    nuc: ReqNucleotide,
+    strand: ReqStrand,
+    // Distance to the nearest end of the aligned part of the read
+    distance: u32,
+    weight: f32,
+}
+
+// Bases of the leftmost mate that fall inside the expected overlap: (roi position, quality, base)
+type MateBases = Vec<(u32, u8, CountedBase)>;
+
+// Fragment identity: name, source, whether the leftmost mate is the first in template, mates start positions
+type MateKey = (Vec<u8>, usize, bool, i64, i64);
+
+enum MateState {
+    // Mates overlap is not tracked for the read
+    Ignore,
+    // Leftmost mate: overlap start (roi coordinates) + cached overlapping bases
+    First(i64, MateBases),
+    // Rightmost mate: cached bases of the leftmost mate
+    Second(MateBases),
+}
+
 #[derive(Clone)]
 pub struct BaseNucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
     // Filtering parameters
     trim5: usize,
     trim3: usize,
     rfilter: Filter,
+    mates: MatesOverlap,
+    countindels: bool,
+    countstats: bool,
+    weights: Option<BaseWeights>,
+    fracmulti: bool,
+    trackbases: bool,
+    longreads: bool,
+    cigarmask: CigarMask,
+    // Optional sparse per-cell counts
+    cells: Option<CellNucCounter>,
     // Caches
     buffer: Vec<NucCounts>,
+    cigar: ReadCigar,
+    indels: Vec<IndelCounts>,
+    stats: Vec<ReadStats>,
+    weighted: Vec<FracNucCounts>,
     matched: Vec<Range<u32>>,
+    // Counted bases of the last read: (roi position, nucleotide)
+    readbases: Vec<(u32, ReqNucleotide)>,
+    // Indexed CIGARs of long reads that might overlap the next intervals
+    indexed: HashMap<ReadKey, ReadCigar>,
+    // Number of walked CIGAR operations
+    #[cfg(test)]
+    walked: usize,
     mapped: u32,
+    pending: HashMap<MateKey, MateBases>,
+    matekey: MateKey,
+    source: usize,
+    // Per-molecule caches, must be zeroed after each molecule
+    molecule: Vec<NucCounts>,
+    molstats: Vec<ReadStats>,
+    molweighted: Vec<FracNucCounts>,
+    molmatched: Vec<Range<u32>>,
     // Current interval
     interval: Interval,
     phantom: PhantomData<fn() -> R>,
@@ -31,14 +239,100 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
             rfilter: filter,
             interval: Interval::new("".to_string(), 0..0),
             buffer: Vec::with_capacity(maxbuf),
+            cigar: Default::default(),
+            indels: Vec::new(),
+            stats: Vec::new(),
+            weighted: Vec::new(),
             matched: Vec::with_capacity(20),
+            readbases: Vec::new(),
+            indexed: HashMap::new(),
+            #[cfg(test)]
+            walked: 0,
             mapped: 0,
+            pending: HashMap::new(),
+            matekey: Default::default(),
+            source: 0,
+            molecule: Vec::new(),
+            molstats: Vec::new(),
+            molweighted: Vec::new(),
+            molmatched: Vec::new(),
             trim5: trim5 as usize,
             trim3: trim3 as usize,
+            mates: MatesOverlap::Count,
+            countindels: false,
+            countstats: false,
+            weights: None,
+            fracmulti: false,
+            trackbases: false,
+            longreads: false,
+            cigarmask: Default::default(),
+            cells: None,
             phantom: Default::default(),
         }
     }
 
+    pub fn with_mates_overlap(mut self, mates: MatesOverlap) -> Self {
+        self.mates = mates;
+        self
+    }
+
+    pub fn with_indels(mut self, indels: bool) -> Self {
+        self.countindels = indels;
+        self
+    }
+
+    pub fn with_read_stats(mut self, stats: bool) -> Self {
+        self.countstats = stats;
+        self
+    }
+
+    pub fn with_base_weights(mut self, weights: Option<BaseWeights>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    // Count multi-mapping reads (NH:i:n) with the weight 1/n
+    pub fn with_multimappers_fraction(mut self, fracmulti: bool) -> Self {
+        self.fracmulti = fracmulti;
+        self
+    }
+
+    // Keep counted bases of the last processed read
+    pub fn with_read_bases(mut self, track: bool) -> Self {
+        self.trackbases = track;
+        self
+    }
+
+    // Index the CIGAR of each read once and reuse it for all overlapping intervals, jumping straight to the first
+    // overlapping operation. Pays off for long reads with many operations (e.g. spliced ONT/PacBio reads).
+    pub fn with_long_reads(mut self, longreads: bool) -> Self {
+        self.longreads = longreads;
+        self
+    }
+
+    pub fn with_cigar_mask(mut self, mask: CigarMask) -> Self {
+        self.cigarmask = mask;
+        self
+    }
+
+    // Count bases of whitelisted cells separately in addition to the total counts
+    pub fn with_cell_barcodes(mut self, barcodes: Option<CellBarcodes>) -> Self {
+        self.cells = barcodes.map(CellNucCounter::new);
+        self
+    }
+
+    // Counts the same bases, but without any optional data (e.g. for per-sample counts)
+    pub fn nucleotides_only(&self) -> Self {
+        self.clone()
+            .with_indels(false)
+            .with_read_stats(false)
+            .with_base_weights(None)
+            .with_multimappers_fraction(false)
+            .with_read_bases(false)
+            .with_long_reads(false)
+            .with_cell_barcodes(None)
+    }
+
     #[inline]
     pub fn interval(&self) -> &Interval {
         &self.interval
@@ -49,6 +343,70 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
         &self.buffer
     }
 
+    #[inline]
+    pub fn indels(&self) -> Option<&[IndelCounts]> {
+        if self.countindels {
+            Some(&self.indels)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn readstats(&self) -> Option<&[ReadStats]> {
+        if self.countstats {
+            Some(&self.stats)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn cells(&self) -> Option<&CellNucCounter> {
+        self.cells.as_ref()
+    }
+
+    #[inline]
+    pub fn cells_mut(&mut self) -> Option<&mut CellNucCounter> {
+        self.cells.as_mut()
+    }
+
+    #[inline]
+    pub fn read_bases(&self) -> &[(u32, ReqNucleotide)] {
+        &self.readbases
+    }
+
+    #[inline]
+    pub fn is_weighted(&self) -> bool {
+        self.weights.is_some() || self.fracmulti
+    }
+
+    #[inline]
+    pub fn is_multimappers_fraction(&self) -> bool {
+        self.fracmulti
+    }
+
+    // Weight of the whole read: 1/n for multi-mapping reads (NH:i:n) if requested, 1 otherwise
+    #[inline]
+    pub fn read_weight(&self, read: &R) -> f32 {
+        if !self.fracmulti {
+            return 1.0;
+        }
+        match read.aux_int(b"NH") {
+            Some(nh) if nh > 1 => 1.0 / nh as f32,
+            _ => 1.0,
+        }
+    }
+
+    #[inline]
+    pub fn weighted(&self) -> Option<&[FracNucCounts]> {
+        if self.is_weighted() {
+            Some(&self.weighted)
+        } else {
+            None
+        }
+    }
+
     #[inline]
     pub fn mapped(&self) -> u32 {
         self.mapped
@@ -60,15 +418,50 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
         debug_assert!(newlen > 0);
         self.buffer.clear();
         self.buffer.resize(newlen as usize, NucCounts::zeros());
+        if self.countindels {
+            self.indels.clear();
+            self.indels.resize(newlen as usize, Default::default());
+        }
+        if self.countstats {
+            self.stats.clear();
+            self.stats.resize(newlen as usize, Default::default());
+        }
+        if self.is_weighted() {
+            self.weighted.clear();
+            self.weighted.resize(newlen as usize, FracNucCounts::zeros());
+        }
+        if let Some(cells) = &mut self.cells {
+            cells.reset();
+        }
+
+        // Indexed reads ending before the new interval won't be seen again
+        if self.interval.contig() != interval.contig() {
+            self.indexed.clear();
+        } else {
+            let start = interval.range().start as i64;
+            self.indexed.retain(|_, x| x.end > start);
+        }
 
         self.mapped = 0;
+        self.pending.clear();
+        self.source = 0;
         self.interval = interval;
     }
 
+    // Following reads come from the given source (i.e. input file), mates are paired only within the same source
+    #[inline]
+    pub fn source(&mut self, index: usize) {
+        self.source = index;
+    }
+
     pub fn count(&mut self, read: &R) -> &[Range<u32>] {
         self.matched.clear();
+        self.readbases.clear();
 
         if self.is_record_ok(read) {
+            if let Some(cells) = &mut self.cells {
+                cells.set_current(cells.cell(read));
+            }
             self.implprocess(read);
 
             if !self.matched.is_empty() {
@@ -78,11 +471,203 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
         &self.matched
     }
 
+    // Count a group of reads originating from the same molecule (i.e. PCR duplicates).
+    // Each position is counted once using the majority consensus, positions with ties are skipped.
+    // Indels are taken from the first counted read of the molecule.
+    // Read stats of the molecule are the majority read strand and the mean distance to the read end.
+    // Weight of the molecule is the mean weight of the consensus bases.
+    // Per-cell counts are assigned to the first whitelisted cell among the molecule reads.
+    pub fn count_molecule(&mut self, reads: &[R]) -> &[Range<u32>] {
+        if self.molecule.len() != self.buffer.len() {
+            self.molecule.clear();
+            self.molecule.resize(self.buffer.len(), NucCounts::zeros());
+        }
+        if self.countstats && self.molstats.len() != self.buffer.len() {
+            self.molstats.clear();
+            self.molstats.resize(self.buffer.len(), Default::default());
+        }
+        if self.is_weighted() && self.molweighted.len() != self.buffer.len() {
+            self.molweighted.clear();
+            self.molweighted.resize(self.buffer.len(), FracNucCounts::zeros());
+        }
+
+        // Pileup the molecule reads in a separate buffer.
+        // Mates are grouped into the same molecule (see UMINucCounter) -> their overlap is resolved by the consensus.
+        std::mem::swap(&mut self.buffer, &mut self.molecule);
+        std::mem::swap(&mut self.stats, &mut self.molstats);
+        std::mem::swap(&mut self.weighted, &mut self.molweighted);
+        let mates = std::mem::replace(&mut self.mates, MatesOverlap::Count);
+        let countindels = self.countindels;
+        let cell = self.cells.as_mut().and_then(|cells| {
+            cells.set_current(None);
+            reads.iter().find_map(|x| cells.cell(x))
+        });
+
+        self.molmatched.clear();
+        self.readbases.clear();
+        for read in reads {
+            self.matched.clear();
+            if self.is_record_ok(read) {
+                self.implprocess(read);
+                self.molmatched.extend(self.matched.drain(..));
+                self.countindels = false;
+            }
+        }
+
+        self.mates = mates;
+        self.countindels = countindels;
+        std::mem::swap(&mut self.buffer, &mut self.molecule);
+        std::mem::swap(&mut self.stats, &mut self.molstats);
+        std::mem::swap(&mut self.weighted, &mut self.molweighted);
+
+        // Merge overlapping ranges
+        self.molmatched.sort_by_key(|x| x.start);
+        self.matched.clear();
+        for range in self.molmatched.drain(..) {
+            match self.matched.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => self.matched.push(range),
+            }
+        }
+
+        // Consensus
+        if let Some(cells) = &mut self.cells {
+            cells.set_current(cell);
+        }
+        for range in &self.matched {
+            for pos in range.start as usize..range.end as usize {
+                let cnts = std::mem::replace(&mut self.molecule[pos], NucCounts::zeros());
+                let stats = if self.countstats { std::mem::take(&mut self.molstats[pos]) } else { Default::default() };
+                let weights =
+                    if self.is_weighted() { std::mem::take(&mut self.molweighted[pos]) } else { Default::default() };
+                let (nuc, &max) = cnts.mostfreq();
+                let ties = [ReqNucleotide::A, ReqNucleotide::C, ReqNucleotide::G, ReqNucleotide::T]
+                    .into_iter()
+                    .filter(|x| cnts[*x] == max)
+                    .count();
+                if max > 0 && ties == 1 {
+                    self.buffer[pos][nuc] += 1;
+                    if let Some(cells) = &mut self.cells {
+                        cells.add(pos as u32, nuc);
+                    }
+                    if self.countstats {
+                        let strand = if stats.forward[nuc] >= stats.reverse[nuc] {
+                            ReqStrand::Forward
+                        } else {
+                            ReqStrand::Reverse
+                        };
+                        self.stats[pos].add(nuc, strand, (stats.distance[nuc] / max as u64) as u32);
+                    }
+                    if self.is_weighted() {
+                        self.weighted[pos][nuc] += weights[nuc] / max as f32;
+                    }
+                }
+            }
+        }
+
+        if !self.matched.is_empty() {
+            self.mapped += 1;
+        }
+        &self.matched
+    }
+
     #[inline]
     fn is_record_ok(&self, record: &R) -> bool {
         self.rfilter.is_read_ok(record) && record.contig() == self.interval.contig()
     }
 
+    fn mate_state(&mut self, read: &R) -> MateState {
+        if self.mates == MatesOverlap::Count {
+            return MateState::Ignore;
+        }
+        // Only primary alignments of paired reads are mates
+        let flags = read.flags();
+        if flags & BAM_FPAIRED as u16 == 0 || flags & (BAM_FSECONDARY | BAM_FSUPPLEMENTARY) as u16 != 0 {
+            return MateState::Ignore;
+        }
+        match read.mate_pos() {
+            None => MateState::Ignore,
+            Some(matepos) => {
+                // Key of the leftmost mate, reused to avoid allocations
+                self.matekey.0.clear();
+                self.matekey.0.extend_from_slice(read.name());
+                self.matekey.1 = self.source;
+                self.matekey.2 = !read.is_first();
+                self.matekey.3 = matepos;
+                self.matekey.4 = read.pos();
+
+                if let Some(cache) = self.pending.remove(&self.matekey) {
+                    MateState::Second(cache)
+                } else if matepos >= read.pos() {
+                    MateState::First(matepos - self.interval.range().start as i64, Vec::new())
+                } else {
+                    MateState::Ignore
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn add_base(&mut self, roipos: usize, base: &CountedBase) {
+        self.buffer[roipos][base.nuc] += 1;
+        if let Some(cells) = &mut self.cells {
+            cells.add(roipos as u32, base.nuc);
+        }
+        if self.countstats {
+            self.stats[roipos].add(base.nuc, base.strand, base.distance);
+        }
+        if self.is_weighted() {
+            self.weighted[roipos][base.nuc] += base.weight;
+        }
+    }
+
+    #[inline]
+    fn remove_base(&mut self, roipos: usize, base: &CountedBase) {
+        self.buffer[roipos][base.nuc] -= 1;
+        if let Some(cells) = &mut self.cells {
+            cells.remove(roipos as u32, base.nuc);
+        }
+        if self.countstats {
+            self.stats[roipos].remove(base.nuc, base.strand, base.distance);
+        }
+        if self.is_weighted() {
+            self.weighted[roipos][base.nuc] -= base.weight;
+        }
+    }
+
+    #[inline]
+    fn count_base(&mut self, read: &R, roipos: usize, seqpos: usize, base: CountedBase, mate: &mut MateState) {
+        match mate {
+            MateState::Ignore => self.add_base(roipos, &base),
+            MateState::First(start, cache) => {
+                self.add_base(roipos, &base);
+                if roipos as i64 >= *start {
+                    cache.push((roipos as u32, read.base_qual(seqpos), base));
+                }
+            }
+            MateState::Second(cache) => match cache.binary_search_by_key(&(roipos as u32), |x| x.0) {
+                // The base was already counted for the first mate
+                Ok(ind) => {
+                    let (_, prevqual, prev) = cache[ind];
+                    if base.nuc == prev.nuc {
+                        return;
+                    }
+                    match self.mates {
+                        MatesOverlap::BestQuality => {
+                            if read.base_qual(seqpos) > prevqual {
+                                self.remove_base(roipos, &prev);
+                                self.add_base(roipos, &base);
+                            }
+                        }
+                        MatesOverlap::DropDiscordant => self.remove_base(roipos, &prev),
+                        MatesOverlap::Count => unreachable!(),
+                    }
+                }
+                Err(_) => self.add_base(roipos, &base),
+            },
+        }
+    }
+
     fn implprocess(&mut self, read: &R) {
         let sequence = read.seq();
 
@@ -99,34 +684,75 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
             ReqStrand::Reverse => (self.trim3, read.len() - self.trim5),
         };
 
-        for block in read.cigar().iter() {
-            if roipos >= roisize || seqpos >= maxseqpos {
+        let mut mate = self.mate_state(read);
+        let (strand, readweight) = (*read.strand(), self.read_weight(read));
+
+        // Long reads are indexed once and taken from the cache for all subsequent intervals
+        let key = self.longreads.then(|| (read.name().to_vec(), read.pos(), read.flags(), read.len()));
+        let cigar = match key.as_ref().and_then(|x| self.indexed.remove(x)) {
+            Some(cigar) => cigar,
+            None => {
+                let mut cigar = std::mem::take(&mut self.cigar);
+                cigar.fill(read, &self.cigarmask, self.longreads);
+                cigar
+            }
+        };
+        let masking = !cigar.masked.is_empty();
+        // Index of the current aligned (M/=/X) base
+        let mut alnpos = 0usize;
+        // Aligned part of the read, i.e. without soft clipped bases
+        let (alnstart, alnend) = cigar.span;
+
+        // Skip all CIGAR operations that end before the roi
+        let mut first = 0;
+        if self.longreads && roipos < 0 && !cigar.offsets.is_empty() {
+            let start = self.interval.range().start as i64;
+            first = cigar.offsets.partition_point(|x| x.0 <= start).saturating_sub(1);
+            let (refpos, readpos, alnbase) = cigar.offsets[first];
+            (roipos, seqpos, alnpos) = (refpos - start, readpos, alnbase);
+        }
+
+        for block in cigar.ops.iter().skip(first) {
+            // Insertions right after the last position still belong to the roi
+            if roipos > roisize || seqpos >= maxseqpos {
                 break;
             }
+            #[cfg(test)]
+            {
+                self.walked += 1;
+            }
             match block {
                 Cigar::Match(ops) | Cigar::Equal(ops) | Cigar::Diff(ops) => {
                     // fast-end when possible
-                    let end = min(roisize as i64, roipos + *ops as i64);
+                    let blockend = roipos + *ops as i64;
+                    let end = min(roisize as i64, blockend);
                     // fast-forward when possible
                     if roipos < 0 {
                         let skip = min(roipos.abs() as u32, *ops);
                         roipos += skip as i64;
                         seqpos += skip as usize;
+                        alnpos += skip as usize;
                     }
 
                     let mut prevmatched: Option<u32> = None;
                     let start = roipos;
                     for _ in start..end {
                         debug_assert!(roipos < roisize);
-                        if seqpos >= minseqpos && seqpos < maxseqpos && self.rfilter.is_base_ok(read, seqpos) {
+                        if seqpos >= minseqpos
+                            && seqpos < maxseqpos
+                            && !(masking && cigar.masked[alnpos])
+                            && self.rfilter.is_base_ok(read, seqpos)
+                        {
                             debug_assert!(roipos >= 0);
                             // From the SAM specification: No assumptions can be made on the letter cases
-                            match sequence[seqpos as usize] {
-                                b'A' | b'a' => self.buffer[roipos as usize].A += 1,
-                                b'T' | b't' => self.buffer[roipos as usize].T += 1,
-                                b'G' | b'g' => self.buffer[roipos as usize].G += 1,
-                                b'C' | b'c' => self.buffer[roipos as usize].C += 1,
-                                _ => {}
+                            if let Ok(nuc) = ReqNucleotide::try_from(sequence[seqpos as usize]) {
+                                let distance = min(seqpos.saturating_sub(alnstart), alnend.saturating_sub(seqpos + 1));
+                                let weight = self.weights.map_or(readweight, |x| x.weight(read, seqpos) * readweight);
+                                let base = CountedBase { nuc, strand, distance: distance as u32, weight };
+                                if self.trackbases {
+                                    self.readbases.push((roipos as u32, nuc));
+                                }
+                                self.count_base(read, roipos as usize, seqpos, base, &mut mate);
                             }
                             if prevmatched.is_none() {
                                 prevmatched = Some(roipos as u32);
@@ -137,20 +763,56 @@ impl<R: AlignedRead, Filter: ReadsFilter<R>> BaseNucCounter<R, Filter> {
                         }
                         roipos += 1;
                         seqpos += 1;
+                        alnpos += 1;
                     }
                     if let Some(m) = prevmatched {
                         self.matched.push(m..roipos as u32);
                     }
+                    // The rest of the read is outside of the roi
+                    if blockend > roisize {
+                        break;
+                    }
                 }
-                Cigar::Del(ops) | Cigar::RefSkip(ops) => {
+                Cigar::Del(ops) => {
+                    if self.countindels && seqpos > minseqpos && seqpos < maxseqpos {
+                        for pos in max(roipos, 0)..min(roisize, roipos + *ops as i64) {
+                            self.indels[pos as usize].deletions += 1;
+                        }
+                    }
                     roipos += *ops as i64;
                 }
-                Cigar::SoftClip(ops) | Cigar::Ins(ops) => {
+                Cigar::RefSkip(ops) => {
+                    roipos += *ops as i64;
+                }
+                Cigar::Ins(ops) => {
+                    // Insertions are assigned to the preceding reference position
+                    let (anchor, end) = (roipos - 1, seqpos + *ops as usize);
+                    if self.countindels && anchor >= 0 && anchor < roisize && seqpos > minseqpos && end < maxseqpos {
+                        let inserted = sequence[seqpos..end].to_ascii_uppercase();
+                        self.indels[anchor as usize].insert(&inserted);
+                    }
+                    seqpos = end;
+                }
+                Cigar::SoftClip(ops) => {
                     seqpos += *ops as usize;
                 }
                 Cigar::HardClip(_) | Cigar::Pad(_) => {}
             }
         }
+
+        match key {
+            Some(key) => {
+                self.indexed.insert(key, cigar);
+            }
+            None => self.cigar = cigar,
+        }
+
+        if let MateState::First(_, cache) = mate {
+            if !cache.is_empty() {
+                let key = (read.name().to_vec(), self.source, read.is_first(), read.pos(), read.mate_pos().unwrap());
+                self.pending.insert(key, cache);
+            }
+        }
     }
 }
 
@@ -165,6 +827,7 @@ mod tests {
     use crate::core::dna::NucCounts;
     use crate::core::read::MockRead;
     use crate::core::rpileup::ncounter::filters::MockReadsFilter;
+    use crate::core::rpileup::ncounter::CellNucCounts;
 
     use super::*;
 
@@ -426,6 +1089,383 @@ mod tests {
         run((2, 0), 2..4, 1, "CGTA", ReqStrand::Reverse, vec![true], vec![M(4)], &[G(), Z()], &[0..1]);
     }
 
+    #[test]
+    fn mates_overlap() {
+        let mate = |pos: i64, matepos: i64, seq: &'static str, qual: Vec<u8>| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_mate_pos().return_const(Some(matepos));
+            read.expect_flags().return_const(BAM_FPAIRED as u16);
+            read.expect_is_first().return_const(pos < matepos);
+            read.expect_name().return_const(b"read".to_vec());
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(vec![M(4)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read.expect_base_qual().returning(move |i| qual[i]);
+            read
+        };
+
+        for (policy, expected) in [
+            (MatesOverlap::Count, [A(), C(), NucCounts::G(2), A() + T(), T(), T()]),
+            (MatesOverlap::BestQuality, [A(), C(), G(), A(), T(), T()]),
+            (MatesOverlap::DropDiscordant, [A(), C(), G(), Z(), T(), T()]),
+        ] {
+            let mut filter = MockReadsFilter::new();
+            filter.expect_is_base_ok().return_const(true);
+
+            let mut counter = BaseNucCounter::new(6, filter, 0, 0).with_mates_overlap(policy);
+            counter.reset(Interval::new("".into(), 0..6));
+
+            counter.implprocess(&mate(0, 2, "ACGT", vec![30; 4]));
+            counter.implprocess(&mate(2, 0, "GATT", vec![20, 40, 30, 30]));
+
+            assert_eq!(counter.buffer, expected);
+            assert!(counter.pending.is_empty());
+        }
+    }
+
+    #[test]
+    fn mates_identity() {
+        let mate = |pos: i64, matepos: i64, seq: &'static str, flags: u32, first: bool| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_mate_pos().return_const(Some(matepos));
+            read.expect_flags().return_const(flags as u16);
+            read.expect_is_first().return_const(first);
+            read.expect_name().return_const(b"read".to_vec());
+            read.expect_len().return_const(4usize);
+            read.expect_cigar().return_once(move || CigarString(vec![M(4)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read.expect_base_qual().return_const(30u8);
+            read
+        };
+        let overlap = [A(), C(), NucCounts::G(2), NucCounts::T(2), G(), T()];
+
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+        let mut counter = BaseNucCounter::new(6, filter, 0, 0).with_mates_overlap(MatesOverlap::DropDiscordant);
+
+        // Secondary and supplementary alignments are not mates
+        for flags in [BAM_FSECONDARY, BAM_FSUPPLEMENTARY] {
+            counter.reset(Interval::new("".into(), 0..6));
+            counter.implprocess(&mate(0, 2, "ACGT", BAM_FPAIRED | flags, true));
+            counter.implprocess(&mate(2, 0, "GTGT", BAM_FPAIRED, false));
+            assert_eq!(counter.buffer, overlap);
+            assert!(counter.pending.is_empty());
+        }
+
+        // Reads with the same name from different sources are not mates
+        counter.reset(Interval::new("".into(), 0..6));
+        counter.implprocess(&mate(0, 2, "ACGT", BAM_FPAIRED, true));
+        counter.source(1);
+        counter.implprocess(&mate(2, 0, "GTGT", BAM_FPAIRED, false));
+        assert_eq!(counter.buffer, overlap);
+        assert_eq!(counter.pending.len(), 1);
+
+        // Same for the reads that are both first in template
+        counter.reset(Interval::new("".into(), 0..6));
+        counter.implprocess(&mate(0, 2, "ACGT", BAM_FPAIRED, true));
+        counter.implprocess(&mate(2, 0, "GTGT", BAM_FPAIRED, true));
+        assert_eq!(counter.buffer, overlap);
+
+        // True mates
+        counter.reset(Interval::new("".into(), 0..6));
+        counter.implprocess(&mate(0, 2, "ACGT", BAM_FPAIRED, true));
+        counter.implprocess(&mate(2, 0, "GTGT", BAM_FPAIRED, false));
+        assert_eq!(counter.buffer, [A(), C(), G(), T(), G(), T()]);
+        assert!(counter.pending.is_empty());
+    }
+
+    #[test]
+    fn base_weights() {
+        let read = || {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(0);
+            read.expect_len().return_const(3usize);
+            read.expect_cigar().return_once(|| CigarString(vec![M(3)]).into_view(0));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(|| b"ACG".to_vec());
+            read.expect_base_qual().returning(|i| [10, 20, 255][i]);
+            read.expect_mapq().return_const(30u8);
+            read
+        };
+
+        for (weights, expected) in [
+            (None, None),
+            (Some(BaseWeights::Phred), Some([0.9, 0.99, 1.0])),
+            (Some(BaseWeights::PhredMapq), Some([0.9 * 0.999, 0.99 * 0.999, 0.999])),
+        ] {
+            let mut filter = MockReadsFilter::new();
+            filter.expect_is_base_ok().return_const(true);
+
+            let mut counter = BaseNucCounter::new(3, filter, 0, 0).with_base_weights(weights);
+            counter.reset(Interval::new("".into(), 0..3));
+            counter.implprocess(&read());
+            assert_eq!(counter.buffer, [A(), C(), G()]);
+
+            match (counter.weighted(), expected) {
+                (Some(weighted), Some(expected)) => {
+                    let weighted = [weighted[0].A, weighted[1].C, weighted[2].G];
+                    assert!(weighted.iter().zip(expected).all(|(x, y)| (x - y).abs() < 1e-6), "{:?}", weighted);
+                }
+                (None, None) => {}
+                _ => panic!("Weighted counts must be available only on request"),
+            }
+        }
+    }
+
+    #[test]
+    fn multimappers_fraction() {
+        let read = |nh: Option<i64>| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(0);
+            read.expect_len().return_const(2usize);
+            read.expect_cigar().return_once(|| CigarString(vec![M(2)]).into_view(0));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(|| b"AC".to_vec());
+            read.expect_base_qual().return_const(20u8);
+            read.expect_aux_int().return_const(nh);
+            read
+        };
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+
+        let mut counter = BaseNucCounter::new(2, filter, 0, 0).with_multimappers_fraction(true);
+        counter.reset(Interval::new("".into(), 0..2));
+        for nh in [Some(4), Some(1), None, Some(2)] {
+            counter.implprocess(&read(nh));
+        }
+        assert_eq!(counter.buffer, [NucCounts::A(4), NucCounts::C(4)]);
+        assert_eq!(counter.weighted().unwrap(), [FracNucCounts::A(2.75), FracNucCounts::C(2.75)]);
+
+        // Combined with base qualities
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+        let mut counter = BaseNucCounter::new(2, filter, 0, 0)
+            .with_multimappers_fraction(true)
+            .with_base_weights(Some(BaseWeights::Phred));
+        counter.reset(Interval::new("".into(), 0..2));
+        counter.implprocess(&read(Some(2)));
+        let weighted = counter.weighted().unwrap();
+        assert!((weighted[0].A - 0.495).abs() < 1e-6 && (weighted[1].C - 0.495).abs() < 1e-6);
+    }
+
+    #[test]
+    fn count_molecule() {
+        let read = |pos: i64, seq: &'static str| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_contig().return_const("".to_string());
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(vec![M(seq.len() as u32)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read
+        };
+
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_read_ok().return_const(true);
+        filter.expect_is_base_ok().return_const(true);
+
+        let mut counter = BaseNucCounter::new(5, filter, 0, 0);
+        counter.reset(Interval::new("".into(), 0..5));
+
+        // Majority consensus
+        let matched = counter.count_molecule(&[read(0, "ACGT"), read(0, "ACGA"), read(0, "AGGA")]);
+        assert_eq!(matched, [0..4]);
+        // Ties are skipped
+        let matched = counter.count_molecule(&[read(3, "AC"), read(3, "AG")]);
+        assert_eq!(matched, [3..5]);
+
+        assert_eq!(counter.buffer, [A(), C(), G(), NucCounts::A(2), Z()]);
+        assert_eq!(counter.mapped(), 2);
+        assert!(counter.molecule.iter().all(|x| *x == Z()));
+    }
+
+    #[test]
+    fn count_molecule_mates_overlap() {
+        let mate = |pos: i64, seq: &'static str| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_contig().return_const("".to_string());
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(vec![M(4)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read
+        };
+
+        for policy in [MatesOverlap::Count, MatesOverlap::BestQuality, MatesOverlap::DropDiscordant] {
+            let mut filter = MockReadsFilter::new();
+            filter.expect_is_read_ok().return_const(true);
+            filter.expect_is_base_ok().return_const(true);
+
+            let mut counter = BaseNucCounter::new(6, filter, 0, 0).with_mates_overlap(policy);
+            counter.reset(Interval::new("".into(), 0..6));
+
+            // Overlapping mates of the same molecule are counted once, discordant bases are dropped
+            let matched = counter.count_molecule(&[mate(0, "ACGT"), mate(2, "GATT")]);
+            assert_eq!(matched, [0..6]);
+            assert_eq!(counter.buffer, [A(), C(), G(), Z(), T(), T()]);
+            assert_eq!(counter.mates, policy);
+            assert!(counter.pending.is_empty());
+        }
+    }
+
+    #[test]
+    fn cells() {
+        let read = |pos: i64, seq: &'static str, barcode: &'static str| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_contig().return_const("".to_string());
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(vec![M(seq.len() as u32)]).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read.expect_aux_str().return_const(barcode.as_bytes().to_vec());
+            read
+        };
+
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_read_ok().return_const(true);
+        filter.expect_is_base_ok().return_const(true);
+
+        let barcodes = CellBarcodes::new(b"CB".to_vec(), vec![b"AAA".to_vec(), b"BBB".to_vec()]);
+        let mut counter = BaseNucCounter::new(3, filter, 0, 0).with_cell_barcodes(Some(barcodes));
+        counter.reset(Interval::new("".into(), 0..3));
+
+        counter.count(&read(0, "AC", "AAA"));
+        counter.count(&read(0, "AG", "BBB"));
+        // Not whitelisted -> counted in total only
+        counter.count(&read(1, "CT", "CCC"));
+        // Consensus of the molecule is assigned to its cell
+        counter.count_molecule(&[read(0, "AA", "BBB"), read(0, "AA", "BBB"), read(0, "TA", "BBB")]);
+
+        assert_eq!(counter.buffer, [NucCounts::A(3), NucCounts::new(1, 2, 1, 0), T()]);
+        let cells = counter.cells_mut().unwrap();
+        cells.split(0, &[0..3]);
+        assert_eq!(
+            cells.range(0),
+            [
+                CellNucCounts { cell: 0, offset: 0, cnts: A() },
+                CellNucCounts { cell: 1, offset: 0, cnts: NucCounts::A(2) },
+                CellNucCounts { cell: 0, offset: 1, cnts: C() },
+                CellNucCounts { cell: 1, offset: 1, cnts: NucCounts::new(1, 0, 1, 0) },
+            ]
+        );
+    }
+
+    #[test]
+    fn indels() {
+        let read = |pos: i64, seq: &'static str, cigar: Vec<Cigar>| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(cigar).into_view(pos));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read
+        };
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+
+        let mut counter = BaseNucCounter::new(6, filter, 0, 0).with_indels(true);
+        counter.reset(Interval::new("".into(), 0..6));
+
+        counter.implprocess(&read(0, "ACgtGT", vec![M(2), I(2), D(2), M(2)]));
+        counter.implprocess(&read(1, "CGTA", vec![M(1), I(1), M(1), D(1), M(2)]));
+        // Insertion after the last position + deletion outside of the roi
+        counter.implprocess(&read(4, "ACGTA", vec![M(2), I(1), D(3), M(2)]));
+        // Indels at the read edges are ignored
+        counter.implprocess(&read(2, "TTA", vec![I(1), M(2), D(1), I(1)]));
+
+        let indels = counter.indels().unwrap();
+        let deletions = indels.iter().map(|x| x.deletions).collect::<Vec<_>>();
+        assert_eq!(deletions, [0, 0, 1, 2, 0, 0]);
+        let insertions = indels.iter().map(|x| x.inserted_sequences()).collect::<Vec<_>>();
+        assert_eq!(insertions, ["", "G:1;GT:1", "", "", "", "G:1"]);
+    }
+
+    #[test]
+    fn cigar_mask() {
+        let read = || {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(0);
+            read.expect_len().return_const(10usize);
+            read.expect_cigar().return_once(|| CigarString(vec![S(1), M(4), N(2), M(2), D(1), M(3)]).into_view(0));
+            read.expect_strand().return_const(ReqStrand::Forward);
+            read.expect_seq().returning(|| b"NACGTACGTA".to_vec());
+            read
+        };
+
+        for (mask, excounts, exmatch) in [
+            (
+                CigarMask::default(),
+                vec![A(), C(), G(), T(), Z(), Z(), A(), C(), Z(), G(), T(), A()],
+                vec![0..4, 6..8, 9..12],
+            ),
+            (
+                CigarMask { splice: 1, indels: 2, softclip: 1 },
+                vec![Z(), C(), G(), Z(), Z(), Z(), Z(), Z(), Z(), Z(), Z(), A()],
+                vec![1..3, 11..12],
+            ),
+            (
+                CigarMask { splice: 0, indels: 0, softclip: 3 },
+                vec![Z(), Z(), Z(), T(), Z(), Z(), A(), C(), Z(), G(), T(), A()],
+                vec![3..4, 6..8, 9..12],
+            ),
+        ] {
+            let mut filter = MockReadsFilter::new();
+            filter.expect_is_base_ok().return_const(true);
+
+            let mut counter = BaseNucCounter::new(12, filter, 0, 0).with_cigar_mask(mask);
+            counter.reset(Interval::new("".into(), 0..12));
+            counter.implprocess(&read());
+
+            assert_eq!(counter.buffer, excounts);
+            assert_eq!(counter.matched, exmatch);
+        }
+    }
+
+    #[test]
+    fn read_stats() {
+        let read = |pos: i64, seq: &'static str, strand: ReqStrand, cigar: Vec<Cigar>| {
+            let mut read = MockRead::new();
+            read.expect_pos().return_const(pos);
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().return_once(move || CigarString(cigar).into_view(pos));
+            read.expect_strand().return_const(strand);
+            read.expect_seq().returning(move || seq.as_bytes().to_vec());
+            read
+        };
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_base_ok().return_const(true);
+
+        let mut counter = BaseNucCounter::new(5, filter, 0, 0).with_read_stats(true);
+        counter.reset(Interval::new("".into(), 0..5));
+        // Distance is measured from the ends of the aligned part of the read
+        counter.implprocess(&read(0, "TTACGTAG", ReqStrand::Reverse, vec![H(3), S(2), M(5), S(1)]));
+        counter.implprocess(&read(1, "CGA", ReqStrand::Forward, vec![M(3)]));
+
+        let mut expected = vec![ReadStats::default(); 5];
+        for (pos, nuc, strand, distance) in [
+            (0, ReqNucleotide::A, ReqStrand::Reverse, 0),
+            (1, ReqNucleotide::C, ReqStrand::Reverse, 1),
+            (2, ReqNucleotide::G, ReqStrand::Reverse, 2),
+            (3, ReqNucleotide::T, ReqStrand::Reverse, 1),
+            (4, ReqNucleotide::A, ReqStrand::Reverse, 0),
+            (1, ReqNucleotide::C, ReqStrand::Forward, 0),
+            (2, ReqNucleotide::G, ReqStrand::Forward, 1),
+            (3, ReqNucleotide::A, ReqStrand::Forward, 0),
+        ] {
+            expected[pos].add(nuc, strand, distance);
+        }
+        assert_eq!(counter.readstats().unwrap(), expected);
+        assert_eq!(counter.buffer, [A(), NucCounts::C(2), NucCounts::G(2), NucCounts::new(1, 0, 0, 1), A()]);
+    }
+
     #[test]
     fn is_record_ok() {
         let contig = "".to_string();
@@ -446,4 +1486,103 @@ mod tests {
             assert_eq!(dummy.is_record_ok(&mut read), result)
         }
     }
+
+    #[test]
+    fn long_reads() {
+        // Synthetic spliced long read: soft clips + ~300 aligned blocks separated by introns and indels
+        let mut state = 17u64;
+        let mut random = |max: u32| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as u32 % max + 1
+        };
+        let mut cigar = vec![S(random(20))];
+        for _ in 0..300 {
+            cigar.push(M(random(60)));
+            cigar.push(match random(4) {
+                1 => N(random(2000)),
+                2 => D(random(3)),
+                3 => I(random(3)),
+                _ => X(random(5)),
+            });
+        }
+        cigar.push(M(random(60)));
+        cigar.push(S(random(20)));
+
+        let seqlen: u32 = cigar
+            .iter()
+            .map(|x| match x {
+                Cigar::Match(ops) | Cigar::Diff(ops) | Cigar::Ins(ops) | Cigar::SoftClip(ops) => *ops,
+                _ => 0,
+            })
+            .sum();
+        let seq: Vec<u8> = (0..seqlen).map(|_| b"ACGT"[random(4) as usize - 1]).collect();
+        let span: u32 = cigar
+            .iter()
+            .map(|x| match x {
+                Cigar::Match(ops) | Cigar::Diff(ops) | Cigar::Del(ops) | Cigar::RefSkip(ops) => *ops,
+                _ => 0,
+            })
+            .sum();
+
+        let bins = (0..1000 + span as u64).step_by(500);
+        let read = |decoded: usize| {
+            let (cigar, seq) = (cigar.clone(), seq.clone());
+            let mut read = MockRead::new();
+            read.expect_name().return_const(b"long".to_vec());
+            read.expect_flags().return_const(16u16);
+            read.expect_contig().return_const("".to_owned());
+            read.expect_pos().return_const(1000);
+            read.expect_len().return_const(seq.len());
+            read.expect_cigar().times(decoded).returning(move || CigarString(cigar.clone()).into_view(1000));
+            read.expect_strand().return_const(ReqStrand::Reverse);
+            read.expect_seq().returning(move || seq.clone());
+            read
+        };
+        // The CIGAR is decoded for each bin in the default mode and only once for long reads
+        let (dread, lread) = (read(bins.clone().count()), read(1));
+
+        // The read is much longer than each bin -> results must be identical to the default mode
+        let mask = CigarMask { splice: 2, indels: 1, softclip: 3 };
+        let counter = |longreads: bool| {
+            let mut filter = MockReadsFilter::new();
+            filter.expect_is_read_ok().return_const(true);
+            filter.expect_is_base_ok().return_const(true);
+            BaseNucCounter::new(500, filter, 5, 10)
+                .with_indels(true)
+                .with_read_stats(true)
+                .with_read_bases(true)
+                .with_cigar_mask(mask)
+                .with_long_reads(longreads)
+        };
+        let (mut default, mut longreads) = (counter(false), counter(true));
+
+        let mut counted = 0;
+        for start in bins {
+            default.reset(Interval::new("".into(), start..start + 500));
+            longreads.reset(Interval::new("".into(), start..start + 500));
+
+            assert_eq!(default.count(&dread), longreads.count(&lread));
+            assert_eq!(default.buffer, longreads.buffer);
+            assert_eq!(default.indels, longreads.indels);
+            assert_eq!(default.stats, longreads.stats);
+            assert_eq!(default.readbases, longreads.readbases);
+            assert_eq!(default.mapped, longreads.mapped);
+            counted += longreads.buffer.iter().map(|x| x.coverage()).sum::<u32>();
+        }
+        assert!(counted > 0);
+        // Bins far from the read start skip most of the CIGAR operations
+        assert!(longreads.walked * 10 < default.walked, "{} vs {}", longreads.walked, default.walked);
+
+        // The index is dropped once the read ends before the interval or the contig changes
+        assert_eq!(longreads.indexed.len(), 1);
+        let end = 1000 + span as u64;
+        longreads.reset(Interval::new("".into(), end..end + 500));
+        assert!(longreads.indexed.is_empty());
+
+        longreads.reset(Interval::new("".into(), 1000..1500));
+        longreads.count(&read(1));
+        assert_eq!(longreads.indexed.len(), 1);
+        longreads.reset(Interval::new("other".into(), 1000..1500));
+        assert!(longreads.indexed.is_empty());
+    }
 }
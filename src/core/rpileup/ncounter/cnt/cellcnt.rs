@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::core::dna::{NucCounts, ReqNucleotide};
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::CellNucCounts;
+
+#[derive(Clone)]
+pub struct CellBarcodes {
+    // Tag that holds the cell barcode, e.g. CB
+    tag: Vec<u8>,
+    // Whitelisted barcode -> cell index (matrix column)
+    whitelist: Arc<HashMap<Vec<u8>, u32>>,
+}
+
+impl CellBarcodes {
+    pub fn new(tag: Vec<u8>, barcodes: Vec<Vec<u8>>) -> Self {
+        let whitelist = barcodes.into_iter().enumerate().map(|(ind, x)| (x, ind as u32)).collect();
+        Self { tag, whitelist: Arc::new(whitelist) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.whitelist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.whitelist.is_empty()
+    }
+
+    // Cell index of the read, None if the barcode is missing or not whitelisted
+    #[inline]
+    pub fn cell<R: AlignedRead>(&self, read: &R) -> Option<u32> {
+        self.whitelist.get(read.aux_str(&self.tag)).copied()
+    }
+}
+
+// Sparse per-cell counts, accumulated base by base by the BaseNucCounter
+#[derive(Clone)]
+pub struct CellNucCounter {
+    barcodes: CellBarcodes,
+    // Cell of the counted read, None if it is not whitelisted
+    current: Option<u32>,
+    // (roi position, cell) -> counts
+    counts: HashMap<(u32, u32), NucCounts>,
+    // Non-zero counts for each counted range, sorted by offset and cell
+    ranges: Vec<Vec<CellNucCounts>>,
+}
+
+impl CellNucCounter {
+    pub fn new(barcodes: CellBarcodes) -> Self {
+        Self { barcodes, current: None, counts: HashMap::new(), ranges: Vec::new() }
+    }
+
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.counts.clear();
+        self.ranges.clear();
+    }
+
+    #[inline]
+    pub fn cell<R: AlignedRead>(&self, read: &R) -> Option<u32> {
+        self.barcodes.cell(read)
+    }
+
+    // Cell for all subsequently counted bases
+    #[inline]
+    pub fn set_current(&mut self, cell: Option<u32>) {
+        self.current = cell;
+    }
+
+    #[inline]
+    pub fn add(&mut self, roipos: u32, nuc: ReqNucleotide) {
+        if let Some(cell) = self.current {
+            self.counts.entry((roipos, cell)).or_insert_with(NucCounts::zeros)[nuc] += 1;
+        }
+    }
+
+    #[inline]
+    pub fn remove(&mut self, roipos: u32, nuc: ReqNucleotide) {
+        if let Some(cnts) = self.current.and_then(|cell| self.counts.get_mut(&(roipos, cell))) {
+            cnts[nuc] -= 1;
+        }
+    }
+
+    // Split accumulated counts by ranges, roi positions are relative to the given start
+    pub fn split(&mut self, start: u64, ranges: &[Range<u64>]) {
+        let mut counts: Vec<_> = self.counts.drain().filter(|(_, x)| x.coverage() > 0).collect();
+        counts.sort_unstable_by_key(|(key, _)| *key);
+
+        // Ranges might overlap -> each one is looked up independently
+        self.ranges = ranges
+            .iter()
+            .map(|range| {
+                let (first, end) = ((range.start - start) as u32, (range.end - start) as u32);
+                let from = counts.partition_point(|((pos, _), _)| *pos < first);
+                let to = counts.partition_point(|((pos, _), _)| *pos < end);
+                counts[from..to]
+                    .iter()
+                    .map(|&((pos, cell), cnts)| CellNucCounts { cell, offset: pos - first, cnts })
+                    .collect()
+            })
+            .collect();
+    }
+
+    // Counts for the given range, available after the split
+    #[inline]
+    pub fn range(&self, ind: usize) -> &[CellNucCounts] {
+        self.ranges.get(ind).map_or(&[][..], |x| x.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    fn barcodes() -> CellBarcodes {
+        CellBarcodes::new(b"CB".to_vec(), vec![b"AAAC-1".to_vec(), b"GGTT-1".to_vec(), b"CCTA-1".to_vec()])
+    }
+
+    fn read(barcode: &str) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_aux_str().return_const(barcode.as_bytes().to_vec());
+        read
+    }
+
+    #[test]
+    fn whitelist() {
+        let barcodes = barcodes();
+        assert_eq!(barcodes.len(), 3);
+        assert!(!barcodes.is_empty());
+
+        for (barcode, cell) in
+            [("AAAC-1", Some(0)), ("CCTA-1", Some(2)), ("GGTT-1", Some(1)), ("GGTT", None), ("", None)]
+        {
+            assert_eq!(barcodes.cell(&read(barcode)), cell, "{}", barcode);
+        }
+    }
+
+    #[test]
+    fn per_cell() {
+        let mut dummy = CellNucCounter::new(barcodes());
+        for (barcode, pos, nuc) in [
+            ("GGTT-1", 3, ReqNucleotide::A),
+            ("AAAC-1", 3, ReqNucleotide::G),
+            ("XXXX-1", 3, ReqNucleotide::G),
+            ("GGTT-1", 3, ReqNucleotide::G),
+            ("AAAC-1", 3, ReqNucleotide::G),
+            ("", 4, ReqNucleotide::T),
+            ("CCTA-1", 0, ReqNucleotide::C),
+        ] {
+            dummy.set_current(dummy.cell(&read(barcode)));
+            dummy.add(pos, nuc);
+        }
+        // Removed bases are dropped from the sparse output
+        dummy.set_current(Some(2));
+        dummy.remove(0, ReqNucleotide::C);
+        dummy.remove(1, ReqNucleotide::C);
+
+        dummy.split(10, &[10..15]);
+        assert_eq!(
+            dummy.range(0),
+            [
+                CellNucCounts { cell: 0, offset: 3, cnts: NucCounts::G(2) },
+                CellNucCounts { cell: 1, offset: 3, cnts: NucCounts::new(1, 0, 1, 0) },
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let mut dummy = CellNucCounter::new(barcodes());
+        for (cell, pos) in [(1, 9), (0, 2), (2, 5), (1, 5), (0, 0), (2, 7)] {
+            dummy.set_current(Some(cell));
+            dummy.add(pos, ReqNucleotide::A);
+        }
+
+        // Offsets are relative to each range, overlapping ranges share counts
+        dummy.split(100, &[100..103, 105..108, 106..110, 110..120]);
+        let entry = |cell, offset| CellNucCounts { cell, offset, cnts: NucCounts::A(1) };
+        assert_eq!(dummy.range(0), [entry(0, 0), entry(0, 2)]);
+        assert_eq!(dummy.range(1), [entry(1, 0), entry(2, 0), entry(2, 2)]);
+        assert_eq!(dummy.range(2), [entry(2, 1), entry(1, 3)]);
+        assert!(dummy.range(3).is_empty());
+        assert!(dummy.range(4).is_empty());
+
+        dummy.reset();
+        assert!(dummy.range(0).is_empty());
+    }
+}
@@ -10,6 +10,7 @@ use crate::core::strandutil::Stranded;
 use crate::core::workload::SiteWorkload;
 
 use super::base::BaseNucCounter;
+use super::umicnt::MoleculeCollider;
 
 #[derive(Clone)]
 pub struct IntervalNucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
@@ -33,11 +34,20 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for Interv
         self.ranges = ranges;
     }
 
+    fn source(&mut self, index: usize) {
+        self.base.source(index);
+    }
+
     fn collide(&mut self, read: &R) {
         self.base.count(read);
     }
 
-    fn finalize(&mut self) {}
+    fn finalize(&mut self) {
+        let start = self.base.interval().range().start;
+        if let Some(cells) = self.base.cells_mut() {
+            cells.split(start, &self.ranges);
+        }
+    }
 
     fn result(&'a self) -> Self::ColliderResult {
         let contig = self.base.interval().contig();
@@ -46,16 +56,29 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for Interv
         let cnts = self
             .ranges
             .iter()
-            .map(|range| {
+            .enumerate()
+            .map(|(ind, range)| {
                 let indx = (range.start - start) as usize..(range.end - start) as usize;
                 InnerNucCounts {
                     data: (),
                     range: range.clone(),
-                    cnts: Stranded::unknown(Some(&self.base.counted()[indx])),
+                    cnts: Stranded::unknown(Some(&self.base.counted()[indx.clone()])),
                     coverage: Stranded::unknown(self.base.mapped()),
+                    fraccoverage: Default::default(),
+                    weighted: Stranded::unknown(self.base.weighted().map(|x| &x[indx.clone()])),
+                    indels: Stranded::unknown(self.base.indels().map(|x| &x[indx.clone()])),
+                    readstats: Stranded::unknown(self.base.readstats().map(|x| &x[indx])),
+                    samples: vec![],
+                    cells: Stranded::unknown(self.base.cells().map_or(&[][..], |x| x.range(ind))),
                 }
             })
             .collect();
         Self::ColliderResult { contig, cnts, mapped: Stranded::unknown(self.base.mapped()) }
     }
 }
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> MoleculeCollider<R> for IntervalNucCounter<R, Filter> {
+    fn collide_molecule(&mut self, reads: &[R]) {
+        self.base.count_molecule(reads);
+    }
+}
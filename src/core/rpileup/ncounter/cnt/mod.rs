@@ -1,9 +1,15 @@
-pub use base::BaseNucCounter;
+pub use base::{BaseNucCounter, BaseWeights, CigarMask, MatesOverlap};
+pub use cellcnt::{CellBarcodes, CellNucCounter};
 pub use intercnt::IntervalNucCounter;
 pub use roicnt::ROINucCounter;
+pub use samplecnt::{PerSampleNucCounter, SampleSplit};
 pub use strandcnt::StrandedNucCounter;
+pub use umicnt::{MoleculeCollider, UMINucCounter};
 
 mod base;
+mod cellcnt;
 mod intercnt;
 mod roicnt;
+mod samplecnt;
 mod strandcnt;
+mod umicnt;
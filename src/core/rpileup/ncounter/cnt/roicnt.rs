@@ -11,18 +11,21 @@ use crate::core::strandutil::Stranded;
 use crate::core::workload::{ROIWorkload, ROI};
 
 use super::base::BaseNucCounter;
+use super::umicnt::MoleculeCollider;
 
 #[derive(Clone)]
 pub struct ROINucCounter<R: AlignedRead, Filter: ReadsFilter<R>> {
     base: BaseNucCounter<R, Filter>,
     rois: Vec<ROI>,
     coverage: Vec<u32>,
+    // Coverage with multi-mapping reads counted fractionally
+    fraccoverage: Vec<f32>,
     index: IntervalTree<u32, usize>,
 }
 
 impl<R: AlignedRead, Filter: ReadsFilter<R>> ROINucCounter<R, Filter> {
     pub fn new(base: BaseNucCounter<R, Filter>) -> Self {
-        Self { base, rois: vec![], coverage: vec![], index: Default::default() }
+        Self { base, rois: vec![], coverage: vec![], fraccoverage: vec![], index: Default::default() }
     }
 }
 
@@ -38,6 +41,8 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for ROINuc
         // Coverage for each roi
         self.coverage.clear();
         self.coverage.resize(self.rois.len(), 0);
+        self.fraccoverage.clear();
+        self.fraccoverage.resize(self.rois.len(), 0.0);
 
         // Index to accurately count ROIs coverage
         self.index = Default::default();
@@ -48,10 +53,16 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for ROINuc
         }
     }
 
+    fn source(&mut self, index: usize) {
+        self.base.source(index);
+    }
+
     fn collide(&mut self, read: &R) {
+        let weight = self.base.read_weight(read);
         let covered_rois = self.base.count(read).iter().flat_map(|x| self.index.find(x)).map(|x| x.data()).unique();
         for ind in covered_rois {
             self.coverage[*ind] += 1;
+            self.fraccoverage[*ind] += weight;
         }
     }
 
@@ -62,18 +73,41 @@ impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for ROINuc
         let instart = self.base.interval().range().start as usize;
 
         let mut cnts = Vec::with_capacity(self.rois.len());
-        for (coverage, roi) in zip(&self.coverage, &self.rois) {
+        let fractional = self.base.is_multimappers_fraction();
+        for ((coverage, fraccoverage), roi) in zip(zip(&self.coverage, &self.fraccoverage), &self.rois) {
             debug_assert_eq!(roi.contig(), contig);
             let (start, end) = (roi.range().start as usize, roi.range().end as usize);
 
             let roicnts = &self.base.counted()[start - instart..end - instart];
+            let roiweighted = self.base.weighted().map(|x| &x[start - instart..end - instart]);
+            let roindels = self.base.indels().map(|x| &x[start - instart..end - instart]);
+            let roistats = self.base.readstats().map(|x| &x[start - instart..end - instart]);
             cnts.push(InnerNucCounts {
                 data: roi,
                 range: roi.range().clone(),
                 cnts: Stranded::unknown(Some(roicnts)),
                 coverage: Stranded::unknown(*coverage),
+                fraccoverage: Stranded::unknown(fractional.then(|| *fraccoverage)),
+                weighted: Stranded::unknown(roiweighted),
+                indels: Stranded::unknown(roindels),
+                readstats: Stranded::unknown(roistats),
+                samples: vec![],
+                cells: Default::default(),
             });
         }
         NucCounterResult { contig, cnts, mapped: Stranded::unknown(self.base.mapped()) }
     }
 }
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> MoleculeCollider<R> for ROINucCounter<R, Filter> {
+    fn collide_molecule(&mut self, reads: &[R]) {
+        // PCR duplicates share the mapping, i.e. the weight of the first read is used for the molecule
+        let weight = reads.first().map_or(1.0, |x| self.base.read_weight(x));
+        let covered_rois =
+            self.base.count_molecule(reads).iter().flat_map(|x| self.index.find(x)).map(|x| x.data()).unique();
+        for ind in covered_rois {
+            self.coverage[*ind] += 1;
+            self.fraccoverage[*ind] += weight;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use itertools::zip;
+
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::{NucCounterResult, SampleNucCounts};
+use crate::core::rpileup::ReadsCollider;
+
+#[derive(Clone)]
+pub enum SampleSplit {
+    // Each input file is a separate sample
+    ByFile(usize),
+    // Each read group is a separate sample: RG ID -> sample index
+    ByReadGroup(HashMap<Vec<u8>, usize>),
+}
+
+impl SampleSplit {
+    pub fn samples(&self) -> usize {
+        match self {
+            SampleSplit::ByFile(files) => *files,
+            SampleSplit::ByReadGroup(groups) => groups.values().max().map_or(0, |x| x + 1),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PerSampleNucCounter<InnerNucCounter> {
+    merged: InnerNucCounter,
+    samples: Vec<InnerNucCounter>,
+    split: Option<SampleSplit>,
+    source: usize,
+}
+
+impl<InnerNucCounter: Clone> PerSampleNucCounter<InnerNucCounter> {
+    // Only nucleotide counts are reported per sample -> `sample` should be a lightweight version of `merged`
+    // (no indels, read stats, weights, cells) that counts the same reads
+    pub fn new(merged: InnerNucCounter, sample: InnerNucCounter, split: Option<SampleSplit>) -> Self {
+        let samples = vec![sample; split.as_ref().map_or(0, |x| x.samples())];
+        Self { merged, samples, split, source: 0 }
+    }
+}
+
+impl<InnerNucCounter> PerSampleNucCounter<InnerNucCounter> {
+    #[inline]
+    fn sample<R: AlignedRead>(&self, read: &R) -> Option<usize> {
+        match &self.split {
+            None => None,
+            Some(SampleSplit::ByFile(_)) => Some(self.source),
+            Some(SampleSplit::ByReadGroup(groups)) => groups.get(read.aux_str(b"RG")).copied(),
+        }
+    }
+}
+
+impl<'a, R, InnerNucCounter, Data> ReadsCollider<'a, R> for PerSampleNucCounter<InnerNucCounter>
+where
+    R: AlignedRead,
+    InnerNucCounter: ReadsCollider<'a, R, ColliderResult = NucCounterResult<'a, Data>>,
+    InnerNucCounter::Workload: Clone,
+{
+    type ColliderResult = InnerNucCounter::ColliderResult;
+    type Workload = InnerNucCounter::Workload;
+
+    fn reset(&mut self, info: Self::Workload) {
+        for sample in &mut self.samples {
+            sample.reset(info.clone());
+        }
+        self.merged.reset(info);
+        self.source = 0;
+    }
+
+    fn source(&mut self, index: usize) {
+        self.merged.source(index);
+        for sample in &mut self.samples {
+            sample.source(index);
+        }
+        self.source = index;
+    }
+
+    // Merged counts can't be summed from samples: they include reads outside of any sample (e.g. without RG)
+    // and track optional data (weights, indels, cells) that is not reported per sample
+    #[inline]
+    fn collide(&mut self, read: &R) {
+        self.merged.collide(read);
+        if let Some(ind) = self.sample(read) {
+            self.samples[ind].collide(read);
+        }
+    }
+
+    fn finalize(&mut self) {
+        self.merged.finalize();
+        for sample in &mut self.samples {
+            sample.finalize();
+        }
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        let mut result = self.merged.result();
+        for sample in &self.samples {
+            let sample = sample.result();
+            debug_assert_eq!(result.cnts.len(), sample.cnts.len());
+
+            for (item, smpl) in zip(&mut result.cnts, sample.cnts) {
+                debug_assert_eq!(item.range, smpl.range);
+                item.samples.push(SampleNucCounts { cnts: smpl.cnts, coverage: smpl.coverage });
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+
+    use crate::core::dna::NucCounts;
+    use crate::core::read::MockRead;
+    use crate::core::rpileup::ncounter::InnerNucCounts;
+    use crate::core::strandutil::Stranded;
+
+    use super::*;
+
+    // Counts all reads as a single "A" at the first position
+    #[derive(Clone)]
+    struct DummyCounter {
+        buffer: Vec<NucCounts>,
+        reads: u32,
+    }
+
+    impl Default for DummyCounter {
+        fn default() -> Self {
+            Self { buffer: vec![NucCounts::zeros()], reads: 0 }
+        }
+    }
+
+    impl<'a> ReadsCollider<'a, MockRead> for DummyCounter {
+        type ColliderResult = NucCounterResult<'a, ()>;
+        type Workload = Interval;
+
+        fn reset(&mut self, _: Self::Workload) {
+            *self = Self::default();
+        }
+
+        fn collide(&mut self, _: &MockRead) {
+            self.buffer[0].A += 1;
+            self.reads += 1;
+        }
+
+        fn finalize(&mut self) {}
+
+        fn result(&'a self) -> Self::ColliderResult {
+            NucCounterResult {
+                contig: "chr1",
+                mapped: Stranded { forward: self.reads, reverse: 0, unknown: 0 },
+                cnts: vec![InnerNucCounts {
+                    data: (),
+                    range: 0..1,
+                    cnts: Stranded { forward: Some(&self.buffer), reverse: None, unknown: None },
+                    coverage: Stranded { forward: self.reads, reverse: 0, unknown: 0 },
+                    fraccoverage: Default::default(),
+                    weighted: Default::default(),
+                    indels: Default::default(),
+                    readstats: Default::default(),
+                    samples: vec![],
+                    cells: Default::default(),
+                }],
+            }
+        }
+    }
+
+    fn read(rg: &str) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_aux_str().return_const(rg.as_bytes().to_vec());
+        read
+    }
+
+    // Merged and per-sample counts of "A"
+    fn counts(dummy: &PerSampleNucCounter<DummyCounter>) -> (u32, Vec<u32>) {
+        let result = dummy.result();
+        assert_eq!(result.cnts.len(), 1);
+        let item = &result.cnts[0];
+        let samples = item.samples.iter().map(|x| x.cnts.forward.unwrap()[0].A).collect();
+        (item.cnts.forward.unwrap()[0].A, samples)
+    }
+
+    #[test]
+    fn unsplit() {
+        let mut dummy = PerSampleNucCounter::new(DummyCounter::default(), DummyCounter::default(), None);
+        dummy.reset(Interval::new("chr1".into(), 0..1));
+        for _ in 0..3 {
+            dummy.collide(&MockRead::new());
+        }
+        dummy.finalize();
+        assert_eq!(counts(&dummy), (3, vec![]));
+    }
+
+    #[test]
+    fn by_file() {
+        let mut dummy =
+            PerSampleNucCounter::new(DummyCounter::default(), DummyCounter::default(), Some(SampleSplit::ByFile(3)));
+        for _ in 0..2 {
+            dummy.reset(Interval::new("chr1".into(), 0..1));
+            dummy.source(0);
+            dummy.collide(&MockRead::new());
+            dummy.collide(&MockRead::new());
+            dummy.source(2);
+            dummy.collide(&MockRead::new());
+            dummy.finalize();
+            assert_eq!(counts(&dummy), (3, vec![2, 0, 1]));
+        }
+    }
+
+    #[test]
+    fn by_read_group() {
+        let groups = HashMap::from([(b"g1".to_vec(), 0), (b"g2".to_vec(), 1), (b"g3".to_vec(), 1)]);
+        let split = SampleSplit::ByReadGroup(groups);
+        assert_eq!(split.samples(), 2);
+
+        let mut dummy = PerSampleNucCounter::new(DummyCounter::default(), DummyCounter::default(), Some(split));
+        dummy.reset(Interval::new("chr1".into(), 0..1));
+        // Reads without RG or with an unknown one are counted only in the merged counts
+        for rg in ["g1", "g2", "g3", "", "g4", "g3"] {
+            dummy.collide(&read(rg));
+        }
+        dummy.finalize();
+        assert_eq!(counts(&dummy), (6, vec![1, 3]));
+    }
+}
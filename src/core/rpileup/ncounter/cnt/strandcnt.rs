@@ -1,4 +1,4 @@
-use bio_types::strand::ReqStrand;
+use bio_types::strand::Strand;
 use itertools::zip;
 
 use crate::core::read::AlignedRead;
@@ -11,22 +11,26 @@ use crate::core::strandutil::Stranded;
 pub struct StrandedNucCounter<Deductor, InnerNucCounter> {
     forward: InnerNucCounter,
     reverse: InnerNucCounter,
+    // Reads with unknown strand, None if the deductor always infers the strand
+    unknown: Option<InnerNucCounter>,
     deductor: Deductor,
 }
 
 impl<Deductor, InnerNucCounter> StrandedNucCounter<Deductor, InnerNucCounter>
 where
+    Deductor: StrandDeducer,
     InnerNucCounter: Clone,
 {
     pub fn new(base: InnerNucCounter, deductor: Deductor) -> Self {
-        Self { forward: base.clone(), reverse: base, deductor }
+        let unknown = deductor.has_unknown().then(|| base.clone());
+        Self { forward: base.clone(), reverse: base, unknown, deductor }
     }
 }
 
 impl<'a, R, Deductor, InnerNucCounter, Data> ReadsCollider<'a, R> for StrandedNucCounter<Deductor, InnerNucCounter>
 where
     R: AlignedRead,
-    Deductor: StrandDeducer<R>,
+    Deductor: StrandDeducer,
     InnerNucCounter: ReadsCollider<'a, R, ColliderResult = NucCounterResult<'a, Data>>,
     InnerNucCounter::Workload: Clone,
     Data: std::cmp::PartialEq,
@@ -36,37 +40,100 @@ where
 
     fn reset(&mut self, info: Self::Workload) {
         self.forward.reset(info.clone());
+        if let Some(unknown) = &mut self.unknown {
+            unknown.reset(info.clone());
+        }
         self.reverse.reset(info);
     }
 
+    fn source(&mut self, index: usize) {
+        self.forward.source(index);
+        self.reverse.source(index);
+        if let Some(unknown) = &mut self.unknown {
+            unknown.source(index);
+        }
+    }
+
     #[inline]
     fn collide(&mut self, read: &R) {
         match self.deductor.deduce(read) {
-            ReqStrand::Forward => self.forward.collide(read),
-            ReqStrand::Reverse => self.reverse.collide(read),
+            Strand::Forward => self.forward.collide(read),
+            Strand::Reverse => self.reverse.collide(read),
+            Strand::Unknown => match &mut self.unknown {
+                Some(unknown) => unknown.collide(read),
+                None => unreachable!("Deductor can't leave the strand unknown"),
+            },
         }
     }
 
     fn finalize(&mut self) {
         self.forward.finalize();
         self.reverse.finalize();
+        if let Some(unknown) = &mut self.unknown {
+            unknown.finalize();
+        }
     }
 
     fn result(&'a self) -> Self::ColliderResult {
-        let (mut fwd, mut rev) = (self.forward.result(), self.reverse.result());
-        debug_assert_eq!(fwd.cnts.len(), rev.cnts.len());
+        let (mut fwd, rev) = (self.forward.result(), self.reverse.result());
+        debug_assert!(fwd.cnts.len() == rev.cnts.len());
+
+        // Unknown strand is reported only if there were reads without a strand
+        let unk = self.unknown.as_ref().map(|x| x.result()).filter(|x| x.mapped.unknown > 0);
+        debug_assert!(unk.as_ref().map_or(true, |x| x.cnts.len() == fwd.cnts.len()));
+        let mut unkcnts = unk.as_ref().map(|x| x.cnts.iter());
 
-        for (f, r) in zip(&mut fwd.cnts, &mut rev.cnts) {
-            debug_assert!([&f.cnts, &r.cnts]
+        for (f, r) in zip(&mut fwd.cnts, &rev.cnts) {
+            let u = unkcnts.as_mut().and_then(|x| x.next());
+            debug_assert!([Some(&f.cnts), Some(&r.cnts), u.map(|x| &x.cnts)].iter().flatten().all(|x| x
+                .forward
+                .is_none()
+                && x.reverse.is_none()
+                && x.unknown.is_some()));
+            debug_assert!([Some(&f.coverage), Some(&r.coverage), u.map(|x| &x.coverage)]
                 .iter()
-                .all(|x| x.forward.is_none() && x.reverse.is_none() && x.unknown.is_some()));
-            debug_assert!([&f.coverage, &r.coverage].iter().all(|x| x.forward == 0 && x.reverse == 0));
-            debug_assert!(f.data == r.data);
+                .flatten()
+                .all(|x| x.forward == 0 && x.reverse == 0));
+            debug_assert!(f.data == r.data && u.map_or(true, |x| f.data == x.data));
 
-            f.coverage = Stranded { forward: f.coverage.unknown, reverse: r.coverage.unknown, unknown: 0 };
-            f.cnts = Stranded { forward: f.cnts.unknown, reverse: r.cnts.unknown, unknown: None };
+            f.coverage = Stranded {
+                forward: f.coverage.unknown,
+                reverse: r.coverage.unknown,
+                unknown: u.map_or(0, |x| x.coverage.unknown),
+            };
+            f.cnts =
+                Stranded { forward: f.cnts.unknown, reverse: r.cnts.unknown, unknown: u.and_then(|x| x.cnts.unknown) };
+            f.fraccoverage = Stranded {
+                forward: f.fraccoverage.unknown,
+                reverse: r.fraccoverage.unknown,
+                unknown: u.and_then(|x| x.fraccoverage.unknown),
+            };
+            f.weighted = Stranded {
+                forward: f.weighted.unknown,
+                reverse: r.weighted.unknown,
+                unknown: u.and_then(|x| x.weighted.unknown),
+            };
+            f.indels = Stranded {
+                forward: f.indels.unknown,
+                reverse: r.indels.unknown,
+                unknown: u.and_then(|x| x.indels.unknown),
+            };
+            f.readstats = Stranded {
+                forward: f.readstats.unknown,
+                reverse: r.readstats.unknown,
+                unknown: u.and_then(|x| x.readstats.unknown),
+            };
+            f.cells = Stranded {
+                forward: f.cells.unknown,
+                reverse: r.cells.unknown,
+                unknown: u.map_or(&[][..], |x| x.cells.unknown),
+            };
         }
-        fwd.mapped = Stranded { forward: fwd.mapped.unknown, reverse: rev.mapped.unknown, unknown: 0 };
+        fwd.mapped = Stranded {
+            forward: fwd.mapped.unknown,
+            reverse: rev.mapped.unknown,
+            unknown: unk.as_ref().map_or(0, |x| x.mapped.unknown),
+        };
         fwd
     }
 }
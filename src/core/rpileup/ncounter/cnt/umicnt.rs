@@ -0,0 +1,267 @@
+use std::cmp::{max, min};
+use std::collections::HashMap;
+
+use bio_types::strand::ReqStrand;
+use rust_htslib::htslib::BAM_FMREVERSE;
+
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ReadsCollider;
+
+// Counter that is able to pileup a group of reads as a single molecule
+pub trait MoleculeCollider<R: AlignedRead> {
+    fn collide_molecule(&mut self, reads: &[R]);
+}
+
+// (UMI, 5` alignment position, strand) for single reads.
+// (UMI, leftmost alignment start of mates, rightmost alignment start of mates, strand of the first mate) for pairs.
+type MoleculeKey = (Vec<u8>, i64, Option<i64>, ReqStrand);
+
+#[derive(Clone)]
+pub struct UMINucCounter<R: AlignedRead, InnerNucCounter> {
+    inner: InnerNucCounter,
+    // UMI tags in the priority order, the first available tag is used
+    tags: Vec<Vec<u8>>,
+    molecules: HashMap<MoleculeKey, Vec<R>>,
+}
+
+impl<R: AlignedRead, InnerNucCounter> UMINucCounter<R, InnerNucCounter> {
+    pub fn new(inner: InnerNucCounter, tags: Vec<Vec<u8>>) -> Self {
+        Self { inner, tags, molecules: HashMap::new() }
+    }
+
+    fn key(&self, read: &R) -> Option<MoleculeKey> {
+        let umi = self.tags.iter().map(|tag| read.aux_str(tag)).find(|x| !x.is_empty())?;
+        // Both mates must end up in the same molecule, otherwise their overlap is counted twice
+        if let Some(matepos) = read.mate_pos() {
+            let strand = if read.is_first() {
+                *read.strand()
+            } else if read.flags() & BAM_FMREVERSE as u16 != 0 {
+                ReqStrand::Reverse
+            } else {
+                ReqStrand::Forward
+            };
+            return Some((umi.to_vec(), min(read.pos(), matepos), Some(max(read.pos(), matepos)), strand));
+        }
+        let start = match read.strand() {
+            ReqStrand::Forward => read.pos(),
+            ReqStrand::Reverse => read.cigar().end_pos(),
+        };
+        Some((umi.to_vec(), start, None, *read.strand()))
+    }
+}
+
+impl<'a, R, InnerNucCounter> ReadsCollider<'a, R> for UMINucCounter<R, InnerNucCounter>
+where
+    R: AlignedRead + Clone,
+    InnerNucCounter: ReadsCollider<'a, R> + MoleculeCollider<R>,
+{
+    type ColliderResult = InnerNucCounter::ColliderResult;
+    type Workload = InnerNucCounter::Workload;
+
+    fn reset(&mut self, info: Self::Workload) {
+        self.molecules.clear();
+        self.inner.reset(info);
+    }
+
+    // Molecules are counted in finalize -> the source only matters for reads without UMI
+    fn source(&mut self, index: usize) {
+        self.inner.source(index);
+    }
+
+    fn collide(&mut self, read: &R) {
+        match self.key(read) {
+            Some(key) => self.molecules.entry(key).or_default().push(read.clone()),
+            // Reads without UMI are counted as is
+            None => self.inner.collide(read),
+        }
+    }
+
+    fn finalize(&mut self) {
+        for (_, reads) in self.molecules.drain() {
+            self.inner.collide_molecule(&reads);
+        }
+        self.inner.finalize();
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        self.inner.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+    use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+    use rust_htslib::bam::Record;
+
+    use crate::core::read::SequencedRead;
+
+    use super::*;
+
+    // Records names of reads counted one by one and as molecules
+    #[derive(Default)]
+    struct DummyCounter {
+        single: Vec<String>,
+        molecules: Vec<Vec<String>>,
+        finalized: bool,
+    }
+
+    fn name(read: &Record) -> String {
+        String::from_utf8(read.name().to_vec()).unwrap()
+    }
+
+    impl<'a> ReadsCollider<'a, Record> for DummyCounter {
+        type ColliderResult = ();
+        type Workload = Interval;
+
+        fn reset(&mut self, _: Self::Workload) {
+            *self = Self::default();
+        }
+
+        fn collide(&mut self, read: &Record) {
+            self.single.push(name(read));
+        }
+
+        fn finalize(&mut self) {
+            self.finalized = true;
+        }
+
+        fn result(&'a self) -> Self::ColliderResult {}
+    }
+
+    impl MoleculeCollider<Record> for DummyCounter {
+        fn collide_molecule(&mut self, reads: &[Record]) {
+            let mut names = reads.iter().map(name).collect::<Vec<_>>();
+            names.sort();
+            self.molecules.push(names);
+        }
+    }
+
+    fn record(name: &str, pos: i64, reverse: bool, tags: &[(&str, &str)]) -> Record {
+        let mut read = Record::new();
+        read.set(name.as_bytes(), Some(&CigarString(vec![Cigar::Match(10)])), &[b'A'; 10], &[30; 10]);
+        read.set_pos(pos);
+        if reverse {
+            read.set_reverse();
+        }
+        for &(tag, value) in tags {
+            read.push_aux(tag.as_bytes(), Aux::String(value)).unwrap();
+        }
+        read
+    }
+
+    fn run(dummy: &mut UMINucCounter<Record, DummyCounter>, reads: &[Record]) {
+        dummy.reset(Interval::new("chr1".into(), 0..100));
+        for read in reads {
+            dummy.collide(read);
+        }
+        dummy.finalize();
+    }
+
+    #[test]
+    fn collapse() {
+        let mut dummy = UMINucCounter::new(DummyCounter::default(), vec![b"UB".to_vec()]);
+        let reads = [
+            record("r1", 0, false, &[("UB", "AAAA")]),
+            record("r2", 0, false, &[("UB", "AAAA")]),
+            // Different UMI
+            record("r3", 0, false, &[("UB", "CCCC")]),
+            // Different 5` end
+            record("r4", 5, false, &[("UB", "AAAA")]),
+            // Different strand, the same 5` end (10 for reverse reads)
+            record("r5", 0, true, &[("UB", "AAAA")]),
+            record("r6", 5, false, &[("UB", "AAAA")]),
+        ];
+        run(&mut dummy, &reads);
+
+        let mut molecules = dummy.inner.molecules.clone();
+        molecules.sort();
+        assert_eq!(molecules, [vec!["r1", "r2"], vec!["r3"], vec!["r4", "r6"], vec!["r5"]]);
+        assert!(dummy.inner.single.is_empty());
+        assert!(dummy.inner.finalized);
+        assert!(dummy.molecules.is_empty());
+    }
+
+    #[test]
+    fn mates() {
+        let mate = |name: &str, pos: i64, matepos: i64, first: bool, umi: &str| {
+            let mut read = record(name, pos, !first, &[("UB", umi)]);
+            read.set_paired();
+            read.set_mpos(matepos);
+            if first {
+                read.set_first_in_template();
+                read.set_mate_reverse();
+            } else {
+                read.set_last_in_template();
+            }
+            read
+        };
+
+        let mut dummy = UMINucCounter::new(DummyCounter::default(), vec![b"UB".to_vec()]);
+        let reads = [
+            // Overlapping mates of the same fragment & its PCR duplicate
+            mate("r1/1", 0, 5, true, "AAAA"),
+            mate("r1/2", 5, 0, false, "AAAA"),
+            mate("r2/1", 0, 5, true, "AAAA"),
+            mate("r2/2", 5, 0, false, "AAAA"),
+            // Different mate position
+            mate("r3/1", 0, 7, true, "AAAA"),
+            // Different UMI
+            mate("r4/2", 5, 0, false, "CCCC"),
+            // Single read with the same 5` end
+            record("r5", 0, false, &[("UB", "AAAA")]),
+        ];
+        run(&mut dummy, &reads);
+
+        let mut molecules = dummy.inner.molecules.clone();
+        molecules.sort();
+        assert_eq!(molecules, [vec!["r1/1", "r1/2", "r2/1", "r2/2"], vec!["r3/1"], vec!["r4/2"], vec!["r5"]]);
+    }
+
+    #[test]
+    fn without_umi() {
+        let mut dummy = UMINucCounter::new(DummyCounter::default(), vec![b"UB".to_vec()]);
+        let reads = [
+            record("r1", 0, false, &[]),
+            record("r2", 0, false, &[("UB", "AAAA")]),
+            // Tag with an empty value
+            record("r3", 0, false, &[("UB", "")]),
+            // Other tags are ignored
+            record("r4", 0, false, &[("RX", "AAAA")]),
+        ];
+        run(&mut dummy, &reads);
+
+        assert_eq!(dummy.inner.single, ["r1", "r3", "r4"]);
+        assert_eq!(dummy.inner.molecules, [vec!["r2"]]);
+    }
+
+    #[test]
+    fn multiple_tags() {
+        let mut dummy = UMINucCounter::new(DummyCounter::default(), vec![b"UB".to_vec(), b"RX".to_vec()]);
+        let reads = [
+            // UB has the priority
+            record("r1", 0, false, &[("RX", "CCCC"), ("UB", "AAAA")]),
+            record("r2", 0, false, &[("UB", "AAAA")]),
+            // RX is used only when UB is missing
+            record("r3", 0, false, &[("RX", "AAAA")]),
+            record("r4", 0, false, &[("RX", "CCCC")]),
+        ];
+        run(&mut dummy, &reads);
+
+        let mut molecules = dummy.inner.molecules.clone();
+        molecules.sort();
+        assert_eq!(molecules, [vec!["r1", "r2", "r3"], vec!["r4"]]);
+        assert!(dummy.inner.single.is_empty());
+    }
+
+    #[test]
+    fn reset() {
+        let mut dummy = UMINucCounter::new(DummyCounter::default(), vec![b"UB".to_vec()]);
+        dummy.reset(Interval::new("chr1".into(), 0..100));
+        dummy.collide(&record("r1", 0, false, &[("UB", "AAAA")]));
+
+        // Pending molecules are dropped by the next reset
+        run(&mut dummy, &[record("r2", 0, false, &[("UB", "AAAA")])]);
+        assert_eq!(dummy.inner.molecules, [vec!["r2"]]);
+    }
+}
@@ -0,0 +1,372 @@
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use rust_htslib::bam::record::Cigar;
+
+use super::{AlignedRead, ReadsFilter};
+
+// Reads filter defined by a boolean expression over read properties, e.g. "NH==1 && (AS>=-10 || mapq>=30)".
+// Supported fields: mapq, flag, alen (number of aligned bases) and any SAM aux tag.
+// Conditions on aux tags that are missing in the read are false.
+#[derive(Clone, Default)]
+pub struct ByExpression {
+    expr: Option<Expr>,
+}
+
+impl FromStr for ByExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        if tokens.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Self { expr: Some(expr) }),
+            Some(x) => Err(format!("Unexpected token in the reads filter expression: {:?}", x)),
+        }
+    }
+}
+
+impl<R: AlignedRead> ReadsFilter<R> for ByExpression {
+    #[inline]
+    fn is_read_ok(&self, record: &R) -> bool {
+        self.expr.as_ref().map_or(true, |x| x.eval(record))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Field {
+    MapQ,
+    Flag,
+    AlignedLen,
+    Tag([u8; 2]),
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mapq" => Ok(Field::MapQ),
+            "flag" => Ok(Field::Flag),
+            "alen" => Ok(Field::AlignedLen),
+            // From the SAM specification: [A-Za-z][A-Za-z0-9]
+            _ => match s.as_bytes() {
+                [x, y] if x.is_ascii_alphabetic() && y.is_ascii_alphanumeric() => Ok(Field::Tag([*x, *y])),
+                _ => Err(format!("Unknown field in the reads filter expression: {}", s)),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    #[inline]
+    fn apply<T: Ord + ?Sized>(&self, left: &T, right: &T) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Value {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Field, Op, Value),
+}
+
+impl Expr {
+    fn eval<R: AlignedRead>(&self, record: &R) -> bool {
+        match self {
+            Expr::Or(left, right) => left.eval(record) || right.eval(record),
+            Expr::And(left, right) => left.eval(record) && right.eval(record),
+            Expr::Not(x) => !x.eval(record),
+            Expr::Cmp(field, op, value) => match (field, value) {
+                (Field::MapQ, Value::Int(x)) => op.apply(&(record.mapq() as i64), x),
+                (Field::Flag, Value::Int(x)) => op.apply(&(record.flags() as i64), x),
+                (Field::AlignedLen, Value::Int(x)) => op.apply(&aligned(record), x),
+                (Field::Tag(tag), Value::Int(x)) => record.aux_int(tag).map_or(false, |tag| op.apply(&tag, x)),
+                (Field::Tag(tag), Value::Str(x)) => {
+                    let string = record.aux_str(tag);
+                    if !string.is_empty() {
+                        op.apply(string, x.as_slice())
+                    } else {
+                        record.aux_char(tag).map_or(false, |chr| op.apply(&[chr][..], x.as_slice()))
+                    }
+                }
+                // Rejected by the parser
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+#[inline]
+fn aligned<R: AlignedRead>(record: &R) -> i64 {
+    record
+        .cigar()
+        .iter()
+        .map(|x| match x {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => *len as i64,
+            _ => 0,
+        })
+        .sum()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    fn next_is(chars: &mut Peekable<Chars>, expected: char) -> bool {
+        chars.next_if_eq(&expected).is_some()
+    }
+
+    while let Some(chr) = chars.next() {
+        let token = match chr {
+            _ if chr.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '&' if next_is(&mut chars, '&') => Token::And,
+            '|' if next_is(&mut chars, '|') => Token::Or,
+            '=' if next_is(&mut chars, '=') => Token::Op(Op::Eq),
+            '!' if next_is(&mut chars, '=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is(&mut chars, '=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is(&mut chars, '=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' | '\'' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some(x) if x == chr => break,
+                        Some(x) => string.push(x),
+                        None => return Err(format!("Unterminated string in the reads filter expression: {}", expr)),
+                    }
+                }
+                Token::Quoted(string)
+            }
+            _ if chr.is_ascii_alphanumeric() || "+-_.:".contains(chr) => {
+                let mut word = chr.to_string();
+                while let Some(x) = chars.next_if(|x| x.is_ascii_alphanumeric() || "+-_.:".contains(*x)) {
+                    word.push(x);
+                }
+                Token::Word(word)
+            }
+            _ => return Err(format!("Unexpected symbol '{}' in the reads filter expression: {}", chr, expr)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Recursive descent parser, operators by priority: !, &&, ||
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn consume(&mut self, token: &Token) -> bool {
+        let matched = self.tokens.get(self.pos) == Some(token);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.consume(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.consume(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.consume(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.consume(&Token::LParen) {
+            let expr = self.or()?;
+            if !self.consume(&Token::RParen) {
+                return Err("Missing closing parenthesis in the reads filter expression".to_owned());
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let field: Field = match self.next() {
+            Some(Token::Word(x)) => x.parse()?,
+            x => return Err(format!("Expected a field in the reads filter expression, got {:?}", x)),
+        };
+        let op = match self.next() {
+            Some(Token::Op(x)) => x,
+            x => return Err(format!("Expected a comparison operator in the reads filter expression, got {:?}", x)),
+        };
+        let value = match self.next() {
+            Some(Token::Word(x)) => match x.parse() {
+                Ok(x) => Value::Int(x),
+                Err(_) => Value::Str(x.into_bytes()),
+            },
+            Some(Token::Quoted(x)) => Value::Str(x.into_bytes()),
+            x => return Err(format!("Expected a value in the reads filter expression, got {:?}", x)),
+        };
+        if matches!((field, &value), (Field::MapQ | Field::Flag | Field::AlignedLen, Value::Str(_))) {
+            return Err(format!("Field {:?} must be compared with an integer in the reads filter expression", field));
+        }
+        Ok(Expr::Cmp(field, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::function;
+    use rust_htslib::bam::record::CigarString;
+
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    fn parse(expr: &str) -> Option<Expr> {
+        ByExpression::from_str(expr).unwrap().expr
+    }
+
+    #[test]
+    fn parse_ok() {
+        let nh = || Expr::Cmp(Field::Tag(*b"NH"), Op::Eq, Value::Int(1));
+        let asc = || Expr::Cmp(Field::Tag(*b"AS"), Op::Ge, Value::Int(-10));
+        let rg = || Expr::Cmp(Field::Tag(*b"RG"), Op::Ne, Value::Str(b"sample 1".to_vec()));
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(" NH==1 "), Some(nh()));
+        assert_eq!(parse("AS>=-10"), Some(asc()));
+        assert_eq!(parse("RG != 'sample 1'"), Some(rg()));
+        assert_eq!(parse("mapq<30"), Some(Expr::Cmp(Field::MapQ, Op::Lt, Value::Int(30))));
+        assert_eq!(parse("NH==1 && AS>=-10"), Some(Expr::And(Box::new(nh()), Box::new(asc()))));
+        assert_eq!(
+            parse("NH==1 || AS>=-10 && RG!=\"sample 1\""),
+            Some(Expr::Or(Box::new(nh()), Box::new(Expr::And(Box::new(asc()), Box::new(rg())))))
+        );
+        assert_eq!(
+            parse("!(NH==1 || AS>=-10) && RG!='sample 1'"),
+            Some(Expr::And(Box::new(Expr::Not(Box::new(Expr::Or(Box::new(nh()), Box::new(asc()))))), Box::new(rg())))
+        );
+        assert_eq!(parse("XS==+"), Some(Expr::Cmp(Field::Tag(*b"XS"), Op::Eq, Value::Str(b"+".to_vec()))));
+    }
+
+    #[test]
+    fn parse_err() {
+        for expr in [
+            "NH",
+            "NH==",
+            "NH=1",
+            "NHX==1",
+            "1H==1",
+            "mapq>=high",
+            "(NH==1",
+            "NH==1)",
+            "NH==1 &&",
+            "NH==1 & AS>0",
+            "RG=='unterminated",
+            "NH==1 AS>0",
+        ] {
+            assert!(ByExpression::from_str(expr).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn is_read_ok() {
+        let mut read = MockRead::new();
+        read.expect_mapq().return_const(20u8);
+        read.expect_flags().return_const(99u16);
+        read.expect_cigar().returning(|| {
+            CigarString(vec![Cigar::SoftClip(5), Cigar::Match(10), Cigar::RefSkip(100), Cigar::Match(5)]).into_view(0)
+        });
+        read.expect_aux_int().returning(|tag| match tag {
+            b"NH" => Some(1),
+            b"AS" => Some(-12),
+            _ => None,
+        });
+        read.expect_aux_str().with(function(|x: &[u8]| x == b"RG")).return_const(b"sample1".to_vec());
+        read.expect_aux_str().with(function(|x: &[u8]| x != b"RG")).return_const(Vec::new());
+        read.expect_aux_char().returning(|tag| if tag == b"XS" { Some(b'+') } else { None });
+
+        for (expr, expected) in [
+            ("", true),
+            ("NH==1", true),
+            ("NH>1", false),
+            ("AS>=-10", false),
+            ("AS>=-12 && NH<=1", true),
+            ("AS>=-10 || mapq>=20", true),
+            ("!(AS>=-10 || mapq>=20)", false),
+            ("flag==99 && alen==15", true),
+            ("alen>15", false),
+            ("RG==sample1", true),
+            ("RG!=sample1", false),
+            ("XS=='+'", true),
+            ("XS==-", false),
+            // Missing tags
+            ("nM<=3", false),
+            ("nM>3", false),
+            ("!nM>3", true),
+            ("CB==AAA", false),
+        ] {
+            let dummy = ByExpression::from_str(expr).unwrap();
+            assert_eq!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read), expected, "{}", expr);
+        }
+    }
+}
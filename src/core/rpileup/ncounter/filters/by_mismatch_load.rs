@@ -0,0 +1,191 @@
+use derive_getters::Getters;
+use derive_more::Constructor;
+use rust_htslib::bam::record::Cigar;
+
+use super::{AlignedRead, ReadsFilter};
+
+// Reads with too many differences from the reference, based on the NM/MD tags.
+// Reads without the required tags are not filtered.
+#[derive(Constructor, Getters, Copy, Clone, Default)]
+pub struct ByMismatchLoad {
+    // Maximum edit distance to the reference (NM)
+    max_edit_distance: Option<u32>,
+    // Maximum number of mismatches per 1000 aligned bases (MD)
+    max_mismatches_per_kb: Option<f32>,
+    // Maximum number of mismatches other than A->G and T->C (MD)
+    max_non_ag_mismatches: Option<u32>,
+    // Maximum edit distance (NM) per alignment column, i.e. aligned, inserted and deleted bases
+    max_error_rate: Option<f32>,
+}
+
+impl ByMismatchLoad {
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.max_edit_distance.is_some()
+            || self.max_mismatches_per_kb.is_some()
+            || self.max_non_ag_mismatches.is_some()
+            || self.max_error_rate.is_some()
+    }
+
+    // Number of alignment columns: aligned (M/=/X), inserted and deleted bases
+    fn alignment_columns<R: AlignedRead>(record: &R) -> u32 {
+        record
+            .cigar()
+            .iter()
+            .map(|op| match op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) | Cigar::Ins(len) | Cigar::Del(len) => *len,
+                Cigar::RefSkip(_) | Cigar::SoftClip(_) | Cigar::HardClip(_) | Cigar::Pad(_) => 0,
+            })
+            .sum()
+    }
+
+    // Number of aligned bases, mismatches and non A->G/T->C mismatches
+    fn mismatches<R: AlignedRead>(record: &R, md: &[u8]) -> (u32, u32, u32) {
+        // Read position for each aligned (M/=/X) base
+        let mut aligned = Vec::with_capacity(record.len());
+        let mut qpos = 0;
+        for op in record.cigar().iter() {
+            match op {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    aligned.extend(qpos..qpos + *len as usize);
+                    qpos += *len as usize;
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) => qpos += *len as usize,
+                Cigar::Del(_) | Cigar::RefSkip(_) | Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+
+        let seq = record.seq();
+        let (mut mismatches, mut nonag) = (0, 0);
+        let (mut ind, mut matched, mut deletion) = (0, 0, false);
+        for &symbol in md {
+            match symbol {
+                b'0'..=b'9' => {
+                    matched = matched * 10 + (symbol - b'0') as usize;
+                    deletion = false;
+                }
+                b'^' => {
+                    ind += matched;
+                    matched = 0;
+                    deletion = true;
+                }
+                // Deleted reference bases
+                _ if deletion => {}
+                refnuc => {
+                    ind += matched;
+                    matched = 0;
+                    let readnuc = match aligned.get(ind) {
+                        Some(x) => seq[*x].to_ascii_uppercase(),
+                        // Malformed MD tag
+                        None => break,
+                    };
+                    mismatches += 1;
+                    if !matches!((refnuc.to_ascii_uppercase(), readnuc), (b'A', b'G') | (b'T', b'C')) {
+                        nonag += 1;
+                    }
+                    ind += 1;
+                }
+            }
+        }
+        (aligned.len() as u32, mismatches, nonag)
+    }
+}
+
+impl<R: AlignedRead> ReadsFilter<R> for ByMismatchLoad {
+    #[inline]
+    fn is_read_ok(&self, record: &R) -> bool {
+        if let Some(maxnm) = self.max_edit_distance {
+            if record.aux_int(b"NM").map_or(false, |nm| nm > maxnm as i64) {
+                return false;
+            }
+        }
+        if let Some(maxrate) = self.max_error_rate {
+            if let Some(nm) = record.aux_int(b"NM") {
+                if nm as f32 > maxrate * ByMismatchLoad::alignment_columns(record) as f32 {
+                    return false;
+                }
+            }
+        }
+        if self.max_mismatches_per_kb.is_none() && self.max_non_ag_mismatches.is_none() {
+            return true;
+        }
+
+        let md = record.aux_str(b"MD");
+        if md.is_empty() {
+            return true;
+        }
+        let (aligned, mismatches, nonag) = ByMismatchLoad::mismatches(record, md);
+        let perkb = self.max_mismatches_per_kb.map_or(true, |x| mismatches as f32 * 1000.0 <= x * aligned as f32);
+        let nonag = self.max_non_ag_mismatches.map_or(true, |x| nonag <= x);
+        perkb && nonag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::Cigar::*;
+    use rust_htslib::bam::record::CigarString;
+
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    fn mock(cigar: Vec<Cigar>, seq: &'static str, md: &'static str, nm: Option<i64>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_cigar().returning(move || CigarString(cigar.clone()).into_view(0));
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read.expect_len().return_const(seq.len());
+        read.expect_aux_str().return_const(md.as_bytes().to_vec());
+        read.expect_aux_int().return_const(nm);
+        read
+    }
+
+    #[test]
+    fn mismatches() {
+        for (cigar, seq, md, expected) in [
+            (vec![Match(10)], "ACGTACGTAC", "10", (10, 0, 0)),
+            // A->G, C->T
+            (vec![Match(10)], "ACGTGCGTAT", "4A4C0", (10, 2, 1)),
+            // Soft clip + insertion + deletion, T->C
+            (vec![SoftClip(2), Match(3), Ins(2), Match(2), Del(2), Match(3)], "NNACGTTCAGCT", "3T1^GG3", (8, 1, 0)),
+            // Spliced read, G->A and A->C
+            (vec![Match(2), RefSkip(100), Match(4)], "AAACCC", "1G2A1", (6, 2, 2)),
+        ] {
+            let read = mock(cigar, seq, md, None);
+            assert_eq!(ByMismatchLoad::mismatches(&read, md.as_bytes()), expected, "{}", md);
+        }
+    }
+
+    #[test]
+    fn is_read_ok() {
+        let read = mock(vec![Match(10)], "ACGTGCGTAT", "4A4C0", Some(2));
+        for (dummy, expected) in [
+            (ByMismatchLoad::default(), true),
+            (ByMismatchLoad::new(Some(2), None, None, None), true),
+            (ByMismatchLoad::new(Some(1), None, None, None), false),
+            (ByMismatchLoad::new(None, Some(200.0), None, None), true),
+            (ByMismatchLoad::new(None, Some(199.0), None, None), false),
+            (ByMismatchLoad::new(None, None, Some(1), None), true),
+            (ByMismatchLoad::new(None, None, Some(0), None), false),
+            (ByMismatchLoad::new(None, None, None, Some(0.2)), true),
+            (ByMismatchLoad::new(None, None, None, Some(0.19)), false),
+            (ByMismatchLoad::new(Some(5), Some(500.0), Some(0), None), false),
+        ] {
+            assert_eq!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read), expected);
+        }
+
+        // No tags -> the read is kept
+        let read = mock(vec![Match(10)], "ACGTGCGTAT", "", None);
+        let dummy = ByMismatchLoad::new(Some(0), Some(0.0), Some(0), Some(0.0));
+        assert!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read));
+
+        // Indels are counted as alignment columns: NM=3 over 15 columns
+        let cigar = vec![SoftClip(2), Match(6), Ins(2), Match(2), Del(3), RefSkip(100), Match(2)];
+        let read = mock(cigar, "NNACGTACGTACGT", "", Some(3));
+        assert_eq!(ByMismatchLoad::alignment_columns(&read), 15);
+        for (maxrate, expected) in [(0.2, true), (0.15, false)] {
+            let dummy = ByMismatchLoad::new(None, None, None, Some(maxrate));
+            assert_eq!(ReadsFilter::<MockRead>::is_read_ok(&dummy, &read), expected);
+        }
+    }
+}
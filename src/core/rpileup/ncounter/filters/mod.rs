@@ -1,13 +1,17 @@
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
+pub use by_expression::ByExpression;
 pub use by_flags::ByFlags;
+pub use by_mismatch_load::ByMismatchLoad;
 pub use by_quality::ByQuality;
 pub use sequential::Sequential;
 
 use crate::core::read::AlignedRead;
 
+mod by_expression;
 mod by_flags;
+mod by_mismatch_load;
 mod by_quality;
 mod sequential;
 
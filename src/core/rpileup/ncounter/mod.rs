@@ -2,7 +2,7 @@ use std::ops::Range;
 
 use bio_types::genome::Position;
 
-pub use crate::core::dna::NucCounts;
+pub use crate::core::dna::{FracNucCounts, IndelCounts, NucCounts, ReadStats};
 
 use crate::core::strandutil::Stranded;
 
@@ -15,6 +15,31 @@ pub struct InnerNucCounts<'a, Data> {
     pub range: Range<Position>,
     pub cnts: Stranded<Option<&'a [NucCounts]>>,
     pub coverage: Stranded<u32>,
+    // Optional coverage with multi-mapping reads counted fractionally, None if not tracked (ROIs only)
+    pub fraccoverage: Stranded<Option<f32>>,
+    // Optional counts weighted by the base-call probability, None if weights are not tracked
+    pub weighted: Stranded<Option<&'a [FracNucCounts]>>,
+    // Optional indels counts, None if indels are not tracked
+    pub indels: Stranded<Option<&'a [IndelCounts]>>,
+    // Optional read strand & position statistics, None if they are not tracked
+    pub readstats: Stranded<Option<&'a [ReadStats]>>,
+    // Optional per-sample counts, empty if samples are not tracked
+    pub samples: Vec<SampleNucCounts<'a>>,
+    // Optional sparse per-cell counts, empty if cells are not tracked
+    pub cells: Stranded<&'a [CellNucCounts]>,
+}
+
+pub struct SampleNucCounts<'a> {
+    pub cnts: Stranded<Option<&'a [NucCounts]>>,
+    pub coverage: Stranded<u32>,
+}
+
+// Non-zero counts of a single cell at the given offset inside the counted range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CellNucCounts {
+    pub cell: u32,
+    pub offset: u32,
+    pub cnts: NucCounts,
 }
 
 pub struct NucCounterResult<'a, Data> {
@@ -0,0 +1,278 @@
+use std::io::Write;
+use std::ops::Range;
+
+use bio_types::genome::{AbstractInterval, Position};
+use bio_types::strand::ReqStrand;
+use csv::Writer;
+use itertools::Itertools;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::core::dna::{Nucleotide, ReqNucleotide};
+use crate::core::read::AlignedRead;
+use crate::core::refpred::{PredNucleotide, RefEngine};
+use crate::core::rpileup::ncounter::cnt::BaseNucCounter;
+use crate::core::rpileup::ncounter::filters::ReadsFilter;
+use crate::core::rpileup::ReadsCollider;
+use crate::core::workload::SiteWorkload;
+
+// Single mismatch between the read and the predicted reference
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReadEdit {
+    pub pos: Position,
+    pub refnuc: Nucleotide,
+    pub nuc: ReqNucleotide,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReadEdits {
+    pub name: Vec<u8>,
+    pub strand: ReqStrand,
+    pub edits: Vec<ReadEdit>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReadEditsBatch {
+    pub contig: String,
+    pub reads: Vec<ReadEdits>,
+}
+
+impl ReadEditsBatch {
+    pub fn to_csv<W: Write>(&self, writer: &mut Writer<W>) -> csv::Result<()> {
+        for read in &self.reads {
+            writer.serialize(SerializeReadEdits { contig: &self.contig, read })?;
+        }
+        Ok(())
+    }
+}
+
+struct SerializeReadEdits<'a> {
+    contig: &'a str,
+    read: &'a ReadEdits,
+}
+
+impl Serialize for SerializeReadEdits<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ReadEdits", 5)?;
+        state.serialize_field("name", &String::from_utf8_lossy(&self.read.name))?;
+        state.serialize_field("contig", self.contig)?;
+        state.serialize_field("strand", self.read.strand.strand_symbol())?;
+        state.serialize_field("positions", &self.read.edits.iter().map(|x| x.pos).join(","))?;
+        state.serialize_field(
+            "types",
+            &self.read.edits.iter().map(|x| format!("{}>{}", x.refnuc, Nucleotide::from(x.nuc))).join(","),
+        )?;
+        state.end()
+    }
+}
+
+// Mismatches against the predicted reference for each read with at least one counted base in the workload.
+// Reads spanning several workloads are reported once per workload.
+#[derive(Clone)]
+pub struct ReadEditsCollider<R: AlignedRead, Filter: ReadsFilter<R>> {
+    base: BaseNucCounter<R, Filter>,
+    refnucpred: Box<dyn RefEngine>,
+    ranges: Vec<Range<u64>>,
+    // Counted reads: name, strand, and the range of their bases in the cache
+    reads: Vec<(Vec<u8>, ReqStrand, Range<usize>)>,
+    bases: Vec<(u32, ReqNucleotide)>,
+    batch: ReadEditsBatch,
+}
+
+impl<R: AlignedRead, Filter: ReadsFilter<R>> ReadEditsCollider<R, Filter> {
+    pub fn new(base: BaseNucCounter<R, Filter>, refnucpred: Box<dyn RefEngine>) -> Self {
+        Self {
+            base: base.with_read_bases(true),
+            refnucpred,
+            ranges: vec![],
+            reads: vec![],
+            bases: vec![],
+            batch: Default::default(),
+        }
+    }
+}
+
+impl<'a, R: AlignedRead, Filter: ReadsFilter<R>> ReadsCollider<'a, R> for ReadEditsCollider<R, Filter> {
+    type ColliderResult = &'a ReadEditsBatch;
+    type Workload = SiteWorkload;
+
+    fn reset(&mut self, info: Self::Workload) {
+        let (interval, ranges) = info.dissolve();
+        self.base.reset(interval);
+        self.ranges = ranges;
+        self.reads.clear();
+        self.bases.clear();
+        self.batch.reads.clear();
+    }
+
+    fn source(&mut self, index: usize) {
+        self.base.source(index);
+    }
+
+    fn collide(&mut self, read: &R) {
+        self.base.count(read);
+
+        // Bases outside of the workload ranges are ignored
+        let start = self.base.interval().range().start;
+        let first = self.bases.len();
+        for &(pos, nuc) in self.base.read_bases() {
+            let pos = start + pos as u64;
+            if self.ranges.iter().any(|x| x.contains(&pos)) {
+                self.bases.push(((pos - start) as u32, nuc));
+            }
+        }
+        if self.bases.len() > first {
+            self.reads.push((read.name().to_vec(), *read.strand(), first..self.bases.len()));
+        }
+    }
+
+    fn finalize(&mut self) {
+        let interval = self.base.interval();
+        self.refnucpred.run(interval.contig(), interval.range(), self.base.counted());
+        let predicted = self.refnucpred.results().predicted;
+
+        let start = interval.range().start;
+        self.batch.contig = interval.contig().to_owned();
+        for (name, strand, range) in self.reads.drain(..) {
+            // Unknown and heterozygous positions are skipped
+            let edits = self.bases[range]
+                .iter()
+                .filter_map(|&(pos, nuc)| match predicted[pos as usize] {
+                    PredNucleotide::Homozygous(refnuc)
+                        if refnuc != Nucleotide::Unknown && refnuc != Nucleotide::from(nuc) =>
+                    {
+                        Some(ReadEdit { pos: start + pos as u64, refnuc, nuc })
+                    }
+                    _ => None,
+                })
+                .collect();
+            self.batch.reads.push(ReadEdits { name, strand, edits });
+        }
+    }
+
+    fn result(&'a self) -> Self::ColliderResult {
+        &self.batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bio_types::genome::Interval;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+    use serde_test::{assert_ser_tokens, Token};
+
+    use crate::core::read::MockRead;
+    use crate::core::refpred::RefEngineResult;
+    use crate::core::rpileup::ncounter::filters::MockReadsFilter;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct DummyRef(Vec<PredNucleotide>, Vec<Nucleotide>);
+
+    impl RefEngine for DummyRef {
+        fn run(&mut self, _: &str, range: Range<Position>, sequenced: &[crate::core::dna::NucCounts]) {
+            assert_eq!(range.end - range.start, sequenced.len() as u64);
+        }
+
+        fn results(&self) -> RefEngineResult<'_> {
+            RefEngineResult { predicted: &self.0, reference: &self.1, quality: None }
+        }
+    }
+
+    fn mock(name: &'static str, pos: i64, seq: &'static str, strand: ReqStrand, cigar: Vec<Cigar>) -> MockRead {
+        let mut read = MockRead::new();
+        read.expect_name().return_const(name.as_bytes().to_vec());
+        read.expect_contig().return_const("1".to_owned());
+        read.expect_pos().return_const(pos);
+        read.expect_len().return_const(seq.len());
+        read.expect_cigar().return_once(move || CigarString(cigar).into_view(pos));
+        read.expect_strand().return_const(strand);
+        read.expect_seq().returning(move || seq.as_bytes().to_vec());
+        read
+    }
+
+    #[test]
+    fn collide() {
+        use Nucleotide::*;
+        let predicted = [A, C, G, Unknown, T, A, C, G, T, A];
+        let mut predicted: Vec<_> = predicted.into_iter().map(PredNucleotide::Homozygous).collect();
+        predicted[5] = PredNucleotide::Heterozygous((A, G));
+
+        let mut filter = MockReadsFilter::new();
+        filter.expect_is_read_ok().return_const(true);
+        filter.expect_is_base_ok().return_const(true);
+        let base = BaseNucCounter::new(10, filter, 0, 0);
+        let mut dummy = ReadEditsCollider::new(base, Box::new(DummyRef(predicted, vec![])));
+
+        // Workload 100-110 without the position 104
+        let intervals = vec![Interval::new("1".into(), 100..104), Interval::new("1".into(), 105..110)];
+        let mut workload = SiteWorkload::from_intervals(intervals, 10, None::<Vec<Interval>>);
+        assert_eq!(workload.len(), 1);
+        dummy.reset(workload.pop().unwrap());
+
+        for read in [
+            // Unknown(103), excluded(104) and heterozygous(105) positions are skipped
+            mock("read1", 100, "ATAAGG", ReqStrand::Forward, vec![Cigar::Match(6)]),
+            // Bases outside of the workload are ignored
+            mock("read2", 106, "GAAA", ReqStrand::Reverse, vec![Cigar::Match(1), Cigar::Del(1), Cigar::Match(3)]),
+            // No mismatches
+            mock("read3", 109, "A", ReqStrand::Forward, vec![Cigar::Match(1)]),
+            // No counted bases -> not reported
+            mock("read4", 90, "AC", ReqStrand::Forward, vec![Cigar::Match(2)]),
+            mock("read5", 104, "G", ReqStrand::Forward, vec![Cigar::Match(1)]),
+        ] {
+            dummy.collide(&read);
+        }
+        dummy.finalize();
+
+        let edit = |pos, refnuc, nuc| ReadEdit { pos, refnuc, nuc };
+        let result = dummy.result();
+        assert_eq!(result.contig, "1");
+        assert_eq!(
+            result.reads,
+            [
+                ReadEdits {
+                    name: b"read1".to_vec(),
+                    strand: ReqStrand::Forward,
+                    edits: vec![edit(101, C, ReqNucleotide::T), edit(102, G, ReqNucleotide::A)],
+                },
+                ReadEdits {
+                    name: b"read2".to_vec(),
+                    strand: ReqStrand::Reverse,
+                    edits: vec![edit(106, C, ReqNucleotide::G), edit(108, T, ReqNucleotide::A)],
+                },
+                ReadEdits { name: b"read3".to_vec(), strand: ReqStrand::Forward, edits: vec![] },
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize() {
+        let read = ReadEdits {
+            name: b"read".to_vec(),
+            strand: ReqStrand::Reverse,
+            edits: vec![
+                ReadEdit { pos: 10, refnuc: Nucleotide::A, nuc: ReqNucleotide::G },
+                ReadEdit { pos: 25, refnuc: Nucleotide::T, nuc: ReqNucleotide::C },
+            ],
+        };
+        assert_ser_tokens(
+            &SerializeReadEdits { contig: "chr1", read: &read },
+            &[
+                Token::Struct { name: "ReadEdits", len: 5 },
+                Token::Str("name"),
+                Token::Str("read"),
+                Token::Str("contig"),
+                Token::Str("chr1"),
+                Token::Str("strand"),
+                Token::Str("-"),
+                Token::Str("positions"),
+                Token::Str("10,25"),
+                Token::Str("types"),
+                Token::Str("A>G,T>C"),
+                Token::StructEnd,
+            ],
+        );
+    }
+}
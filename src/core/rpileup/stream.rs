@@ -0,0 +1,203 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::path::Path;
+
+use bio_types::genome::{AbstractInterval, Interval, Position};
+use rust_htslib::bam::{Read, Reader, Record};
+
+// Coordinate-sorted reads from a non-indexed source (i.e. stdin) that can be read only once.
+// Workloads must be requested in the stream order: contigs as in the header, then by the start position.
+pub struct HTSStream {
+    records: Box<dyn Iterator<Item = Record> + Send>,
+    contigs: Vec<Interval>,
+    tids: HashMap<String, u32>,
+    // Reads that might overlap the current or the next workloads: tid, end position, read
+    buffer: VecDeque<(u32, i64, Record)>,
+    // Last requested workload and the last read from the stream
+    workload: Option<(u32, i64)>,
+    last: Option<(u32, i64)>,
+    exhausted: bool,
+}
+
+impl HTSStream {
+    pub fn new(contigs: Vec<Interval>, records: Box<dyn Iterator<Item = Record> + Send>) -> Self {
+        let tids = contigs.iter().enumerate().map(|(ind, x)| (x.contig().to_owned(), ind as u32)).collect();
+        Self { records, contigs, tids, buffer: VecDeque::new(), workload: None, last: None, exhausted: false }
+    }
+
+    // The reference is required to decode CRAM streams
+    pub fn stdin(reference: &Path) -> Self {
+        let mut reader = Reader::from_stdin().expect("Failed to parse the BAM/CRAM header from stdin");
+        reader
+            .set_reference(reference)
+            .unwrap_or_else(|_| panic!("Failed to set reference {} for the stdin", reference.display()));
+
+        let header = reader.header();
+        let contigs = (0..header.target_count())
+            .map(|tid| {
+                let name = String::from_utf8_lossy(header.tid2name(tid)).to_string();
+                let length = header.target_len(tid).expect("Failed to parse the stdin header");
+                Interval::new(name, 0..length)
+            })
+            .collect();
+
+        let records = std::iter::from_fn(move || {
+            let mut record = Record::new();
+            match reader.read(&mut record) {
+                None => None,
+                Some(Ok(())) => Some(record),
+                Some(Err(_)) => panic!("Failed to read reads from the stdin (HTS stream corrupted?)"),
+            }
+        });
+        Self::new(contigs, Box::new(records))
+    }
+
+    // Contigs in the header order
+    pub fn contigs(&self) -> &[Interval] {
+        &self.contigs
+    }
+
+    // Sort the workload in the stream order. Contigs missing in the header go first, they have no reads anyway.
+    pub fn sort<W: AbstractInterval>(&self, workload: &mut [W]) {
+        workload.sort_by_key(|x| (self.tids.get(x.contig()).copied(), x.range().start));
+    }
+
+    // Advance the stream to the given workload and return reads overlapping it in the stream order
+    pub fn fetch(&mut self, contig: &str, range: Range<Position>) -> impl Iterator<Item = &Record> + '_ {
+        let (start, end) = (range.start as i64, range.end as i64);
+        let tid = match self.tids.get(contig) {
+            Some(&x) => x,
+            // No such contig in the stream
+            None => return self.overlapping(u32::MAX, start, end),
+        };
+
+        assert!(
+            self.workload.map_or(true, |x| x <= (tid, start)),
+            "Workloads must be processed in the order of reads in the stream, got {}:{}-{} out of order",
+            contig,
+            start,
+            end
+        );
+        self.workload = Some((tid, start));
+
+        // Reads ending before the workload won't be required anymore
+        self.buffer.retain(|(rtid, rend, _)| (*rtid, *rend) > (tid, start));
+
+        // Read the stream until the first read starting after the workload
+        while !self.exhausted && !matches!(self.buffer.back(), Some((rtid, _, x)) if (*rtid, x.pos()) >= (tid, end)) {
+            let record = match self.records.next() {
+                Some(x) => x,
+                None => {
+                    self.exhausted = true;
+                    break;
+                }
+            };
+            // Unmapped reads
+            if record.tid() < 0 {
+                continue;
+            }
+
+            let (rtid, rpos) = (record.tid() as u32, record.pos());
+            assert!(
+                self.last.map_or(true, |x| x <= (rtid, rpos)),
+                "Input stream must be sorted by coordinate (samtools sort)"
+            );
+            self.last = Some((rtid, rpos));
+
+            let rend = record.cigar().end_pos();
+            if (rtid, rend) > (tid, start) {
+                self.buffer.push_back((rtid, rend, record));
+            }
+        }
+        self.overlapping(tid, start, end)
+    }
+
+    fn overlapping(&self, tid: u32, start: i64, end: i64) -> impl Iterator<Item = &Record> + '_ {
+        self.buffer.iter().filter(move |(rtid, rend, x)| *rtid == tid && x.pos() < end && *rend > start).map(|x| &x.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    use super::*;
+
+    fn record(name: &str, tid: i32, pos: i64, len: u32) -> Record {
+        let mut record = Record::new();
+        let seq = vec![b'A'; len as usize];
+        let qual = vec![30; len as usize];
+        record.set(name.as_bytes(), Some(&CigarString(vec![Cigar::Match(len)])), &seq, &qual);
+        record.set_tid(tid);
+        record.set_pos(pos);
+        record
+    }
+
+    fn names<'a>(reads: impl Iterator<Item = &'a Record>) -> Vec<String> {
+        reads.map(|x| String::from_utf8_lossy(x.qname()).to_string()).collect()
+    }
+
+    fn dummy(reads: Vec<Record>) -> HTSStream {
+        let contigs = vec![Interval::new("1".into(), 0..1000), Interval::new("2".into(), 0..1000)];
+        HTSStream::new(contigs, Box::new(reads.into_iter()))
+    }
+
+    #[test]
+    fn sort() {
+        let dummy = dummy(vec![]);
+        let mut workload = vec![
+            Interval::new("2".into(), 0..10),
+            Interval::new("1".into(), 10..20),
+            Interval::new("3".into(), 0..10),
+            Interval::new("1".into(), 0..10),
+        ];
+        dummy.sort(&mut workload);
+        assert_eq!(
+            workload,
+            [
+                Interval::new("3".into(), 0..10),
+                Interval::new("1".into(), 0..10),
+                Interval::new("1".into(), 10..20),
+                Interval::new("2".into(), 0..10),
+            ]
+        );
+    }
+
+    #[test]
+    fn fetch() {
+        let mut dummy = dummy(vec![
+            record("a", 0, 0, 10),
+            record("b", 0, 5, 30),
+            record("c", 0, 12, 5),
+            record("d", 0, 40, 10),
+            record("e", 1, 0, 10),
+            record("f", 1, 100, 10),
+            record("unmapped", -1, -1, 10),
+        ]);
+
+        assert_eq!(names(dummy.fetch("1", 0..10)), ["a", "b"]);
+        // Reads spanning several workloads are reported for each of them
+        assert_eq!(names(dummy.fetch("1", 10..20)), ["b", "c"]);
+        assert_eq!(names(dummy.fetch("1", 20..30)), ["b"]);
+        assert!(names(dummy.fetch("3", 0..100)).is_empty());
+        // Reads between workloads are skipped
+        assert_eq!(names(dummy.fetch("2", 5..20)), ["e"]);
+        assert_eq!(names(dummy.fetch("2", 100..200)), ["f"]);
+        assert!(names(dummy.fetch("2", 500..600)).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_order() {
+        let mut dummy = dummy(vec![record("a", 0, 0, 10)]);
+        dummy.fetch("2", 0..10).for_each(drop);
+        dummy.fetch("1", 0..10).for_each(drop);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unsorted() {
+        let mut dummy = dummy(vec![record("a", 0, 50, 10), record("b", 0, 10, 10)]);
+        dummy.fetch("1", 0..100).for_each(drop);
+    }
+}
@@ -0,0 +1,60 @@
+use bio_types::strand::{ReqStrand, Strand};
+
+use crate::core::read::AlignedRead;
+
+use super::StrandDeducer;
+
+// Transcription strand reported by the aligner for spliced reads:
+// * XS:A:+/- (STAR, HISAT2, TopHat) - strand relative to the reference
+// * ts:A:+/- (minimap2) - strand relative to the read
+// Reads without these tags (typically unspliced) have unknown strand.
+#[derive(Copy, Clone, Default)]
+pub struct DeduceStrandByTags {}
+
+impl StrandDeducer for DeduceStrandByTags {
+    fn deduce<R: AlignedRead>(&self, record: &R) -> Strand {
+        match record.aux_char(b"XS") {
+            Some(b'+') => return Strand::Forward,
+            Some(b'-') => return Strand::Reverse,
+            _ => {}
+        }
+        match (record.aux_char(b"ts"), record.strand()) {
+            (Some(b'+'), strand) => (*strand).into(),
+            (Some(b'-'), ReqStrand::Forward) => Strand::Reverse,
+            (Some(b'-'), ReqStrand::Reverse) => Strand::Forward,
+            _ => Strand::Unknown,
+        }
+    }
+
+    fn has_unknown(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    #[test]
+    fn deduce() {
+        let dummy = DeduceStrandByTags::default();
+        let mut read = MockRead::new();
+        for (xs, ts, strand, expected) in [
+            (Some(b'+'), None, ReqStrand::Reverse, Strand::Forward),
+            (Some(b'-'), Some(b'+'), ReqStrand::Forward, Strand::Reverse),
+            (None, Some(b'+'), ReqStrand::Forward, Strand::Forward),
+            (None, Some(b'+'), ReqStrand::Reverse, Strand::Reverse),
+            (None, Some(b'-'), ReqStrand::Forward, Strand::Reverse),
+            (None, Some(b'-'), ReqStrand::Reverse, Strand::Forward),
+            (None, None, ReqStrand::Forward, Strand::Unknown),
+            (Some(b'?'), None, ReqStrand::Reverse, Strand::Unknown),
+        ] {
+            read.expect_aux_char().returning(move |tag| if tag == b"XS" { xs } else { ts });
+            read.expect_strand().return_const(strand);
+            assert!(dummy.deduce(&read).same(&expected));
+            read.checkpoint();
+        }
+    }
+}
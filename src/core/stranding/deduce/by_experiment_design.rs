@@ -1,6 +1,6 @@
 use std::fmt::{Display, Formatter};
 
-use bio_types::strand::ReqStrand;
+use bio_types::strand::{ReqStrand, Strand};
 use derive_more::Constructor;
 
 use crate::core::read::AlignedRead;
@@ -50,10 +50,10 @@ impl DeduceStrandByDesign {
     }
 }
 
-impl<R: AlignedRead> StrandDeducer<R> for DeduceStrandByDesign {
-    fn deduce(&self, record: &R) -> ReqStrand {
+impl StrandDeducer for DeduceStrandByDesign {
+    fn deduce<R: AlignedRead>(&self, record: &R) -> Strand {
         let strand = *record.strand();
-        match self.design {
+        let strand = match self.design {
             StrandSpecificExperimentDesign::Same => strand,
             StrandSpecificExperimentDesign::Flip => DeduceStrandByDesign::flip(&strand),
             StrandSpecificExperimentDesign::Same1Flip2 => {
@@ -70,7 +70,12 @@ impl<R: AlignedRead> StrandDeducer<R> for DeduceStrandByDesign {
                     strand
                 }
             }
-        }
+        };
+        strand.into()
+    }
+
+    fn has_unknown(&self) -> bool {
+        false
     }
 }
 
@@ -111,7 +116,7 @@ mod tests {
         ] {
             let dummy = DeduceStrandByDesign::new(design);
             read.expect_strand().return_const(strand);
-            assert_eq!(dummy.deduce(&read), expected);
+            assert!(dummy.deduce(&read).same(&expected.into()));
             read.checkpoint();
         }
     }
@@ -132,7 +137,7 @@ mod tests {
             let dummy = DeduceStrandByDesign::new(design);
             read.expect_strand().return_const(strand);
             read.expect_is_first().return_const(is_first);
-            assert_eq!(dummy.deduce(&read), expected);
+            assert!(dummy.deduce(&read).same(&expected.into()));
             read.checkpoint();
         }
     }
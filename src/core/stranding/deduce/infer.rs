@@ -0,0 +1,211 @@
+use std::fmt::{Display, Formatter};
+
+use bio_types::strand::ReqStrand;
+use rust_htslib::htslib::{BAM_FPAIRED, BAM_FUNMAP};
+
+use crate::core::read::AlignedRead;
+use crate::core::rpileup::ncounter::filters::ReadsFilter;
+use crate::core::stranding::predict::algo::StrandByGenomicAnnotation;
+
+use super::StrandSpecificExperimentDesign;
+
+// Strand-specificity of the library inferred from reads overlapping annotated genes,
+// the same way as in the RSeQC infer_experiment.py
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExperimentDesignTally {
+    // Reads overlapping genes on both strands
+    pub failed: u32,
+    // Single-end reads: ++,-- and +-,-+
+    pub same: u32,
+    pub flip: u32,
+    // Paired-end reads: 1++,1--,2+-,2-+ and 1+-,1-+,2++,2--
+    pub same1flip2: u32,
+    pub flip1same2: u32,
+}
+
+impl ExperimentDesignTally {
+    // Count the read given the number of overlapping genes on the forward and reverse strands
+    pub fn add<R: AlignedRead>(&mut self, read: &R, forward: u32, reverse: u32) {
+        let same = match (forward > 0, reverse > 0) {
+            (false, false) => return,
+            (true, true) => {
+                self.failed += 1;
+                return;
+            }
+            (true, false) => *read.strand() == ReqStrand::Forward,
+            (false, true) => *read.strand() == ReqStrand::Reverse,
+        };
+
+        if read.flags() & BAM_FPAIRED as u16 != 0 {
+            if read.is_first() == same {
+                self.same1flip2 += 1;
+            } else {
+                self.flip1same2 += 1;
+            }
+        } else if same {
+            self.same += 1;
+        } else {
+            self.flip += 1;
+        }
+    }
+
+    // Count up to `maxreads` informative reads, i.e. mapped reads that passed the filter and overlap genes
+    pub fn sample<R: AlignedRead>(
+        &mut self,
+        reads: impl Iterator<Item = R>,
+        filter: &impl ReadsFilter<R>,
+        annotation: &StrandByGenomicAnnotation,
+        maxreads: u32,
+    ) {
+        for read in reads {
+            if self.total() >= maxreads {
+                break;
+            }
+            if read.flags() & BAM_FUNMAP as u16 != 0 || !filter.is_read_ok(&read) {
+                continue;
+            }
+            let (start, end) = (read.pos() as u64, read.cigar().end_pos() as u64);
+            let (forward, reverse) = annotation.genes_in(read.contig(), start..end);
+            self.add(&read, forward, reverse);
+        }
+    }
+
+    #[inline]
+    pub fn total(&self) -> u32 {
+        self.failed + self.same + self.flip + self.same1flip2 + self.flip1same2
+    }
+
+    #[inline]
+    pub fn is_paired(&self) -> bool {
+        self.same1flip2 + self.flip1same2 > self.same + self.flip
+    }
+
+    // Fractions of reads explained by the two possible designs + fraction of undetermined reads
+    pub fn fractions(&self) -> (f32, f32, f32) {
+        let total = self.total().max(1) as f32;
+        let (first, second) =
+            if self.is_paired() { (self.same1flip2, self.flip1same2) } else { (self.same, self.flip) };
+        (first as f32 / total, second as f32 / total, self.failed as f32 / total)
+    }
+
+    // Design that explains at least `minfrac` of reads, None for unstranded libraries
+    pub fn design(&self, minfrac: f32) -> Option<StrandSpecificExperimentDesign> {
+        let (first, second, _) = self.fractions();
+        let designs = if self.is_paired() {
+            (StrandSpecificExperimentDesign::Same1Flip2, StrandSpecificExperimentDesign::Flip1Same2)
+        } else {
+            (StrandSpecificExperimentDesign::Same, StrandSpecificExperimentDesign::Flip)
+        };
+        if first >= minfrac {
+            Some(designs.0)
+        } else if second >= minfrac {
+            Some(designs.1)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for ExperimentDesignTally {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (first, second, failed) = self.fractions();
+        let (library, designs) = if self.is_paired() {
+            ("PairEnd", ["1++,1--,2+-,2-+", "1+-,1-+,2++,2--"])
+        } else {
+            ("SingleEnd", ["++,--", "+-,-+"])
+        };
+        write!(
+            f,
+            "{} data, {} informative reads; fraction of reads failed to determine: {:.4}; \
+            explained by \"{}\": {:.4}; explained by \"{}\": {:.4}",
+            library,
+            self.total(),
+            failed,
+            designs[0],
+            first,
+            designs[1],
+            second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::read::MockRead;
+
+    use super::*;
+
+    fn add(tally: &mut ExperimentDesignTally, paired: bool, first: bool, strand: ReqStrand, genes: (u32, u32)) {
+        let mut read = MockRead::new();
+        read.expect_flags().return_const(if paired { BAM_FPAIRED as u16 } else { 0u16 });
+        read.expect_is_first().return_const(first);
+        read.expect_strand().return_const(strand);
+        tally.add(&read, genes.0, genes.1);
+    }
+
+    #[test]
+    fn add_single_end() {
+        let mut dummy = ExperimentDesignTally::default();
+        for (strand, genes) in [
+            (ReqStrand::Forward, (1, 0)),
+            (ReqStrand::Reverse, (0, 2)),
+            (ReqStrand::Forward, (0, 1)),
+            (ReqStrand::Forward, (1, 1)),
+            (ReqStrand::Reverse, (0, 0)),
+        ] {
+            add(&mut dummy, false, true, strand, genes);
+        }
+        assert_eq!(dummy, ExperimentDesignTally { failed: 1, same: 2, flip: 1, same1flip2: 0, flip1same2: 0 });
+        assert!(!dummy.is_paired());
+    }
+
+    #[test]
+    fn add_paired_end() {
+        let mut dummy = ExperimentDesignTally::default();
+        for (first, strand, genes) in [
+            (true, ReqStrand::Forward, (1, 0)),
+            (false, ReqStrand::Reverse, (1, 0)),
+            (false, ReqStrand::Forward, (1, 0)),
+            (true, ReqStrand::Forward, (0, 3)),
+            (true, ReqStrand::Reverse, (0, 3)),
+        ] {
+            add(&mut dummy, true, first, strand, genes);
+        }
+        assert_eq!(dummy, ExperimentDesignTally { failed: 0, same: 0, flip: 0, same1flip2: 3, flip1same2: 2 });
+        assert!(dummy.is_paired());
+    }
+
+    #[test]
+    fn design() {
+        use StrandSpecificExperimentDesign::*;
+        for (tally, expected) in [
+            (ExperimentDesignTally { failed: 5, same: 90, flip: 5, ..Default::default() }, Some(Same)),
+            (ExperimentDesignTally { failed: 0, same: 2, flip: 98, ..Default::default() }, Some(Flip)),
+            (
+                ExperimentDesignTally { failed: 2, same1flip2: 97, flip1same2: 1, ..Default::default() },
+                Some(Same1Flip2),
+            ),
+            (
+                ExperimentDesignTally { failed: 10, same1flip2: 5, flip1same2: 85, ..Default::default() },
+                Some(Flip1Same2),
+            ),
+            (ExperimentDesignTally { failed: 10, same: 45, flip: 45, ..Default::default() }, None),
+            (ExperimentDesignTally { failed: 50, same: 40, flip: 10, ..Default::default() }, None),
+            (ExperimentDesignTally::default(), None),
+        ] {
+            assert!(tally.design(0.8) == expected);
+        }
+    }
+
+    #[test]
+    fn fractions_and_format() {
+        let dummy = ExperimentDesignTally { failed: 1, same1flip2: 1, flip1same2: 2, ..Default::default() };
+        assert_eq!(dummy.fractions(), (0.25, 0.5, 0.25));
+        assert_eq!(
+            format!("{}", dummy),
+            "PairEnd data, 4 informative reads; fraction of reads failed to determine: 0.2500; \
+            explained by \"1++,1--,2+-,2-+\": 0.2500; explained by \"1+-,1-+,2++,2--\": 0.5000"
+        );
+        assert_eq!(ExperimentDesignTally::default().fractions(), (0.0, 0.0, 0.0));
+    }
+}
@@ -1,11 +1,18 @@
-use bio_types::strand::ReqStrand;
+use bio_types::strand::Strand;
 
+pub use by_aligner_tags::DeduceStrandByTags;
 pub use by_experiment_design::{DeduceStrandByDesign, StrandSpecificExperimentDesign};
+pub use infer::ExperimentDesignTally;
 
 use crate::core::read::AlignedRead;
 
+mod by_aligner_tags;
 mod by_experiment_design;
+mod infer;
 
-pub trait StrandDeducer<R: AlignedRead> {
-    fn deduce(&self, record: &R) -> ReqStrand;
+pub trait StrandDeducer {
+    // Transcription strand of the read, unknown if it can't be deduced
+    fn deduce<R: AlignedRead>(&self, record: &R) -> Strand;
+    // Whether the strand of some reads might be unknown
+    fn has_unknown(&self) -> bool;
 }
@@ -102,6 +102,13 @@ impl StrandByGenomicAnnotation {
         (forward, reverse)
     }
 
+    // Number of genes on the forward and reverse strands overlapping the given range
+    pub fn genes_in(&self, contig: &str, range: Range<Position>) -> (u32, u32) {
+        let (start, end) = (range.start, range.end);
+        let dummy = Contig::new(contig.into(), start as isize, (end - start) as usize, Strand::Unknown);
+        self.strand_in_index(&dummy, &self.genes)
+    }
+
     fn predict(&self, contig: &str, range: Range<Position>) -> Strand {
         let (start, end) = (range.start, range.end);
         let dummy = Contig::new(contig.into(), start as isize, (end - start) as usize, Strand::Unknown);
@@ -280,6 +287,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn genes_in() {
+        let gff = "\n\
+        chr1\t.\tgene\t2\t12\t.\t+\t0\n\
+        chr1\t.\texon\t4\t6\t.\t+\t0\n\
+        chr1\t.\tgene\t10\t20\t.\t-\t0";
+        let dummy = StrandByGenomicAnnotation::parse_gff(BufReader::new(gff.as_bytes()), 0, |_| {});
+        for (contig, query, expected) in [
+            ("chr1", 0..1, (0, 0)),
+            ("chr1", 3..5, (1, 0)),
+            ("chr1", 8..11, (1, 1)),
+            ("chr1", 15..30, (0, 1)),
+            ("2", 3..5, (0, 0)),
+        ] {
+            assert_eq!(dummy.genes_in(contig, query), expected);
+        }
+    }
+
     #[test]
     fn intervals_in() {
         let gff = "\n\
@@ -0,0 +1,109 @@
+use std::ops::Range;
+
+use bio_types::genome::{AbstractInterval, Interval, Position};
+use bio_types::strand::Strand;
+use derive_getters::{Dissolve, Getters};
+use itertools::Itertools;
+
+use crate::core::dna::Nucleotide;
+
+// Reported site to be tested for the linkage with neighbouring sites
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkageSite {
+    pub contig: String,
+    pub pos: Position,
+    pub trstrand: Strand,
+    pub refnuc: Nucleotide,
+}
+
+// Sites in the genomic bin, sorted by position.
+// Pairs are formed between the first `owned` sites and all sites within the window downstream of them.
+#[derive(Clone, Debug, PartialEq, Getters, Dissolve)]
+pub struct LinkageWorkload {
+    interval: Interval,
+    sites: Vec<LinkageSite>,
+    owned: usize,
+}
+
+impl AbstractInterval for LinkageWorkload {
+    fn contig(&self) -> &str {
+        self.interval.contig()
+    }
+
+    fn range(&self) -> Range<Position> {
+        self.interval.range()
+    }
+}
+
+impl LinkageWorkload {
+    pub fn from_sites(mut sites: Vec<LinkageSite>, window: u64, binsize: u64) -> Vec<LinkageWorkload> {
+        assert!(binsize > 0, "Binsize must be > 0");
+        sites.sort_by(|x, y| {
+            x.contig
+                .cmp(&y.contig)
+                .then(x.pos.cmp(&y.pos))
+                .then(x.trstrand.strand_symbol().cmp(y.trstrand.strand_symbol()))
+        });
+
+        let mut result = Vec::new();
+        for (contig, group) in &sites.into_iter().group_by(|x| x.contig.clone()) {
+            let group = group.collect_vec();
+            let mut start = 0;
+            while start < group.len() {
+                let binstart = group[start].pos;
+                let owned = start + group[start..].iter().take_while(|x| x.pos < binstart + binsize).count();
+                let maxpos = group[owned - 1].pos + window;
+                let end = owned + group[owned..].iter().take_while(|x| x.pos <= maxpos).count();
+
+                // Single sites can't form pairs
+                if end - start > 1 {
+                    let interval = Interval::new(contig.clone(), binstart..group[end - 1].pos + 1);
+                    result.push(LinkageWorkload { interval, sites: group[start..end].to_vec(), owned: owned - start });
+                }
+                start = owned;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_sites() {
+        let site = |contig: &str, pos, trstrand| LinkageSite {
+            contig: contig.to_owned(),
+            pos,
+            trstrand,
+            refnuc: Nucleotide::A,
+        };
+        let sites = vec![
+            site("1", 150, Strand::Forward),
+            site("1", 10, Strand::Forward),
+            site("1", 100, Strand::Reverse),
+            site("1", 100, Strand::Forward),
+            site("1", 500, Strand::Forward),
+            site("2", 0, Strand::Unknown),
+        ];
+
+        // Sites are sorted by position, isolated sites are skipped
+        let workload = LinkageWorkload::from_sites(sites.clone(), 100, 50);
+        assert_eq!(
+            workload,
+            vec![
+                LinkageWorkload {
+                    interval: Interval::new("1".into(), 10..101),
+                    sites: vec![sites[1].clone(), sites[3].clone(), sites[2].clone()],
+                    owned: 1,
+                },
+                LinkageWorkload {
+                    interval: Interval::new("1".into(), 100..151),
+                    sites: vec![sites[3].clone(), sites[2].clone(), sites[0].clone()],
+                    owned: 2,
+                },
+            ]
+        );
+    }
+}
@@ -1,6 +1,8 @@
+pub use linkage::{LinkageSite, LinkageWorkload};
 pub use roi::{ROIWorkload, ROI};
 pub use site::SiteWorkload;
 
+mod linkage;
 pub mod roi;
 mod site;
 mod utils;
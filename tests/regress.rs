@@ -1,11 +1,15 @@
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::App;
 use file_diff::diff_files;
 use indicatif::{MultiProgress, ProgressBar};
+use itertools::Itertools;
 use rayon::ThreadPoolBuilder;
-use tempfile::NamedTempFile;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Cigar, CigarString};
+use rust_htslib::bam::Read;
+use tempfile::{NamedTempFile, TempDir};
 
 use reat::cli;
 
@@ -14,6 +18,8 @@ mod paths;
 const TMP_CREATE_ERROR: &str = "Failed to create temporary file";
 const TMP_DELETE_ERROR: &str = "Failed to delete temporary file";
 const THREAD_POOL_ERROR: &str = "Failed to initialize thread pool";
+const CRAM_CREATE_ERROR: &str = "Failed to convert the example BAM to CRAM";
+const LONG_READS_ERROR: &str = "Failed to create synthetic long reads from the example BAM";
 
 #[allow(non_camel_case_types)]
 enum SubCommand {
@@ -42,6 +48,100 @@ fn run(args: &[&str], launch: SubCommand) {
     masterbar.join_and_clear().expect("Failed to join pbars. Leak?");
 }
 
+// Indexed CRAM copy of the example BAM compressed with the test reference
+fn cram(folder: &Path) -> PathBuf {
+    let cram = folder.join("SRX6966474.cram");
+    let mut reader = bam::Reader::from_path(&*paths::bam::EXAMPLE).expect(CRAM_CREATE_ERROR);
+    let header = bam::Header::from_template(reader.header());
+
+    let mut writer = bam::Writer::from_path(&cram, &header, bam::Format::Cram).expect(CRAM_CREATE_ERROR);
+    writer.set_reference(&*paths::GRCh38::FASTA).expect(CRAM_CREATE_ERROR);
+    for record in reader.records() {
+        writer.write(&record.expect(CRAM_CREATE_ERROR)).expect(CRAM_CREATE_ERROR);
+    }
+    // Flush & close the file before indexing
+    drop(writer);
+
+    bam::index::build(&cram, None, bam::index::Type::Bai, 1).expect(CRAM_CREATE_ERROR);
+    cram
+}
+
+// Indexed BAM with synthetic long reads: non-overlapping example reads are chained into spliced alignments.
+// Returns the BAM along with a BED file covering all reads on each contig.
+fn longreads(folder: &Path) -> (PathBuf, PathBuf) {
+    let (saveto, regions) = (folder.join("long-reads.bam"), folder.join("long-reads.bed"));
+    let mut reader = bam::Reader::from_path(&*paths::bam::EXAMPLE).expect(LONG_READS_ERROR);
+    let header = bam::Header::from_template(reader.header());
+    let contigs: Vec<String> =
+        reader.header().target_names().iter().map(|x| String::from_utf8_lossy(x).to_string()).collect();
+
+    // Chains of reads: (contig, end, reads)
+    let (mut open, mut chains): (Vec<(i32, i64, Vec<bam::Record>)>, Vec<Vec<bam::Record>>) = (vec![], vec![]);
+    for record in reader.records() {
+        let record = record.expect(LONG_READS_ERROR);
+        let clipped = record.cigar().iter().any(|x| matches!(x, Cigar::SoftClip(_) | Cigar::HardClip(_)));
+        if record.is_unmapped() || record.is_secondary() || record.is_supplementary() || clipped {
+            continue;
+        }
+        let (tid, pos, end) = (record.tid(), record.pos(), record.cigar().end_pos());
+        match open.iter().position(|x| x.0 == tid && x.1 <= pos) {
+            Some(ind) => {
+                open[ind].1 = end;
+                open[ind].2.push(record);
+                if open[ind].2.len() == 25 {
+                    chains.push(open.swap_remove(ind).2);
+                }
+            }
+            None => open.push((tid, end, vec![record])),
+        }
+    }
+    chains.extend(open.into_iter().map(|x| x.2));
+    chains.sort_by_key(|x| (x[0].tid(), x[0].pos()));
+
+    let mut bed = String::new();
+    for (tid, group) in &chains.iter().group_by(|x| x[0].tid()) {
+        let (start, end) = group.fold((i64::MAX, 0), |(start, end), chain| {
+            (start.min(chain[0].pos()), end.max(chain.last().unwrap().cigar().end_pos()))
+        });
+        bed.push_str(&format!("{}\t{}\t{}\n", contigs[tid as usize], start, end));
+    }
+    std::fs::write(&regions, bed).expect(LONG_READS_ERROR);
+
+    let mut writer = bam::Writer::from_path(&saveto, &header, bam::Format::Bam).expect(LONG_READS_ERROR);
+    for chain in chains {
+        let (mut cigar, mut seq, mut qual) = (vec![], vec![], vec![]);
+        for (ind, record) in chain.iter().enumerate() {
+            // Gaps between the chained reads become introns
+            if ind > 0 {
+                let gap = record.pos() - chain[ind - 1].cigar().end_pos();
+                if gap > 0 {
+                    cigar.push(Cigar::RefSkip(gap as u32));
+                }
+            }
+            cigar.extend(record.cigar().iter().copied());
+            seq.extend(record.seq().as_bytes());
+            qual.extend_from_slice(record.qual());
+        }
+
+        let first = &chain[0];
+        let mut record = bam::Record::new();
+        record.set(first.qname(), Some(&CigarString(cigar)), &seq, &qual);
+        record.set_tid(first.tid());
+        record.set_pos(first.pos());
+        record.set_mapq(chain.iter().map(|x| x.mapq()).min().unwrap());
+        record.set_flags(if first.is_reverse() { 16 } else { 0 });
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        record.set_insert_size(0);
+        writer.write(&record).expect(LONG_READS_ERROR);
+    }
+    // Flush & close the file before indexing
+    drop(writer);
+
+    bam::index::build(&saveto, None, bam::index::Type::Bai, 1).expect(LONG_READS_ERROR);
+    (saveto, regions)
+}
+
 fn same(first: &Path, second: &Path) -> bool {
     let mut first = match File::open(first) {
         Ok(f) => f,
@@ -139,6 +239,54 @@ mod site {
         assert!(same(tmp.path(), expected.as_path()));
         tmp.close().expect(TMP_DELETE_ERROR);
     }
+
+    #[test]
+    fn cram() {
+        // CRAM input must produce exactly the same results as the BAM one (see deducted_strand)
+        let expected = paths::expected::LOCI.join("deducted.csv");
+        assert!(expected.is_file());
+
+        let folder = TempDir::new().expect(TMP_CREATE_ERROR);
+        let cram = cram(folder.path());
+
+        let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        #[rustfmt::skip]
+        let args = [
+            "test", "--input", cram.to_str().unwrap(), "-r", &paths::GRCh38::FASTA, "-s", "f/s",
+            "-n", "Test", "--out-min-cov", "20", "-o", tmp.path().to_str().unwrap(),
+        ];
+        run(&args, SubCommand::sites);
+
+        assert!(same(tmp.path(), expected.as_path()));
+        tmp.close().expect(TMP_DELETE_ERROR);
+        folder.close().expect(TMP_DELETE_ERROR);
+    }
+
+    #[test]
+    fn long_reads() {
+        // Long reads spanning many small bins must produce exactly the same results in the long-read mode
+        let folder = TempDir::new().expect(TMP_CREATE_ERROR);
+        let (bam, regions) = longreads(folder.path());
+
+        let default = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        let longreads = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        for (tmp, mode) in [(&default, None), (&longreads, Some("--long-reads"))] {
+            #[rustfmt::skip]
+            let mut args = vec![
+                "test", "--input", bam.to_str().unwrap(), "-r", &paths::GRCh38::FASTA, "-s", "s",
+                "--region", regions.to_str().unwrap(), "--binsize", "1000", "-o", tmp.path().to_str().unwrap(),
+            ];
+            args.extend(mode);
+            run(&args, SubCommand::sites);
+        }
+
+        let sites = std::fs::read_to_string(default.path()).expect("Failed to read the results");
+        assert!(sites.lines().count() > 1);
+        assert!(same(default.path(), longreads.path()));
+        default.close().expect(TMP_DELETE_ERROR);
+        longreads.close().expect(TMP_DELETE_ERROR);
+        folder.close().expect(TMP_DELETE_ERROR);
+    }
 }
 
 mod rois {
@@ -265,4 +413,27 @@ mod rois {
         assert!(same(ei.as_path(), expected.as_path()), "{} vs {}", ei.display(), expected.display());
         fs::remove_file(ei).expect(TMP_DELETE_ERROR);
     }
+
+    #[test]
+    fn cram() {
+        // CRAM input must produce exactly the same results as the BAM one (see deducted_strand)
+        let expected = paths::expected::ROIS.join("deducted.csv");
+        assert!(expected.is_file());
+
+        let folder = TempDir::new().expect(TMP_CREATE_ERROR);
+        let cram = cram(folder.path());
+
+        let tmp = NamedTempFile::new().expect(TMP_CREATE_ERROR);
+        #[rustfmt::skip]
+        let args = [
+            "test", "-i", cram.to_str().unwrap(), "-r", &paths::GRCh38::FASTA, "-s", "f/s", "--out-min-cov", "20",
+            "-n", "Test", "--in-flags", "3", "--rois", &paths::GRCh38::ALU, "--hyperedit",
+            "-o", tmp.path().to_str().unwrap(),
+        ];
+        run(&args, SubCommand::rois);
+
+        assert!(same(tmp.path(), expected.as_path()));
+        tmp.close().expect(TMP_DELETE_ERROR);
+        folder.close().expect(TMP_DELETE_ERROR);
+    }
 }